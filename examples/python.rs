@@ -0,0 +1,25 @@
+use std::process::Command;
+
+use incremental_command::ProcessReader;
+
+const PYTHON: &str = r#"\
+import sys
+import time
+for i in range(5):
+    print("stdout", i, file=sys.stdout)
+    print("stderr", i, file=sys.stderr)
+    time.sleep(1)
+print("done")
+"#;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::new("python3");
+    cmd.args(["-u", "-c", PYTHON]);
+    let reader = ProcessReader::start(cmd)?;
+
+    for line in reader {
+        println!("{line:?}");
+    }
+
+    Ok(())
+}
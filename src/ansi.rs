@@ -0,0 +1,72 @@
+use std::{borrow::Cow, ops::RangeInclusive};
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// Removes ANSI escape sequences, such as colors and cursor movement, from
+/// `bytes`.
+///
+/// This covers CSI sequences like `ESC [ 1 ; 31 m`, OSC sequences like
+/// window titles and hyperlinks, which end with `BEL` or `ESC \`, and other
+/// two-byte escapes. A sequence that is cut off at the end is removed as
+/// well.
+pub(crate) fn strip(bytes: &[u8]) -> Cow<'_, [u8]> {
+    if !bytes.contains(&ESC) {
+        return Cow::Borrowed(bytes);
+    }
+
+    let mut stripped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != ESC {
+            stripped.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        i += 1;
+        match bytes.get(i) {
+            // CSI: parameter and intermediate bytes, then a final byte.
+            Some(b'[') => {
+                i += 1;
+                while i < bytes.len() && (0x20..=0x3f).contains(&bytes[i]) {
+                    i += 1;
+                }
+                skip_final(bytes, &mut i, 0x40..=0x7e);
+            }
+            // OSC: anything up to `BEL` or `ESC \`.
+            Some(b']') => {
+                i += 1;
+                while i < bytes.len() {
+                    if bytes[i] == BEL {
+                        i += 1;
+                        break;
+                    }
+                    if bytes[i] == ESC && bytes.get(i + 1) == Some(&b'\\') {
+                        i += 2;
+                        break;
+                    }
+                    i += 1;
+                }
+            }
+            // Other escapes: intermediate bytes, then a final byte.
+            Some(_) => {
+                while i < bytes.len() && (0x20..=0x2f).contains(&bytes[i]) {
+                    i += 1;
+                }
+                skip_final(bytes, &mut i, 0x30..=0x7e);
+            }
+            None => {}
+        }
+    }
+
+    Cow::Owned(stripped)
+}
+
+/// Skips the final byte of a sequence at `i`, unless it is something else,
+/// such as a control character, that ends the sequence early and is kept.
+fn skip_final(bytes: &[u8], i: &mut usize, range: RangeInclusive<u8>) {
+    if bytes.get(*i).is_some_and(|byte| range.contains(byte)) {
+        *i += 1;
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    thread,
+};
+
+use futures_channel::mpsc::{self, UnboundedReceiver};
+use futures_core::Stream;
+
+use crate::{Out, ProcessHandle, ProcessReader, Stdin};
+
+/// An asynchronous [`ProcessReader`] that implements [`Stream`].
+///
+/// The reader is driven on a dedicated background thread, so this works with
+/// any executor. Created with [`ProcessReader::into_async`].
+///
+/// Dropping the stream stops the background thread once the child writes
+/// its next output or exits, at which point the reader's
+/// [`DropPolicy`](crate::DropPolicy) applies.
+#[derive(Debug)]
+pub struct AsyncProcessReader {
+    handle: ProcessHandle,
+    stdin: Option<Stdin>,
+    receiver: UnboundedReceiver<Result<Out, io::Error>>,
+}
+
+impl ProcessReader {
+    /// Moves the reader onto a background thread and returns a [`Stream`] of
+    /// its output.
+    pub fn into_async(mut self) -> Result<AsyncProcessReader, io::Error> {
+        let id = self.id();
+        let handle = self.handle();
+        let stdin = self.take_stdin();
+        let (sender, receiver) = mpsc::unbounded();
+
+        thread::Builder::new()
+            .name(format!("process-reader-{id}"))
+            .spawn(move || {
+                for out in self {
+                    if sender.unbounded_send(out).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(AsyncProcessReader {
+            handle,
+            stdin,
+            receiver,
+        })
+    }
+}
+
+impl AsyncProcessReader {
+    /// Returns the OS-assigned process identifier of the child.
+    pub fn id(&self) -> u32 {
+        self.handle.id()
+    }
+
+    /// Returns a handle for killing, signalling or waiting for the child.
+    pub fn handle(&self) -> ProcessHandle {
+        self.handle.clone()
+    }
+
+    /// Returns a handle for writing to the child's stdin.
+    ///
+    /// See [`ProcessReader::stdin`]. Writes block the calling thread.
+    pub fn stdin(&mut self) -> Option<&mut Stdin> {
+        self.stdin.as_mut()
+    }
+
+    /// Takes ownership of the child's stdin handle.
+    pub fn take_stdin(&mut self) -> Option<Stdin> {
+        self.stdin.take()
+    }
+}
+
+impl Stream for AsyncProcessReader {
+    type Item = Result<Out, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::{
+        fs::{FileTypeExt, MetadataExt, OpenOptionsExt},
+        io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
+    },
+    time::Duration,
+};
+
+use mio::{
+    unix::{pipe::Receiver, SourceFd},
+    Events, Interest, Poll, Token,
+};
+
+use crate::{
+    builder::grow_read_buf,
+    event::OutputQueue,
+    framing::{Framer, Stream},
+    tee::Tee,
+    Out, ProcessReaderBuilder,
+};
+
+const STDOUT: Token = Token(0);
+const STDERR: Token = Token(1);
+const EXIT: Token = Token(2);
+
+/// How often files are checked for appended output, since they cannot be
+/// polled for it.
+const FILE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The device and inode number that identify a file.
+type FileId = (u64, u64);
+
+/// Where the output of one of the process's streams is read from.
+enum Tap {
+    /// A read end of its own for the pipe that the process writes to.
+    Pipe(Receiver),
+    /// The file that the process writes to, followed from where it ended
+    /// when attaching.
+    File(File),
+}
+
+impl Read for Tap {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Pipe(pipe) => pipe.read(buf),
+            Self::File(file) => file.read(buf),
+        }
+    }
+}
+
+/// Reads the output that a running process, which was not spawned by this
+/// one, writes from the moment of attaching, yielding the same items as a
+/// [`ProcessReader`](crate::ProcessReader).
+///
+/// Created with [`ProcessReader::attach`](crate::ProcessReader::attach) or
+/// [`ProcessReaderBuilder::attach`].
+///
+/// The process's stdout and stderr are opened anew through
+/// `/proc/<pid>/fd`, which requires the same permissions as tracing it. A
+/// stream that goes to a regular file is followed from its current end. One
+/// that goes to a pipe is read through a new read end of that pipe, which
+/// competes with whatever already reads it, so that each write ends up with
+/// only one of them. Streams that go anywhere else, such as a terminal or a
+/// socket, cannot be read. If both go to the same place, their output is
+/// yielded as stdout.
+///
+/// The process's exit status cannot be collected, so once it has exited and
+/// its remaining output has been read, the iterator ends without
+/// [`Out::Done`].
+///
+/// ```no_run
+/// use incremental_command::ProcessReader;
+///
+/// for out in ProcessReader::attach(1234)? {
+///     println!("{:?}", out?);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct AttachedReader {
+    pid: u32,
+    poll: Poll,
+    events: Events,
+    /// Becomes readable once the process has exited.
+    pidfd: OwnedFd,
+    stdout: Option<Tap>,
+    stderr: Option<Tap>,
+    stdout_framer: Framer,
+    stderr_framer: Framer,
+    output_buf: OutputQueue,
+    read_buf: Vec<u8>,
+    done: bool,
+}
+
+impl AttachedReader {
+    pub(crate) fn new(builder: ProcessReaderBuilder, pid: u32) -> Result<Self, io::Error> {
+        let ProcessReaderBuilder {
+            buffer_size,
+            mode,
+            stdout: stdout_options,
+            stderr: stderr_options,
+            max_line_length,
+            decoder,
+            line_pool,
+            tee_stdout,
+            tee_stderr,
+            tee_rotation,
+            tee_only,
+            wall_clock,
+            ..
+        } = builder;
+
+        // The pidfd refers to the process itself, but it does not keep its
+        // ID from being reused, so the streams opened below are only known
+        // to belong to it if it is still running afterwards.
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if pidfd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd as _) };
+
+        let stdout = open_tap(pid, libc::STDOUT_FILENO)?;
+        let stderr = open_tap(pid, libc::STDERR_FILENO)?;
+        let (mut stdout, mut stderr) = match (stdout, stderr) {
+            (Some((stdout, out)), Some((_, err))) if out == err => (Some(stdout), None),
+            (stdout, stderr) => (stdout.map(|(tap, _)| tap), stderr.map(|(tap, _)| tap)),
+        };
+        if exited(&pidfd)? {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the process exited while attaching to it",
+            ));
+        }
+        if stdout.is_none() && stderr.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "neither stdout nor stderr of the process is a pipe or a regular file",
+            ));
+        }
+
+        let poll = Poll::new()?;
+        for (tap, token) in [(&mut stdout, STDOUT), (&mut stderr, STDERR)] {
+            if let Some(Tap::Pipe(pipe)) = tap {
+                poll.registry().register(pipe, token, Interest::READABLE)?;
+            }
+        }
+        poll.registry()
+            .register(&mut SourceFd(&pidfd.as_raw_fd()), EXIT, Interest::READABLE)?;
+
+        let (stdout_tee, stderr_tee) = Tee::open(
+            tee_stdout.as_deref(),
+            tee_stderr.as_deref(),
+            tee_rotation.as_ref(),
+            tee_only,
+        )?;
+        let stdout_framer = Framer::new(
+            Stream::Stdout,
+            mode,
+            stdout_options,
+            max_line_length,
+            decoder,
+            stdout_tee,
+            line_pool.clone(),
+        );
+        let stderr_framer = Framer::new(
+            Stream::Stderr,
+            mode,
+            stderr_options,
+            max_line_length,
+            decoder,
+            stderr_tee,
+            line_pool,
+        );
+
+        Ok(Self {
+            pid,
+            poll,
+            events: Events::with_capacity(4),
+            pidfd,
+            stdout,
+            stderr,
+            stdout_framer,
+            stderr_framer,
+            output_buf: OutputQueue::new(wall_clock, None),
+            read_buf: vec![0; buffer_size],
+            done: false,
+        })
+    }
+
+    /// Returns the process identifier of the process.
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Waits for the process to write or exit, and reads what it wrote.
+    fn step(&mut self) -> Result<(), io::Error> {
+        let following =
+            matches!(self.stdout, Some(Tap::File(_))) || matches!(self.stderr, Some(Tap::File(_)));
+        match self
+            .poll
+            .poll(&mut self.events, following.then_some(FILE_INTERVAL))
+        {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            res => res?,
+        }
+        let exited = self.events.iter().any(|event| event.token() == EXIT);
+
+        drain(
+            &mut self.stdout,
+            &mut self.stdout_framer,
+            &mut self.output_buf,
+            &mut self.read_buf,
+            &self.poll,
+        )?;
+        drain(
+            &mut self.stderr,
+            &mut self.stderr_framer,
+            &mut self.output_buf,
+            &mut self.read_buf,
+            &self.poll,
+        )?;
+
+        // Other processes may still hold the pipes open, so the end of the
+        // output is not awaited.
+        if exited {
+            self.stdout_framer.flush(&mut self.output_buf);
+            self.stderr_framer.flush(&mut self.output_buf);
+            self.poll
+                .registry()
+                .deregister(&mut SourceFd(&self.pidfd.as_raw_fd()))?;
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether the process that `pidfd` refers to has exited, without
+/// waiting for it to.
+fn exited(pidfd: &OwnedFd) -> Result<bool, io::Error> {
+    let mut fds = [libc::pollfd {
+        fd: pidfd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    loop {
+        match unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            n => return Ok(n > 0),
+        }
+    }
+}
+
+/// Opens the stream `fd` of the process `pid` for reading, along with the
+/// device and inode it refers to, or returns `None` if it is closed or
+/// neither a pipe nor a regular file.
+fn open_tap(pid: u32, fd: i32) -> Result<Option<(Tap, FileId)>, io::Error> {
+    let path = format!("/proc/{pid}/fd/{fd}");
+    let metadata = match fs::metadata(&path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        res => res?,
+    };
+
+    let tap = if metadata.file_type().is_fifo() {
+        // Opening a pipe through `/proc` opens it anew, with the requested
+        // access instead of that of the process's end.
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)?;
+        Tap::Pipe(unsafe { Receiver::from_raw_fd(file.into_raw_fd()) })
+    } else if metadata.is_file() {
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::End(0))?;
+        Tap::File(file)
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some((tap, (metadata.dev(), metadata.ino()))))
+}
+
+/// Reads everything that is available from `tap`, dropping it once the pipe
+/// has no writers left.
+fn drain(
+    tap: &mut Option<Tap>,
+    framer: &mut Framer,
+    out_buf: &mut OutputQueue,
+    buf: &mut Vec<u8>,
+    poll: &Poll,
+) -> Result<(), io::Error> {
+    let Some(reader) = tap else {
+        return Ok(());
+    };
+
+    loop {
+        let n = match reader.read(buf) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => res?,
+        };
+
+        if n == 0 {
+            break;
+        }
+        out_buf.stamp();
+        framer.push(&buf[..n], out_buf);
+        grow_read_buf(buf, n);
+    }
+
+    // A file is only at its end for now.
+    if let Some(Tap::Pipe(mut pipe)) = tap.take_if(|tap| matches!(tap, Tap::Pipe(_))) {
+        poll.registry().deregister(&mut pipe)?;
+        framer.flush(out_buf);
+    }
+    Ok(())
+}
+
+impl Iterator for AttachedReader {
+    type Item = Result<Out, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.output_buf.pop_front() {
+                return Some(event.map(|event| event.out));
+            }
+            if self.done {
+                return None;
+            }
+
+            if let Err(err) = self.step() {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+    }
+}
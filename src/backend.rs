@@ -0,0 +1,507 @@
+#[cfg(unix)]
+use std::{
+    ffi::OsStr,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+};
+use std::{ffi::OsString, fmt, io, path::PathBuf, process::Command};
+
+/// Where a command runs, set with
+/// [`backend`](crate::ProcessReaderBuilder::backend).
+///
+/// A backend turns the command into one that is spawned locally and reads
+/// the output of the original command, such as an SSH client that runs it
+/// on another host. The local command is then read like any other, so its
+/// output is yielded as the same [`Out`](crate::Out) items, followed by the
+/// exit status that it reports for the original command.
+///
+/// Variables set with [`env`](crate::ProcessReaderBuilder::env) are set on
+/// the command before it is passed to the backend, like those set on the
+/// [`Command`] itself. The other builder options that configure the spawned
+/// process, such as [`uid`](crate::ProcessReaderBuilder::uid),
+/// [`rlimit`](crate::ProcessReaderBuilder::rlimit) or
+/// [`env_clear`](crate::ProcessReaderBuilder::env_clear), apply to the
+/// command that the backend returns, which is spawned locally.
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Returns the command that is spawned locally to run `cmd`.
+    fn command(&self, cmd: Command) -> Result<Command, io::Error>;
+}
+
+impl<B: Backend + ?Sized> Backend for Box<B> {
+    fn command(&self, cmd: Command) -> Result<Command, io::Error> {
+        (**self).command(cmd)
+    }
+}
+
+/// Runs commands on this host, which is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Local;
+
+impl Backend for Local {
+    fn command(&self, cmd: Command) -> Result<Command, io::Error> {
+        Ok(cmd)
+    }
+}
+
+/// Runs commands inside of a running container, through the `exec` command
+/// of the Docker or Podman client.
+///
+/// The engine's API is not used directly: like every [`Backend`], this only
+/// turns the command into one that the reader spawns and reads locally, so
+/// creating, starting and inspecting the exec is left to the client. Use the
+/// API itself where the exit code has to be told apart from the client's
+/// own failures, which are described below.
+///
+/// The client attaches to the command through the engine's API, splits its
+/// output back into stdout and stderr, and exits with the command's exit
+/// code, which is then reported in [`Out::Done`](crate::Out::Done). The
+/// current directory and the environment variables set on the [`Command`]
+/// are passed on, and so is stdin if the backend is
+/// [`interactive`](Self::interactive).
+///
+/// The client reports its own failures through the same exit code: 125 if
+/// it failed itself, such as when the container is not running, 126 if the
+/// command could not be run and 127 if it was not found. So
+/// [`Out::Done`](crate::Out::Done) cannot tell those apart from a command
+/// that exited with one of these codes, and the client's error message is
+/// yielded as a line of stderr like the command's own.
+///
+/// Killing the client does not stop the command inside of the container.
+/// Builder options that configure the spawned process, other than
+/// [`env`](crate::ProcessReaderBuilder::env), apply to the client, and the
+/// standard streams configured on the [`Command`] itself are not carried
+/// over.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{Container, Out, ProcessReader};
+///
+/// let mut cmd = Command::new("psql");
+/// cmd.args(["-c", "select 1"]);
+/// let reader = ProcessReader::builder(cmd)
+///     .backend(Container::docker("db").user("postgres"))
+///     .start()?;
+/// for out in reader {
+///     if let Out::Done(status) = out? {
+///         println!("psql exited with {status}");
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Container {
+    program: PathBuf,
+    container: OsString,
+    options: Vec<OsString>,
+}
+
+impl Container {
+    /// Creates a backend that runs commands in `container`, by name or ID,
+    /// with the `docker` client.
+    pub fn docker(container: impl Into<OsString>) -> Self {
+        Self::with_client("docker", container.into())
+    }
+
+    /// Creates a backend that runs commands in `container`, by name or ID,
+    /// with the `podman` client.
+    pub fn podman(container: impl Into<OsString>) -> Self {
+        Self::with_client("podman", container.into())
+    }
+
+    fn with_client(program: &str, container: OsString) -> Self {
+        Self {
+            program: PathBuf::from(program),
+            container,
+            options: Vec::new(),
+        }
+    }
+
+    /// Sets the client to run, such as the full path of `docker`. Defaults
+    /// to the client the backend was created for, from the `PATH`.
+    pub fn program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Sets the user, or `user:group`, that the command runs as inside of
+    /// the container. Defaults to the container's user.
+    pub fn user(self, user: impl Into<OsString>) -> Self {
+        self.arg("--user").arg(user)
+    }
+
+    /// Sets whether stdin is passed on to the command. Defaults to `false`,
+    /// which leaves it closed.
+    ///
+    /// Enable this along with
+    /// [`pipe_stdin`](crate::ProcessReaderBuilder::pipe_stdin) to write to
+    /// the command.
+    pub fn interactive(self, enable: bool) -> Self {
+        match enable {
+            true => self.arg("--interactive"),
+            false => self,
+        }
+    }
+
+    /// Sets whether the command runs with extended privileges. Defaults to
+    /// `false`.
+    pub fn privileged(self, enable: bool) -> Self {
+        match enable {
+            true => self.arg("--privileged"),
+            false => self,
+        }
+    }
+
+    fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.options.push(arg.into());
+        self
+    }
+}
+
+impl Backend for Container {
+    fn command(&self, cmd: Command) -> Result<Command, io::Error> {
+        let mut exec = Command::new(&self.program);
+        exec.arg("exec").args(&self.options);
+        if let Some(dir) = cmd.get_current_dir() {
+            exec.arg("--workdir").arg(dir);
+        }
+
+        let mut removed = Vec::new();
+        for (key, value) in cmd.get_envs() {
+            match value {
+                Some(value) => {
+                    let mut var = key.to_owned();
+                    var.push("=");
+                    var.push(value);
+                    exec.arg("--env").arg(var);
+                }
+                None => removed.push(key),
+            }
+        }
+
+        exec.arg("--").arg(&self.container);
+        // The client can only set variables, so those that are removed are
+        // removed by `env` inside of the container.
+        if !removed.is_empty() {
+            exec.arg("env");
+            for key in removed {
+                exec.arg("-u").arg(key);
+            }
+            exec.arg("--");
+        }
+        exec.arg(cmd.get_program()).args(cmd.get_args());
+        Ok(exec)
+    }
+}
+
+/// Runs commands on a remote host through the `ssh` client, in an exec
+/// channel without a terminal.
+///
+/// The program, its arguments, the current directory and the environment
+/// variables set on the [`Command`] are quoted for a POSIX shell on the
+/// remote host, which runs the program through `env`.
+///
+/// The exit code of the remote command is reported as that of the local
+/// client. OpenSSH reports a command that was killed by a signal, like its
+/// own errors, with exit code 255. Killing the local client closes the
+/// connection, but whether that stops the remote command is up to it.
+///
+/// The client runs in batch mode, so that it fails rather than prompting for
+/// a password. Builder options that configure the spawned process, such as
+/// [`uid`](crate::ProcessReaderBuilder::uid) or
+/// [`env_clear`](crate::ProcessReaderBuilder::env_clear), apply to the local
+/// client, and so do the standard streams configured on the builder. Those configured on
+/// the [`Command`] itself are not carried over.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{Out, ProcessReader, Ssh};
+///
+/// let mut cmd = Command::new("systemctl");
+/// cmd.args(["restart", "app"]);
+/// let reader = ProcessReader::builder(cmd)
+///     .backend(Ssh::new("deploy@web-1").port(2222))
+///     .start()?;
+/// for out in reader {
+///     if let Out::Stdout(line) | Out::Stderr(line) = out? {
+///         println!("web-1: {line}");
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct Ssh {
+    program: PathBuf,
+    destination: OsString,
+    options: Vec<OsString>,
+}
+
+#[cfg(unix)]
+impl Ssh {
+    /// Creates a backend that runs commands on `destination`, such as
+    /// `host` or `user@host`, or a host configured in `~/.ssh/config`.
+    pub fn new(destination: impl Into<OsString>) -> Self {
+        Self {
+            program: PathBuf::from("ssh"),
+            destination: destination.into(),
+            options: Vec::new(),
+        }
+    }
+
+    /// Sets the `ssh` client to run. Defaults to `ssh` from the `PATH`.
+    pub fn program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Sets the port to connect to.
+    pub fn port(self, port: u16) -> Self {
+        self.arg("-p").arg(port.to_string())
+    }
+
+    /// Sets the file that the private key is read from.
+    pub fn identity_file(self, path: impl Into<PathBuf>) -> Self {
+        self.arg("-i").arg(path.into())
+    }
+
+    /// Sets a client option, as with `-o key=value`, such as
+    /// `ConnectTimeout`.
+    pub fn option(self, key: &str, value: &str) -> Self {
+        self.arg("-o").arg(format!("{key}={value}"))
+    }
+
+    fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.options.push(arg.into());
+        self
+    }
+}
+
+#[cfg(unix)]
+impl Backend for Ssh {
+    fn command(&self, cmd: Command) -> Result<Command, io::Error> {
+        let mut remote = Vec::new();
+        if let Some(dir) = cmd.get_current_dir() {
+            remote.extend_from_slice(b"cd ");
+            quote(dir.as_os_str(), &mut remote);
+            remote.extend_from_slice(b" && ");
+        }
+
+        // Variables are removed as options of `env`, which have to come
+        // before those that are set.
+        remote.extend_from_slice(b"exec env");
+        for (key, _) in cmd.get_envs().filter(|(_, value)| value.is_none()) {
+            remote.extend_from_slice(b" -u ");
+            quote(key, &mut remote);
+        }
+        remote.extend_from_slice(b" --");
+        for (key, value) in cmd.get_envs() {
+            let Some(value) = value else {
+                continue;
+            };
+            let mut var = key.to_owned();
+            var.push("=");
+            var.push(value);
+            remote.push(b' ');
+            quote(&var, &mut remote);
+        }
+        for word in [cmd.get_program()].into_iter().chain(cmd.get_args()) {
+            remote.push(b' ');
+            quote(word, &mut remote);
+        }
+
+        let mut ssh = Command::new(&self.program);
+        ssh.args(["-T", "-o", "BatchMode=yes"])
+            .args(&self.options)
+            .arg("--")
+            .arg(&self.destination)
+            .arg(OsString::from_vec(remote));
+        Ok(ssh)
+    }
+}
+
+/// Runs commands in a transient systemd unit, through `systemd-run`, which
+/// creates it with the `StartTransientUnit` call of the service manager's
+/// D-Bus API.
+///
+/// The reader does not call D-Bus itself: like every [`Backend`], this only
+/// turns the command into one that is spawned locally, so the unit is
+/// created by `systemd-run`, which has to be installed, and its failures are
+/// reported like those of the command.
+///
+/// By default the command runs in a scope, which `systemd-run` moves itself
+/// into before executing the command in place. The command then stays a
+/// child of the reader, with the environment, current directory and
+/// standard streams it would have had otherwise, and killing it works as
+/// usual. With [`service`](Self::service) it runs as a service instead,
+/// started by the service manager in a clean environment, and `systemd-run`
+/// passes its streams and exit code through. Killing `systemd-run` does not
+/// stop the service, and builder options that configure the spawned process,
+/// such as [`uid`](crate::ProcessReaderBuilder::uid), only apply to
+/// `systemd-run` rather than the service.
+///
+/// Either way the unit gets its own cgroup, so the processes it leaves
+/// behind are cleaned up by systemd once it is stopped, and resource
+/// properties such as [`memory_max`](Self::memory_max) are enforced for all
+/// of them. The unit is garbage collected once it exits, even if it failed.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{ProcessReader, Systemd};
+///
+/// let mut cmd = Command::new("make");
+/// cmd.arg("-j8");
+/// let reader = ProcessReader::builder(cmd)
+///     .backend(Systemd::scope().user(true).memory_max(4 << 30).cpu_quota(400))
+///     .start()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug)]
+pub struct Systemd {
+    program: PathBuf,
+    service: bool,
+    options: Vec<OsString>,
+}
+
+#[cfg(target_os = "linux")]
+impl Systemd {
+    /// Creates a backend that runs commands in a transient scope.
+    pub fn scope() -> Self {
+        Self::new(false)
+    }
+
+    /// Creates a backend that runs commands as a transient service.
+    pub fn service() -> Self {
+        Self::new(true)
+    }
+
+    fn new(service: bool) -> Self {
+        Self {
+            program: PathBuf::from("systemd-run"),
+            service,
+            options: Vec::new(),
+        }
+    }
+
+    /// Sets the `systemd-run` program to run. Defaults to `systemd-run` from
+    /// the `PATH`.
+    pub fn program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Sets whether the unit is created by the calling user's service
+    /// manager rather than the system's. Defaults to `false`.
+    pub fn user(self, enable: bool) -> Self {
+        match enable {
+            true => self.arg("--user"),
+            false => self,
+        }
+    }
+
+    /// Sets the name of the unit. Defaults to one generated by systemd.
+    ///
+    /// Starting a command fails while another unit with the same name is
+    /// still running.
+    pub fn unit(self, name: impl Into<OsString>) -> Self {
+        self.arg(with_prefix("--unit=", name.into()))
+    }
+
+    /// Sets the slice that the unit is placed in, such as `batch.slice`.
+    pub fn slice(self, name: impl Into<OsString>) -> Self {
+        self.arg(with_prefix("--slice=", name.into()))
+    }
+
+    /// Sets a property of the unit, as with `-p key=value`, such as
+    /// `IOWeight` or `MemoryHigh`.
+    pub fn property(self, key: &str, value: &str) -> Self {
+        self.arg("-p").arg(format!("{key}={value}"))
+    }
+
+    /// Sets the amount of memory in bytes that the unit may use before its
+    /// processes are killed, as the `MemoryMax` property.
+    pub fn memory_max(self, bytes: u64) -> Self {
+        self.property("MemoryMax", &bytes.to_string())
+    }
+
+    /// Sets the CPU time that the unit may use, in percent of one CPU, as
+    /// the `CPUQuota` property. Values above 100 allow more than one CPU.
+    pub fn cpu_quota(self, percent: u32) -> Self {
+        self.property("CPUQuota", &format!("{percent}%"))
+    }
+
+    /// Sets the number of tasks that the unit may have, as the `TasksMax`
+    /// property.
+    pub fn tasks_max(self, tasks: u64) -> Self {
+        self.property("TasksMax", &tasks.to_string())
+    }
+
+    fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.options.push(arg.into());
+        self
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Backend for Systemd {
+    fn command(&self, cmd: Command) -> Result<Command, io::Error> {
+        let mut run = Command::new(&self.program);
+        run.args(["--quiet", "--collect"]).args(&self.options);
+
+        if !self.service {
+            // A scope runs the command in place of `systemd-run`, which
+            // passes on what it was spawned with.
+            run.arg("--scope");
+            if let Some(dir) = cmd.get_current_dir() {
+                run.current_dir(dir);
+            }
+            for (key, value) in cmd.get_envs() {
+                match value {
+                    Some(value) => run.env(key, value),
+                    None => run.env_remove(key),
+                };
+            }
+            run.arg("--").arg(cmd.get_program()).args(cmd.get_args());
+            return Ok(run);
+        }
+
+        run.args(["--pipe", "--wait"]);
+        if let Some(dir) = cmd.get_current_dir() {
+            run.arg(with_prefix("--working-directory=", dir.into()));
+        }
+        // Services start with a clean environment, so only the variables
+        // that are set have to be passed on.
+        for (key, value) in cmd.get_envs() {
+            if let Some(value) = value {
+                let mut var = with_prefix("--setenv=", key.to_owned());
+                var.push("=");
+                var.push(value);
+                run.arg(var);
+            }
+        }
+        run.arg("--").arg(cmd.get_program()).args(cmd.get_args());
+        Ok(run)
+    }
+}
+
+/// Returns `value` with `prefix` in front of it, for options of the form
+/// `--key=value`.
+#[cfg(target_os = "linux")]
+fn with_prefix(prefix: &str, value: OsString) -> OsString {
+    let mut arg = OsString::from(prefix);
+    arg.push(value);
+    arg
+}
+
+/// Appends `word` to `out` in single quotes, which a POSIX shell takes
+/// literally.
+#[cfg(unix)]
+fn quote(word: &OsStr, out: &mut Vec<u8>) {
+    out.push(b'\'');
+    for &byte in word.as_bytes() {
+        match byte {
+            b'\'' => out.extend_from_slice(b"'\\''"),
+            byte => out.push(byte),
+        }
+    }
+    out.push(b'\'');
+}
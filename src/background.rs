@@ -0,0 +1,166 @@
+use std::{
+    io,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use crate::{Out, ProcessHandle, ProcessReader, Stdin};
+
+/// Controls a child whose output is read on a background thread and sent
+/// over a channel.
+///
+/// Created with [`ProcessReader::spawn_std_channel`] or, with the
+/// `crossbeam` feature, [`ProcessReader::spawn_channel`]. The thread stops
+/// once the child has exited, or once the receiver has been dropped and the
+/// child writes its next output, at which point the reader's
+/// [`DropPolicy`](crate::DropPolicy) applies.
+///
+/// Dropping this waits for the thread to stop, so it should be kept until
+/// the receiver is done.
+#[derive(Debug)]
+pub struct ReaderThread {
+    handle: ProcessHandle,
+    stdin: Option<Stdin>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ReaderThread {
+    /// Moves `reader` onto a new thread that passes every item to `send`
+    /// until it returns `false`.
+    pub(crate) fn spawn(
+        mut reader: ProcessReader,
+        mut send: impl FnMut(Result<Out, io::Error>) -> bool + Send + 'static,
+    ) -> Result<Self, io::Error> {
+        let handle = reader.handle();
+        let stdin = reader.take_stdin();
+        let thread = thread::Builder::new()
+            .name(format!("process-reader-{}", reader.id()))
+            .spawn(move || {
+                for out in reader {
+                    if !send(out) {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            handle,
+            stdin,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the OS-assigned process identifier of the child.
+    pub fn id(&self) -> u32 {
+        self.handle.id()
+    }
+
+    /// Returns a handle for killing, signalling or waiting for the child.
+    pub fn handle(&self) -> ProcessHandle {
+        self.handle.clone()
+    }
+
+    /// Returns a handle for writing to the child's stdin.
+    ///
+    /// See [`ProcessReader::stdin`].
+    pub fn stdin(&mut self) -> Option<&mut Stdin> {
+        self.stdin.as_mut()
+    }
+
+    /// Takes ownership of the child's stdin handle.
+    pub fn take_stdin(&mut self) -> Option<Stdin> {
+        self.stdin.take()
+    }
+}
+
+impl Drop for ReaderThread {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl ProcessReader {
+    /// Spawns `cmd` and reads its output on a background thread, which sends
+    /// every item to the returned [`std::sync::mpsc`] channel.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::ProcessReader;
+    ///
+    /// let (receiver, _thread) = ProcessReader::spawn_std_channel(Command::new("ls"))?;
+    /// for out in receiver {
+    ///     println!("{:?}", out?);
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn spawn_std_channel(
+        cmd: std::process::Command,
+    ) -> Result<(mpsc::Receiver<Result<Out, io::Error>>, ReaderThread), io::Error> {
+        Self::start(cmd)?.into_std_channel()
+    }
+
+    /// Moves the reader onto a background thread, like
+    /// [`spawn_std_channel`](Self::spawn_std_channel).
+    pub fn into_std_channel(
+        self,
+    ) -> Result<(mpsc::Receiver<Result<Out, io::Error>>, ReaderThread), io::Error> {
+        let (sender, receiver) = mpsc::channel();
+        let thread = ReaderThread::spawn(self, move |out| sender.send(out).is_ok())?;
+        Ok((receiver, thread))
+    }
+}
+
+#[cfg(feature = "crossbeam")]
+impl ProcessReader {
+    /// Spawns `cmd` and reads its output on a background thread, which sends
+    /// every item to the returned channel.
+    ///
+    /// Since the receiver is a [`crossbeam_channel::Receiver`], the output of
+    /// several children can be waited for together with other channels using
+    /// [`crossbeam_channel::select!`].
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use crossbeam_channel::select;
+    /// use incremental_command::ProcessReader;
+    ///
+    /// let (build, _build_thread) = ProcessReader::spawn_channel(Command::new("make"))?;
+    /// let (server, _server_thread) = ProcessReader::spawn_channel(Command::new("./serve"))?;
+    /// loop {
+    ///     select! {
+    ///         recv(build) -> out => println!("build: {:?}", out),
+    ///         recv(server) -> out => println!("server: {:?}", out),
+    ///     }
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn spawn_channel(
+        cmd: std::process::Command,
+    ) -> Result<
+        (
+            crossbeam_channel::Receiver<Result<Out, io::Error>>,
+            ReaderThread,
+        ),
+        io::Error,
+    > {
+        Self::start(cmd)?.into_channel()
+    }
+
+    /// Moves the reader onto a background thread, like
+    /// [`spawn_channel`](Self::spawn_channel).
+    pub fn into_channel(
+        self,
+    ) -> Result<
+        (
+            crossbeam_channel::Receiver<Result<Out, io::Error>>,
+            ReaderThread,
+        ),
+        io::Error,
+    > {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let thread = ReaderThread::spawn(self, move |out| sender.send(out).is_ok())?;
+        Ok((receiver, thread))
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, IsTerminal, Write},
+    process::{Command, ExitCode, ExitStatus},
+    time::Instant,
+};
+
+use incremental_command::{write_json_line, Event, ExitKind, Out, ProcessReader};
+
+const USAGE: &str = "\
+Usage: process-reader run [OPTIONS] -- <COMMAND> [ARGS]...
+
+Runs COMMAND and prints its output line by line, annotated with the stream
+each line was written to and the time since the command was started. Exits
+with the exit code of COMMAND.
+
+Options:
+      --json            Print every line, and finally the exit status, as a
+                        JSON object
+      --prefix[=LABEL]  Only prefix lines with [stdout] or [stderr], or with
+                        [LABEL] for both streams
+      --color <WHEN>    Print stderr in red: auto, always or never
+                        [default: auto]
+  -h, --help            Print this help";
+
+/// How each line is printed.
+enum Format {
+    /// With the time since the start and the stream.
+    Annotated,
+    /// With only the stream, or a custom label, in brackets.
+    Prefixed(Option<String>),
+    Json,
+}
+
+/// What the command line asks for.
+struct Options {
+    format: Format,
+    color: bool,
+    program: OsString,
+    args: Vec<OsString>,
+}
+
+enum Parsed {
+    Run(Options),
+    Help,
+}
+
+fn parse_args(mut args: impl Iterator<Item = OsString>) -> Result<Parsed, String> {
+    match args.next() {
+        Some(arg) if arg == "run" => {}
+        Some(arg) if arg == "-h" || arg == "--help" => return Ok(Parsed::Help),
+        Some(arg) => return Err(format!("unknown subcommand {arg:?}")),
+        None => return Err("missing subcommand".to_owned()),
+    }
+
+    let missing = || "missing command".to_owned();
+    let mut format = Format::Annotated;
+    let mut color = None;
+    let program = loop {
+        let arg = args.next().ok_or_else(missing)?;
+        match arg.to_str() {
+            Some("--") => break args.next().ok_or_else(missing)?,
+            Some("--json") => format = Format::Json,
+            Some("--prefix") => format = Format::Prefixed(None),
+            Some(option) if option.starts_with("--prefix=") => {
+                let label = &option["--prefix=".len()..];
+                format = Format::Prefixed(Some(label.to_owned()));
+            }
+            Some("--color") => {
+                let when = args.next().ok_or("missing value for --color")?;
+                color = Some(parse_color(&when.to_string_lossy())?);
+            }
+            Some(option) if option.starts_with("--color=") => {
+                color = Some(parse_color(&option["--color=".len()..])?);
+            }
+            Some("-h" | "--help") => return Ok(Parsed::Help),
+            Some(option) if option.starts_with('-') => {
+                return Err(format!("unknown option {option:?}"));
+            }
+            _ => break arg,
+        }
+    };
+
+    Ok(Parsed::Run(Options {
+        format,
+        color: color.unwrap_or_else(|| io::stdout().is_terminal()),
+        program,
+        args: args.collect(),
+    }))
+}
+
+/// Parses the value of `--color` into whether to use colors, where `auto`
+/// depends on whether stdout is a terminal.
+fn parse_color(when: &str) -> Result<bool, String> {
+    match when {
+        "auto" => Ok(io::stdout().is_terminal()),
+        "always" => Ok(true),
+        "never" => Ok(false),
+        _ => Err(format!("invalid value {when:?} for --color")),
+    }
+}
+
+fn run(options: Options) -> Result<ExitStatus, io::Error> {
+    let mut cmd = Command::new(&options.program);
+    cmd.args(&options.args);
+
+    let started = Instant::now();
+    let json = matches!(options.format, Format::Json);
+    let mut reader = ProcessReader::builder(cmd).wall_clock(json).start()?;
+    let mut stdout = io::stdout().lock();
+
+    while let Some(event) = reader.next_event() {
+        let event = event?;
+        if json {
+            write_json_line(&mut stdout, &event)?;
+            stdout.flush()?;
+        }
+
+        if let Out::Done(status) = event.out {
+            return Ok(status);
+        }
+
+        if !json {
+            print_event(&mut stdout, &event, &options, started)?;
+        }
+    }
+
+    Err(io::Error::other("the child's exit status was not reported"))
+}
+
+fn print_event(
+    out: &mut impl Write,
+    event: &Event,
+    options: &Options,
+    started: Instant,
+) -> Result<(), io::Error> {
+    let (tag, text) = match &event.out {
+        Out::Stdout(line) | Out::StdoutProgress(line) => ("stdout", line.clone()),
+        Out::Stderr(line) | Out::StderrProgress(line) => ("stderr", line.clone()),
+        Out::TimedOut => ("status", "timed out".to_owned()),
+        Out::Idle => ("status", "idle".to_owned()),
+        other => ("other", format!("{other:?}")),
+    };
+
+    let (start, end) = match tag {
+        "stderr" if options.color => ("\x1b[31m", "\x1b[0m"),
+        _ => ("", ""),
+    };
+
+    match &options.format {
+        Format::Prefixed(label) => {
+            let label = label.as_deref().unwrap_or(tag);
+            writeln!(out, "{start}[{label}] {text}{end}")?;
+        }
+        _ => {
+            let elapsed = event.at.saturating_duration_since(started).as_secs_f64();
+            writeln!(out, "{start}{elapsed:>10.3} {tag} | {text}{end}")?;
+        }
+    }
+    out.flush()
+}
+
+/// Maps the child's exit status to ours, following the shell convention of
+/// 128 plus the signal number for children killed by a signal.
+fn exit_code(status: ExitStatus) -> ExitCode {
+    match ExitKind::from(status) {
+        ExitKind::Code(code) => ExitCode::from(code as u8),
+        ExitKind::Signaled { signal, .. } => ExitCode::from(128u8.wrapping_add(signal as u8)),
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args_os().skip(1)) {
+        Ok(Parsed::Run(options)) => options,
+        Ok(Parsed::Help) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let program = options.program.clone();
+    match run(options) {
+        Ok(status) => exit_code(status),
+        Err(err) => {
+            eprintln!("error: failed to run {program:?}: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,1526 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io, mem,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus},
+    sync::Arc,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::io::OwnedFd;
+
+#[cfg(unix)]
+use crate::{DetachedJob, DetachedReader, ProcessBackend};
+
+#[cfg(feature = "encoding")]
+use encoding_rs::Encoding;
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+#[cfg(feature = "regex")]
+use crate::OnMatch;
+use crate::{
+    decode::Decoder,
+    env::EnvOptions,
+    framing::StreamOptions,
+    hooks::{Hook, Hooks},
+    sys, Backend, InvalidUtf8, LinePool, Out, ProcessReader, Resource, Rotation,
+};
+#[cfg(target_os = "linux")]
+use crate::{AttachedReader, SeccompFilter};
+
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The size that a read buffer grows to at most while reads keep filling it.
+pub(crate) const MAX_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Doubles `buf`, up to [`MAX_BUFFER_SIZE`], if a read of `n` bytes filled
+/// it, so that a pipe with a lot of output is drained in fewer reads.
+pub(crate) fn grow_read_buf(buf: &mut Vec<u8>, n: usize) {
+    if n == buf.len() && buf.len() < MAX_BUFFER_SIZE {
+        buf.resize((buf.len() * 2).min(MAX_BUFFER_SIZE), 0);
+    }
+}
+
+/// How bytes read from the child are turned into [`Out`](crate::Out) items.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Split output on `\n`, or the configured
+    /// [`delimiter`](ProcessReaderBuilder::delimiter), and yield one item per
+    /// line, dropping `\r`.
+    #[default]
+    Lines,
+    /// Like [`Mode::Lines`], but treat a `\r` that is not followed by `\n` as
+    /// an in-place update of the current line, as used by progress bars.
+    ///
+    /// The text before such a `\r` is yielded as
+    /// [`Out::StdoutProgress`](crate::Out::StdoutProgress) or
+    /// [`Out::StderrProgress`](crate::Out::StderrProgress), once the byte after
+    /// it has been read. Whatever follows replaces it, up to the next update or
+    /// the end of the line.
+    Progress,
+    /// Split output like [`Mode::Lines`], but yield every line exactly as the
+    /// child wrote it, as raw bytes in
+    /// [`Out::StdoutBytes`](crate::Out::StdoutBytes) and
+    /// [`Out::StderrBytes`](crate::Out::StderrBytes).
+    ///
+    /// Lines keep their delimiter, carriage returns and any invalid UTF-8, so
+    /// concatenating them reproduces the output byte for byte.
+    RawLines,
+    /// Split output exactly like [`Mode::Lines`], but yield every line as
+    /// raw bytes in [`Out::StdoutBytes`](crate::Out::StdoutBytes) and
+    /// [`Out::StderrBytes`](crate::Out::StderrBytes), without converting it
+    /// to UTF-8.
+    ///
+    /// This suits line-oriented output that is not necessarily text, such as
+    /// file names, which can be turned into an `OsString` with
+    /// `std::os::unix::ffi::OsStringExt::from_vec` on Unix.
+    ByteLines,
+    /// Yield every chunk as it is read, without any framing.
+    Raw,
+    /// Yield every chunk as it is read, as raw bytes in
+    /// [`Out::StdoutBytes`](crate::Out::StdoutBytes) and
+    /// [`Out::StderrBytes`](crate::Out::StderrBytes).
+    ///
+    /// Unlike the other modes, this is safe to use for binary output.
+    Bytes,
+    /// Split output like [`Mode::ByteLines`], but yield every line as a
+    /// [`bytes::Bytes`] in [`Out::StdoutShared`](crate::Out::StdoutShared)
+    /// and [`Out::StderrShared`](crate::Out::StderrShared).
+    ///
+    /// Lines are slices of the buffer that output is read into, so they do
+    /// not need an allocation and copy of their own, which matters with
+    /// hundreds of thousands of lines per second. A `\r` before the default
+    /// delimiter is dropped. ANSI stripping, patterns and the
+    /// [`max_line_length`](ProcessReaderBuilder::max_line_length) do not
+    /// apply.
+    #[cfg(feature = "bytes")]
+    SharedLines,
+}
+
+/// What happens to a line that is longer than the maximum length.
+///
+/// See [`ProcessReaderBuilder::max_line_length`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineOverflow {
+    /// Keep the start of the line, discard the rest and emit
+    /// [`Out::Truncated`](crate::Out::Truncated) right after it.
+    Truncate,
+    /// Emit the line in pieces of the maximum length.
+    Split,
+    /// Discard the line and yield an error of kind
+    /// [`io::ErrorKind::InvalidData`] in its place. Reading continues with
+    /// the next line.
+    Error,
+}
+
+/// Which output of each stream a [`ProcessReader`] yields, for when only a
+/// sense of progress is needed.
+///
+/// See [`ProcessReaderBuilder::sample`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sampling {
+    /// Yield the first item and every `n`th one after it.
+    Nth(u64),
+    /// Yield the first item, and after that the first one read at least
+    /// this long after the last item that was yielded.
+    Interval(Duration),
+}
+
+/// How much output of each stream a [`ProcessReader`] yields at most.
+///
+/// See [`ProcessReaderBuilder::output_limit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputLimit {
+    /// At most this many lines, or chunks in modes that do not split lines.
+    Lines(usize),
+    /// At most this many bytes, not counting delimiters. Items are never
+    /// split, so an item that does not fit is dropped as a whole.
+    Bytes(usize),
+}
+
+/// Which part of a stream's output is dropped once it exceeds its
+/// [`OutputLimit`].
+///
+/// See [`ProcessReaderBuilder::output_limit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Truncation {
+    /// Keep the start of the output and drop the rest.
+    Tail,
+    /// Keep the start and the end of the output, each up to half of the
+    /// limit, and drop everything in between. The end is only yielded once
+    /// the child has exited, since it is not known before.
+    Middle,
+}
+
+/// How fast a [`ProcessReader`] yields the output of each stream at most.
+///
+/// See [`ProcessReaderBuilder::rate_limit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimit {
+    /// At most this many lines per second, or chunks in modes that do not
+    /// split lines.
+    Lines(u32),
+    /// At most this many bytes per second, not counting delimiters.
+    Bytes(u64),
+}
+
+/// What happens to output beyond a [`RateLimit`].
+///
+/// See [`ProcessReaderBuilder::rate_limit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RatePolicy {
+    /// Drop it.
+    Drop,
+    /// Hold back the most recent item, replacing it with every newer one,
+    /// and yield it once the rate allows, so that the latest output is
+    /// always shown eventually.
+    Coalesce,
+}
+
+/// What happens to new output when the queue of items waiting to be yielded
+/// is full.
+///
+/// See [`ProcessReaderBuilder::queue_capacity`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Stop reading from the child until there is room again. Once the pipe
+    /// buffers are full too, the child blocks on its next write.
+    Backpressure,
+    /// Drop the oldest output in the queue to make room.
+    DropOldest,
+    /// Drop the new output.
+    DropNewest,
+}
+
+/// What a [`ProcessReader`] does when its child has been idle for too long.
+///
+/// See [`ProcessReaderBuilder::idle_timeout`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdleAction {
+    /// Emit [`Out::Idle`](crate::Out::Idle) and keep reading. The event is
+    /// repeated for every further idle period.
+    Notify,
+    /// Emit [`Out::Idle`](crate::Out::Idle) and kill the child.
+    Kill,
+}
+
+/// What happens to a still-running child when its [`ProcessReader`] is
+/// dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Leave the child running. It is not reaped, so it remains a zombie
+    /// after exiting until the parent process exits.
+    #[default]
+    Detach,
+    /// Kill the child and wait for it to exit.
+    Kill,
+    /// Close the child's stdin and wait for it to exit, discarding any
+    /// further output so it cannot block on a full pipe.
+    Wait,
+}
+
+/// How the kernel schedules the child, set with
+/// [`scheduling_policy`](ProcessReaderBuilder::scheduling_policy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// The default time-sharing policy, `SCHED_OTHER`.
+    Other,
+    /// For CPU-bound work that is not interactive, `SCHED_BATCH`. The child
+    /// is preempted less often, but woken up later.
+    Batch,
+    /// For work that only runs when the CPU would otherwise be idle,
+    /// `SCHED_IDLE`.
+    Idle,
+}
+
+/// A namespace that the child is moved into a new one of, with
+/// [`unshare`](ProcessReaderBuilder::unshare).
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Namespace {
+    /// A new PID namespace, in which the child sees only itself and its
+    /// descendants.
+    Pid,
+    /// A new mount namespace, in which mounts are not visible to the rest
+    /// of the system.
+    Mount,
+    /// A new network namespace, with only a loopback interface.
+    Network,
+    /// A new IPC namespace, with its own System V IPC objects and POSIX
+    /// message queues.
+    Ipc,
+}
+
+/// The niceness, scheduling policy and CPU affinity of the child.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Scheduling {
+    pub(crate) nice: Option<i32>,
+    pub(crate) policy: Option<SchedulingPolicy>,
+    pub(crate) cpus: Option<Vec<usize>>,
+}
+
+/// The user and groups the child runs as, set with
+/// [`uid`](ProcessReaderBuilder::uid), [`gid`](ProcessReaderBuilder::gid)
+/// and [`groups`](ProcessReaderBuilder::groups).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Credentials {
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
+    pub(crate) groups: Option<Vec<u32>>,
+}
+
+/// Configures how a [`ProcessReader`] spawns and reads its child.
+///
+/// Created with [`ProcessReader::builder`].
+#[derive(Debug)]
+pub struct ProcessReaderBuilder {
+    pub(crate) cmd: Command,
+    /// Where the command runs, or `None` to run it on this host.
+    pub(crate) backend: Option<Box<dyn Backend>>,
+    /// How the child is spawned, or `None` for the built-in way.
+    #[cfg(unix)]
+    pub(crate) process_backend: Option<Arc<dyn ProcessBackend>>,
+    pub(crate) env: EnvOptions,
+    pub(crate) credentials: Credentials,
+    pub(crate) umask: Option<u32>,
+    pub(crate) root: Option<PathBuf>,
+    pub(crate) rlimits: Vec<(Resource, u64)>,
+    pub(crate) scheduling: Scheduling,
+    #[cfg(target_os = "linux")]
+    pub(crate) oom_score_adj: Option<i32>,
+    #[cfg(target_os = "linux")]
+    pub(crate) cgroup: Option<PathBuf>,
+    #[cfg(target_os = "linux")]
+    pub(crate) namespaces: Vec<Namespace>,
+    #[cfg(target_os = "linux")]
+    pub(crate) seccomp: Option<SeccompFilter>,
+    pub(crate) buffer_size: usize,
+    pub(crate) mode: Mode,
+    pub(crate) stdout: StreamOptions,
+    pub(crate) stderr: StreamOptions,
+    pub(crate) max_line_length: Option<(usize, LineOverflow)>,
+    pub(crate) decoder: Decoder,
+    pub(crate) line_pool: Option<LinePool>,
+    pub(crate) tee_stdout: Option<PathBuf>,
+    pub(crate) tee_stderr: Option<PathBuf>,
+    pub(crate) tee_rotation: Option<Rotation>,
+    pub(crate) tee_only: bool,
+    pub(crate) capture_stdout: bool,
+    pub(crate) capture_stderr: bool,
+    pub(crate) capture_fds: Vec<i32>,
+    /// The files to follow, by name.
+    pub(crate) follow: Vec<(Arc<str>, PathBuf)>,
+    /// The file descriptors to read alongside the child's output, by name.
+    #[cfg(unix)]
+    pub(crate) sources: Vec<(Arc<str>, OwnedFd)>,
+    pub(crate) merge_stderr: bool,
+    pub(crate) pipe_stdin: bool,
+    pub(crate) pty: bool,
+    pub(crate) process_group: bool,
+    pub(crate) new_session: bool,
+    #[cfg(unix)]
+    pub(crate) posix_spawn: bool,
+    pub(crate) drop_policy: DropPolicy,
+    pub(crate) wall_clock: bool,
+    pub(crate) resource_usage: bool,
+    pub(crate) locate_core_dumps: bool,
+    pub(crate) check: Option<usize>,
+    pub(crate) hooks: Hooks,
+    pub(crate) queue_capacity: Option<(usize, QueuePolicy)>,
+    pub(crate) sampling: Option<Sampling>,
+    pub(crate) output_limit: Option<(OutputLimit, Truncation)>,
+    pub(crate) rate_limit: Option<(RateLimit, RatePolicy)>,
+    pub(crate) poll_timeout: Option<Duration>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) idle_timeout: Option<(Duration, IdleAction)>,
+}
+
+impl ProcessReaderBuilder {
+    pub(crate) fn new(cmd: Command) -> Self {
+        Self {
+            cmd,
+            backend: None,
+            #[cfg(unix)]
+            process_backend: None,
+            env: EnvOptions::default(),
+            credentials: Credentials::default(),
+            umask: None,
+            root: None,
+            rlimits: Vec::new(),
+            scheduling: Scheduling::default(),
+            #[cfg(target_os = "linux")]
+            oom_score_adj: None,
+            #[cfg(target_os = "linux")]
+            cgroup: None,
+            #[cfg(target_os = "linux")]
+            namespaces: Vec::new(),
+            #[cfg(target_os = "linux")]
+            seccomp: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            mode: Mode::default(),
+            stdout: StreamOptions::default(),
+            stderr: StreamOptions::default(),
+            max_line_length: None,
+            decoder: Decoder::default(),
+            line_pool: None,
+            tee_stdout: None,
+            tee_stderr: None,
+            tee_rotation: None,
+            tee_only: false,
+            capture_stdout: true,
+            capture_stderr: true,
+            capture_fds: Vec::new(),
+            follow: Vec::new(),
+            #[cfg(unix)]
+            sources: Vec::new(),
+            merge_stderr: false,
+            pipe_stdin: false,
+            pty: false,
+            process_group: false,
+            new_session: false,
+            #[cfg(unix)]
+            posix_spawn: false,
+            drop_policy: DropPolicy::default(),
+            wall_clock: false,
+            resource_usage: false,
+            locate_core_dumps: false,
+            check: None,
+            hooks: Hooks::default(),
+            queue_capacity: None,
+            sampling: None,
+            output_limit: None,
+            rate_limit: None,
+            poll_timeout: None,
+            timeout: None,
+            idle_timeout: None,
+        }
+    }
+
+    /// Starts the child with an empty environment, apart from the variables
+    /// set on the [`Command`] and with [`env`](Self::env).
+    pub fn env_clear(mut self) -> Self {
+        self.env.clear = true;
+        self
+    }
+
+    /// Inherits only the listed variables from the parent's environment,
+    /// instead of all of them. Can be called repeatedly to allow more.
+    ///
+    /// Variables set on the [`Command`] and with [`env`](Self::env) are
+    /// passed regardless.
+    pub fn env_allow<I, K>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<OsStr>,
+    {
+        let allow = self.env.allow.get_or_insert_with(Vec::new);
+        allow.extend(keys.into_iter().map(|key| key.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets an environment variable for the child, overriding any value
+    /// inherited or set on the [`Command`].
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        let (key, value) = (key.as_ref().to_owned(), value.as_ref().to_owned());
+        self.env.vars.push((key, value));
+        self
+    }
+
+    /// Returns the environment the child will be spawned with, sorted by
+    /// name, based on the parent's environment as it is now.
+    ///
+    /// Changes that are not visible through [`Command::get_envs`] are not
+    /// reflected, such as calling [`Command::env_clear`] on the command
+    /// itself.
+    pub fn environment(&self) -> Vec<(OsString, OsString)> {
+        self.env.resolve(&self.cmd).into_iter().collect()
+    }
+
+    /// Sets where the command runs, such as on a remote host with
+    /// [`Ssh`](crate::Ssh), in a container with
+    /// [`Container`](crate::Container) or in a systemd unit with
+    /// [`Systemd`](crate::Systemd). Defaults to running it on this host, like
+    /// [`Local`](crate::Local).
+    ///
+    /// The backend is applied when the child is spawned, and the command it
+    /// returns is spawned and read in place of the original one. Variables
+    /// set with [`env`](Self::env) are set on the original command, for the
+    /// backend to pass on. The other options that configure the spawned
+    /// process, such as [`env_clear`](Self::env_clear), [`uid`](Self::uid)
+    /// or [`rlimit`](Self::rlimit), apply to the local command that the
+    /// backend returns.
+    pub fn backend(mut self, backend: impl Backend + 'static) -> Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    /// Spawns the child, hands over its streams, and waits for, kills and
+    /// signals it with `backend`, instead of with [`Pipes`]. See
+    /// [`ProcessBackend`].
+    ///
+    /// The options that decide how the streams are connected are up to the
+    /// backend, so [`pty`](Self::pty), [`merge_stderr`](Self::merge_stderr)
+    /// and [`capture_fd`](Self::capture_fd) make spawning fail with a
+    /// backend. So does [`resource_usage`](Self::resource_usage), since the
+    /// resources are collected when reaping the child.
+    ///
+    /// [`Pipes`]: crate::Pipes
+    /// [`ProcessBackend`]: crate::ProcessBackend
+    #[cfg(unix)]
+    pub fn process_backend(mut self, backend: impl ProcessBackend + 'static) -> Self {
+        self.process_backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Replaces the command with the one that the backend runs it with, if
+    /// any, so that what is configured on it afterwards applies to the
+    /// command that is spawned.
+    pub(crate) fn apply_backend(&mut self) -> Result<(), io::Error> {
+        if let Some(backend) = self.backend.take() {
+            self.env.forward(&mut self.cmd);
+            let cmd = mem::replace(&mut self.cmd, Command::new(""));
+            self.cmd = backend.command(cmd)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the options that configure the spawned process to the
+    /// command, in the order that they depend on each other in.
+    pub(crate) fn prepare(&mut self) -> Result<Prepared, io::Error> {
+        self.apply_backend()?;
+        let cmd = &mut self.cmd;
+        self.env.apply(cmd);
+        #[cfg(target_os = "linux")]
+        let cgroup = {
+            let cgroup = crate::cgroup::place(self.cgroup.take(), cmd)?;
+            sys::unshare(cmd, &self.namespaces, self.root.as_deref())?;
+            // A new PID namespace adds processes above the child, which pass
+            // on the signals sent to their group.
+            self.process_group |= self.namespaces.contains(&Namespace::Pid);
+            cgroup
+        };
+        sys::set_root(cmd, self.root.take())?;
+        // Privileges are dropped last, so that the limits and scheduling are
+        // set while they are still held.
+        sys::set_umask(cmd, self.umask);
+        sys::set_rlimits(cmd, &self.rlimits);
+        sys::set_scheduling(cmd, mem::take(&mut self.scheduling));
+        #[cfg(target_os = "linux")]
+        sys::set_oom_score_adj(cmd, self.oom_score_adj);
+        sys::set_credentials(cmd, mem::take(&mut self.credentials));
+
+        Ok(Prepared {
+            #[cfg(target_os = "linux")]
+            cgroup,
+        })
+    }
+
+    /// Returns the first option that is set which needs something to run in
+    /// the child before `exec`, ruling out
+    /// [`posix_spawn`](Self::posix_spawn).
+    #[cfg(unix)]
+    pub(crate) fn posix_spawn_conflict(&self) -> Option<&'static str> {
+        #[allow(unused_mut)]
+        let mut options = vec![
+            (self.process_backend.is_some(), "process_backend"),
+            (self.pty, "pty"),
+            (self.root.is_some(), "chroot"),
+            (self.umask.is_some(), "umask"),
+            (!self.rlimits.is_empty(), "rlimit"),
+            (self.scheduling.nice.is_some(), "nice"),
+            (self.scheduling.policy.is_some(), "scheduling_policy"),
+            (self.scheduling.cpus.is_some(), "cpu_affinity"),
+            (self.credentials.uid.is_some(), "uid"),
+            (self.credentials.gid.is_some(), "gid"),
+            (self.credentials.groups.is_some(), "groups"),
+        ];
+        #[cfg(target_os = "linux")]
+        options.extend([
+            (self.cgroup.is_some(), "cgroup"),
+            (!self.namespaces.is_empty(), "unshare"),
+            (self.seccomp.is_some(), "seccomp"),
+            (self.oom_score_adj.is_some(), "oom_score_adj"),
+        ]);
+        #[cfg(not(target_os = "linux"))]
+        options.push((self.new_session, "new_session"));
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        options.push((self.cmd.get_current_dir().is_some(), "current_dir"));
+
+        options
+            .into_iter()
+            .find_map(|(set, option)| set.then_some(option))
+    }
+
+    /// Sets the working directory of the child.
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.cmd.current_dir(dir);
+        self
+    }
+
+    /// Sets the umask of the child, the permission bits that are cleared on
+    /// the files and directories it creates, such as `0o022`. By default, it
+    /// inherits the parent's.
+    #[cfg(unix)]
+    pub fn umask(mut self, mask: u32) -> Self {
+        self.umask = Some(mask);
+        self
+    }
+
+    /// Confines the child to the directory tree under `root`, by making it
+    /// the child's root directory with `chroot` before it drops any
+    /// privileges. This requires `CAP_SYS_CHROOT`.
+    ///
+    /// The program is looked up inside of `root`, and so is the
+    /// [`current_dir`](Self::current_dir), which otherwise defaults to
+    /// `root` itself. With new PID and mount namespaces, the fresh `/proc`
+    /// is mounted under `root`.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::ProcessReader;
+    ///
+    /// let reader = ProcessReader::builder(Command::new("/bin/build"))
+    ///     .chroot("/srv/jobs/rootfs")
+    ///     .current_dir("/src")
+    ///     .uid(1000)
+    ///     .start()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(unix)]
+    pub fn chroot(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Sets the user ID the child runs as, which requires the privileges to
+    /// change it.
+    ///
+    /// When running as root without [`groups`](Self::groups), the child's
+    /// supplementary groups are cleared.
+    #[cfg(unix)]
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.credentials.uid = Some(uid);
+        self
+    }
+
+    /// Sets the group ID the child runs as, like [`uid`](Self::uid).
+    #[cfg(unix)]
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.credentials.gid = Some(gid);
+        self
+    }
+
+    /// Sets the supplementary groups of the child, which requires the
+    /// privileges to change them.
+    ///
+    /// They are set before the group and user ID, so that dropping
+    /// privileges with [`uid`](Self::uid) still leaves the child with these
+    /// groups.
+    #[cfg(unix)]
+    pub fn groups(mut self, groups: impl Into<Vec<u32>>) -> Self {
+        self.credentials.groups = Some(groups.into());
+        self
+    }
+
+    /// Limits how much of `resource` the child may use, setting both its soft
+    /// and hard limit with `setrlimit` before it runs the program. Setting
+    /// the same resource again replaces its limit.
+    ///
+    /// The hard limit for [`Resource::Cpu`] is a second more, so that the
+    /// child is sent `SIGXCPU` first. Exceeding the CPU time or file size
+    /// limit is reported as [`Out::LimitExceeded`].
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::{ProcessReader, Resource};
+    ///
+    /// let reader = ProcessReader::builder(Command::new("./submission"))
+    ///     .rlimit(Resource::Cpu, 10)
+    ///     .rlimit(Resource::AddressSpace, 512 * 1024 * 1024)
+    ///     .rlimit(Resource::OpenFiles, 64)
+    ///     .start()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(unix)]
+    pub fn rlimit(mut self, resource: Resource, limit: u64) -> Self {
+        self.rlimits.retain(|&(limited, _)| limited != resource);
+        self.rlimits.push((resource, limit));
+        self
+    }
+
+    /// Sets the nice value of the child, from -20 for the highest priority
+    /// to 19 for the lowest. Lowering it below the parent's requires
+    /// privileges.
+    #[cfg(unix)]
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.scheduling.nice = Some(nice);
+        self
+    }
+
+    /// Sets the scheduling policy of the child, such as
+    /// [`SchedulingPolicy::Idle`] for batch work that should not compete with
+    /// the parent for the CPU.
+    #[cfg(target_os = "linux")]
+    pub fn scheduling_policy(mut self, policy: SchedulingPolicy) -> Self {
+        self.scheduling.policy = Some(policy);
+        self
+    }
+
+    /// Restricts the child to run on the listed CPUs, numbered from zero.
+    ///
+    /// Spawning fails if none of them are available.
+    #[cfg(target_os = "linux")]
+    pub fn cpu_affinity(mut self, cpus: impl IntoIterator<Item = usize>) -> Self {
+        self.scheduling.cpus = Some(cpus.into_iter().collect());
+        self
+    }
+
+    /// Sets the `oom_score_adj` of the child, from -1000 to 1000, which its
+    /// descendants inherit. The higher it is, the sooner the kernel's OOM
+    /// killer picks the child once memory runs out, so that a
+    /// memory-hungry child is killed before the parent. Lowering it below
+    /// the parent's requires `CAP_SYS_RESOURCE`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `adj` is out of range.
+    #[cfg(target_os = "linux")]
+    pub fn oom_score_adj(mut self, adj: i32) -> Self {
+        assert!(
+            (-1000..=1000).contains(&adj),
+            "oom_score_adj must be between -1000 and 1000"
+        );
+        self.oom_score_adj = Some(adj);
+        self
+    }
+
+    /// Places the child and everything it spawns in a new cgroup under
+    /// `parent`, a directory of a cgroup v2 hierarchy in which this process
+    /// may create cgroups, such as a subtree delegated to it.
+    ///
+    /// The cgroup is available from [`ProcessReader::cgroup`] to account for
+    /// and kill the whole process tree. Spawning fails if it cannot be
+    /// created or entered.
+    #[cfg(target_os = "linux")]
+    pub fn cgroup(mut self, parent: impl Into<PathBuf>) -> Self {
+        self.cgroup = Some(parent.into());
+        self
+    }
+
+    /// Moves the child into a new `namespace`, to run it lightly sandboxed.
+    /// This usually requires `CAP_SYS_ADMIN`, and spawning fails without it.
+    ///
+    /// Mounts made in a new [`Namespace::Mount`] do not propagate back. The
+    /// loopback interface of a new [`Namespace::Network`] is brought up.
+    ///
+    /// In a new [`Namespace::Pid`], the child is not the process that was
+    /// spawned. That process forks the first process of the namespace, which
+    /// forks the child and, as its init process, reaps any orphans. Both
+    /// pass on the signals they receive, such as those from
+    /// [`signal`](ProcessReader::signal), to the child and its descendants,
+    /// and exit the way the child did. Everything left in the namespace is
+    /// killed once the child exits. This implies
+    /// [`process_group`](Self::process_group), and along with a new mount
+    /// namespace, a fresh `/proc` is mounted for the namespace.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::{Namespace, ProcessReader};
+    ///
+    /// let reader = ProcessReader::builder(Command::new("./job"))
+    ///     .unshare(Namespace::Pid)
+    ///     .unshare(Namespace::Mount)
+    ///     .unshare(Namespace::Network)
+    ///     .start()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn unshare(mut self, namespace: Namespace) -> Self {
+        if !self.namespaces.contains(&namespace) {
+            self.namespaces.push(namespace);
+        }
+        self
+    }
+
+    /// Installs a seccomp `filter` in the child, as the last step before it
+    /// runs the program, restricting the system calls that it and its
+    /// descendants can make.
+    ///
+    /// The filter must allow `execve`, and with a [`pty`](Self::pty),
+    /// `setsid` and `ioctl`. The child is also made unable to gain
+    /// privileges, so setuid programs it runs keep its user.
+    #[cfg(target_os = "linux")]
+    pub fn seccomp(mut self, filter: SeccompFilter) -> Self {
+        self.seccomp = Some(filter);
+        self
+    }
+
+    /// Sets the size of the buffer used for each read from the pipes.
+    /// Defaults to 64 KiB.
+    ///
+    /// Whenever a read fills the buffer, it is doubled for the following
+    /// reads, up to 1 MiB, so that a child that writes a lot of output is
+    /// read with fewer system calls.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "buffer size must be non-zero");
+        self.buffer_size = size;
+        self
+    }
+
+    /// Sets how output is framed. Defaults to [`Mode::Lines`].
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the byte sequence that separates records on both stdout and
+    /// stderr in [`Mode::Lines`]. Defaults to `\n`.
+    ///
+    /// Carriage returns are only dropped with the default delimiter. With
+    /// `\0`, for example, the output of `find -print0` can be read one path
+    /// per item.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delimiter` is empty.
+    pub fn delimiter(self, delimiter: impl Into<Vec<u8>>) -> Self {
+        let delimiter = delimiter.into();
+        self.stdout_delimiter(delimiter.clone())
+            .stderr_delimiter(delimiter)
+    }
+
+    /// Sets the record delimiter for stdout only. See
+    /// [`delimiter`](Self::delimiter).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delimiter` is empty.
+    pub fn stdout_delimiter(mut self, delimiter: impl Into<Vec<u8>>) -> Self {
+        self.stdout.delimiter = non_empty_delimiter(delimiter.into());
+        self
+    }
+
+    /// Sets the record delimiter for stderr only. See
+    /// [`delimiter`](Self::delimiter).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delimiter` is empty.
+    pub fn stderr_delimiter(mut self, delimiter: impl Into<Vec<u8>>) -> Self {
+        self.stderr.delimiter = non_empty_delimiter(delimiter.into());
+        self
+    }
+
+    /// Sets the maximum length of a line in bytes, not counting its
+    /// delimiter, and what happens to longer lines. Defaults to no limit.
+    ///
+    /// This keeps memory bounded when the child writes a lot of output
+    /// without a delimiter. It applies to every mode that splits output into
+    /// lines.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn max_line_length(mut self, max: usize, overflow: LineOverflow) -> Self {
+        assert!(max > 0, "maximum line length must be non-zero");
+        self.max_line_length = Some((max, overflow));
+        self
+    }
+
+    /// Sets the encoding that the child writes its output in, such as
+    /// `encoding_rs::SHIFT_JIS`. Defaults to UTF-8.
+    ///
+    /// Lines are transcoded to UTF-8, replacing anything invalid with
+    /// U+FFFD. Delimiters are still matched as bytes, so only encodings that
+    /// are compatible with ASCII are supported.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `encoding` is not compatible with ASCII, such as UTF-16.
+    #[cfg(feature = "encoding")]
+    pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+        assert!(
+            encoding.is_ascii_compatible(),
+            "encoding must be compatible with ASCII"
+        );
+        self.decoder.set_encoding(encoding);
+        self
+    }
+
+    /// Uses the encoding named by the current locale, as set in `LC_ALL`,
+    /// `LC_CTYPE` or `LANG`. See [`encoding`](Self::encoding).
+    ///
+    /// The default of UTF-8 is kept if the locale does not name an
+    /// encoding, or names one that is unknown or not compatible with ASCII.
+    #[cfg(feature = "encoding")]
+    pub fn locale_encoding(self) -> Self {
+        match crate::decode::locale_encoding() {
+            Some(encoding) => self.encoding(encoding),
+            None => self,
+        }
+    }
+
+    /// Sets whether ANSI escape sequences, such as colors, cursor movement
+    /// and window titles, are removed from lines on both stdout and stderr.
+    /// Defaults to `false`.
+    ///
+    /// This applies to every mode except [`Mode::RawLines`] and
+    /// [`Mode::Bytes`]. In [`Mode::Raw`], a sequence that is split across
+    /// reads is not recognized.
+    pub fn strip_ansi(self, strip: bool) -> Self {
+        self.stdout_strip_ansi(strip).stderr_strip_ansi(strip)
+    }
+
+    /// Sets whether ANSI escape sequences are removed from stdout only. See
+    /// [`strip_ansi`](Self::strip_ansi).
+    pub fn stdout_strip_ansi(mut self, strip: bool) -> Self {
+        self.stdout.strip_ansi = strip;
+        self
+    }
+
+    /// Sets whether ANSI escape sequences are removed from stderr only. See
+    /// [`strip_ansi`](Self::strip_ansi).
+    pub fn stderr_strip_ansi(mut self, strip: bool) -> Self {
+        self.stderr.strip_ansi = strip;
+        self
+    }
+
+    /// Enables strict decoding, where `invalid` decides what happens to
+    /// output that is not valid UTF-8, or not valid in the configured
+    /// [`encoding`](Self::encoding). Defaults to replacing invalid bytes with
+    /// U+FFFD without strict mode.
+    ///
+    /// In [`Mode::Raw`], strict mode also holds back a UTF-8 sequence that is
+    /// cut off at the end of a read until the rest of it has been read, so
+    /// that characters are never split across items.
+    pub fn strict_utf8(mut self, invalid: InvalidUtf8) -> Self {
+        self.decoder.set_strict(invalid);
+        self
+    }
+
+    /// Takes the buffers that lines are stored in from `pool`, so that lines
+    /// given back to it are reused instead of allocating new ones. Defaults
+    /// to allocating every line.
+    ///
+    /// Only lines that are valid UTF-8 reuse buffers, and only in
+    /// [`Mode::Lines`] and [`Mode::Progress`] without an
+    /// [`encoding`](Self::encoding).
+    pub fn line_pool(mut self, pool: LinePool) -> Self {
+        self.line_pool = Some(pool);
+        self
+    }
+
+    /// Registers a pattern that stdout lines are matched against, emitting
+    /// [`Out::Match`](crate::Out::Match) for every line it matches.
+    ///
+    /// Patterns are identified by the order in which they are registered,
+    /// across both streams and starting at zero. Only complete lines in
+    /// [`Mode::Lines`] and [`Mode::Progress`] are matched, and every
+    /// matching pattern emits its own item. With
+    /// [`merge_stderr`](Self::merge_stderr), stderr lines are matched against
+    /// the stdout patterns.
+    #[cfg(feature = "regex")]
+    pub fn stdout_pattern(mut self, pattern: Regex, on_match: OnMatch) -> Self {
+        let id = self.stdout.patterns.len() + self.stderr.patterns.len();
+        self.stdout.patterns.push(id, pattern, on_match);
+        self
+    }
+
+    /// Registers a pattern that stderr lines are matched against. See
+    /// [`stdout_pattern`](Self::stdout_pattern).
+    #[cfg(feature = "regex")]
+    pub fn stderr_pattern(mut self, pattern: Regex, on_match: OnMatch) -> Self {
+        let id = self.stdout.patterns.len() + self.stderr.patterns.len();
+        self.stderr.patterns.push(id, pattern, on_match);
+        self
+    }
+
+    /// Copies everything read from stdout to the file at `path`, which is
+    /// created or truncated when the child is spawned.
+    ///
+    /// Output is written as soon as it is read, exactly as the child wrote
+    /// it, so the file is complete even if items are never consumed. Failed
+    /// writes are yielded as errors, and reading continues.
+    pub fn tee_stdout(mut self, path: impl Into<PathBuf>) -> Self {
+        self.tee_stdout = Some(path.into());
+        self
+    }
+
+    /// Copies everything read from stderr to the file at `path`. See
+    /// [`tee_stdout`](Self::tee_stdout).
+    pub fn tee_stderr(mut self, path: impl Into<PathBuf>) -> Self {
+        self.tee_stderr = Some(path.into());
+        self
+    }
+
+    /// Copies everything read from both stdout and stderr to a single file
+    /// at `path`, interleaved in the order it was read. See
+    /// [`tee_stdout`](Self::tee_stdout).
+    pub fn tee(self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.tee_stdout(path.clone()).tee_stderr(path)
+    }
+
+    /// Rotates the files written by [`tee`](Self::tee),
+    /// [`tee_stdout`](Self::tee_stdout) and [`tee_stderr`](Self::tee_stderr)
+    /// as configured by `rotation`. Defaults to never rotating them.
+    pub fn tee_rotation(mut self, rotation: Rotation) -> Self {
+        self.tee_rotation = Some(rotation);
+        self
+    }
+
+    /// Sets whether the output of streams that are written to a file with
+    /// [`tee`](Self::tee), [`tee_stdout`](Self::tee_stdout) or
+    /// [`tee_stderr`](Self::tee_stderr) is only written there, and not
+    /// yielded. Defaults to `false`.
+    ///
+    /// The reader still counts the output in [`stats`](ProcessReader::stats)
+    /// and for the idle timeout. On Linux, output is moved from the pipe to
+    /// the file with `splice`, without copying it through the reader's
+    /// memory.
+    pub fn tee_only(mut self, enable: bool) -> Self {
+        self.tee_only = enable;
+        self
+    }
+
+    /// Sets whether stdout is captured. Defaults to `true`.
+    ///
+    /// When disabled, stdout is left as configured on the [`Command`], e.g. to
+    /// send it to a file, and [`merge_stderr`](Self::merge_stderr) has no
+    /// effect.
+    pub fn capture_stdout(mut self, capture: bool) -> Self {
+        self.capture_stdout = capture;
+        self
+    }
+
+    /// Sets whether stderr is captured. Defaults to `true`.
+    ///
+    /// When disabled, stderr is left as configured on the [`Command`].
+    pub fn capture_stderr(mut self, capture: bool) -> Self {
+        self.capture_stderr = capture;
+        self
+    }
+
+    /// Captures what the child writes to the file descriptor `fd`, as used
+    /// by tools with options like `--status-fd`, by connecting it to a pipe.
+    /// Its output is split into lines like stdout, with the same
+    /// [`delimiter`](Self::delimiter),
+    /// [`max_line_length`](Self::max_line_length) and decoding, which are
+    /// yielded as [`Out::Fd`](crate::Out::Fd). The modes that yield bytes
+    /// split it like [`Mode::Lines`], and the in-place updates of
+    /// [`Mode::Progress`] are not yielded.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::{Out, ProcessReader};
+    ///
+    /// let mut cmd = Command::new("gpg");
+    /// cmd.args(["--status-fd", "3", "--verify", "release.sig"]);
+    /// for out in ProcessReader::builder(cmd).capture_fd(3).start()? {
+    ///     if let Out::Fd { line, .. } = out? {
+    ///         println!("status: {line}");
+    ///     }
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fd` is one of the standard streams.
+    #[cfg(unix)]
+    pub fn capture_fd(mut self, fd: i32) -> Self {
+        assert!(
+            fd > 2,
+            "the standard streams cannot be captured with capture_fd"
+        );
+        if !self.capture_fds.contains(&fd) {
+            self.capture_fds.push(fd);
+        }
+        self
+    }
+
+    /// Reads `fd`, such as a socket or the read end of a pipe, on the same
+    /// poll loop as the child's output, and yields its lines as
+    /// [`Out::Source`](crate::Out::Source) under `name`, interleaved with
+    /// the child's output in the order in which they were read. Lines are
+    /// split like those of [`capture_fd`](Self::capture_fd).
+    ///
+    /// The file descriptor is switched to non-blocking mode, and is read
+    /// until it is closed or the child exits. Regular files cannot be
+    /// polled, so use [`follow_file`](Self::follow_file) for them.
+    ///
+    /// ```no_run
+    /// use std::{os::unix::net::UnixDatagram, process::Command};
+    /// use incremental_command::{Out, ProcessReader};
+    ///
+    /// let control = UnixDatagram::bind("/tmp/control.sock")?;
+    /// let reader = ProcessReader::builder(Command::new("./server"))
+    ///     .source("control", control)
+    ///     .start()?;
+    /// for out in reader {
+    ///     match out? {
+    ///         Out::Stdout(line) => println!("{line}"),
+    ///         Out::Source { line, .. } => println!("control: {line}"),
+    ///         _ => {}
+    ///     }
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(unix)]
+    pub fn source(mut self, name: impl Into<Arc<str>>, fd: impl Into<OwnedFd>) -> Self {
+        self.sources.push((name.into(), fd.into()));
+        self
+    }
+
+    /// Follows the file at `path` while the child runs, like `tail -F`, and
+    /// yields the lines appended to it as
+    /// [`Out::Followed`](crate::Out::Followed) under `name`, for programs
+    /// that write their logs to a file rather than to stdout. Lines are
+    /// split like those of [`capture_fd`](Self::capture_fd).
+    ///
+    /// The file is followed from where it ends when the child is spawned, or
+    /// from its start if it is only created later. On Linux, it is checked
+    /// for new lines whenever inotify reports a change in its directory, and
+    /// elsewhere, or if the directory cannot be watched, every 100ms. It is
+    /// checked once more after the child has exited. A file that is
+    /// truncated or replaced, such as by log rotation, is followed from its
+    /// start again. A relative `path` is resolved against the
+    /// current directory of this process, not that of the child.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::{Out, ProcessReader};
+    ///
+    /// let reader = ProcessReader::builder(Command::new("./server"))
+    ///     .follow_file("log", "server.log")
+    ///     .start()?;
+    /// for out in reader {
+    ///     if let Out::Followed { line, .. } = out? {
+    ///         println!("log: {line}");
+    ///     }
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn follow_file(mut self, name: impl Into<Arc<str>>, path: impl Into<PathBuf>) -> Self {
+        self.follow.push((name.into(), path.into()));
+        self
+    }
+
+    /// Sets whether stderr is redirected into stdout, like `2>&1` in a shell.
+    /// Defaults to `false`.
+    ///
+    /// Both streams then share a single pipe, so their output is yielded as
+    /// [`Out::Stdout`](crate::Out::Stdout) in exactly the order the child wrote
+    /// it. This takes precedence over
+    /// [`capture_stderr`](Self::capture_stderr).
+    pub fn merge_stderr(mut self, merge: bool) -> Self {
+        self.merge_stderr = merge;
+        self
+    }
+
+    /// Sets whether the child's stdin is connected to a pipe that can be
+    /// written through [`ProcessReader::stdin`]. Defaults to `false`.
+    ///
+    /// When disabled, stdin is left as configured on the [`Command`].
+    pub fn pipe_stdin(mut self, pipe: bool) -> Self {
+        self.pipe_stdin = pipe;
+        self
+    }
+
+    /// Sets whether the child's stdin and stdout are connected to a
+    /// pseudo-terminal instead of pipes. Defaults to `false`.
+    ///
+    /// Many programs only line-buffer their output, or print colors and
+    /// progress output, when attached to a terminal. The child becomes the
+    /// leader of a new session with the pseudo-terminal as its controlling
+    /// terminal, so it is also placed in its own process group. Stdin can
+    /// always be written through [`ProcessReader::stdin`]; note that the
+    /// terminal echoes input back as output.
+    ///
+    /// Terminals translate `\n` to `\r\n`, so [`Mode::Raw`] output will contain
+    /// carriage returns.
+    #[cfg(unix)]
+    pub fn pty(mut self, enable: bool) -> Self {
+        self.pty = enable;
+        self
+    }
+
+    /// Sets whether the child is spawned as the leader of a new process
+    /// group. Defaults to `false`.
+    ///
+    /// When enabled, every signal the reader sends on its own (timeouts,
+    /// [`shutdown`](ProcessReader::shutdown), [`DropPolicy::Kill`]) is sent to
+    /// the whole group, so subprocesses of the child are terminated as well.
+    #[cfg(unix)]
+    pub fn process_group(mut self, enable: bool) -> Self {
+        self.process_group = enable;
+        self
+    }
+
+    /// Sets whether the child is spawned in a new session with `setsid`,
+    /// detached from the parent's controlling terminal. Defaults to `false`.
+    ///
+    /// The child then no longer receives the signals that the terminal
+    /// sends, such as `SIGINT` on Ctrl-C, and cannot read from it. As the
+    /// session leader, it also leads a new process group, so this implies
+    /// [`process_group`](Self::process_group). A [`pty`](Self::pty) always
+    /// starts a new session.
+    #[cfg(unix)]
+    pub fn new_session(mut self, enable: bool) -> Self {
+        self.new_session = enable;
+        self
+    }
+
+    /// Sets whether the child is spawned with `posix_spawn` instead of being
+    /// forked, with its pipes and [captured](Self::capture_fd) file
+    /// descriptors handed over as `dup2` file actions. Defaults to `false`.
+    ///
+    /// Forking copies the page tables of the parent, which gets slow once it
+    /// uses a lot of memory, while `posix_spawn` lets the C library avoid
+    /// that. Only the program, arguments, environment and working directory
+    /// of the [`Command`] are used, so its stdio and anything set through
+    /// [`CommandExt`](std::os::unix::process::CommandExt) are ignored, and
+    /// streams that are not captured are inherited. The environment is
+    /// resolved like [`env_clear`](Self::env_clear) describes, which does
+    /// not see [`Command::env_clear`].
+    ///
+    /// Since nothing can run in the child before `exec`, starting the reader
+    /// fails with [`ErrorKind::Unsupported`](io::ErrorKind::Unsupported) if
+    /// [`pty`](Self::pty), [`process_backend`](Self::process_backend),
+    /// [`chroot`](Self::chroot), [`umask`](Self::umask),
+    /// [`rlimit`](Self::rlimit), [`nice`](Self::nice),
+    /// [`scheduling_policy`](Self::scheduling_policy),
+    /// [`cpu_affinity`](Self::cpu_affinity), [`uid`](Self::uid),
+    /// [`gid`](Self::gid) or [`groups`](Self::groups) is set, as well as on
+    /// Linux the cgroup, namespace, seccomp and OOM score options. Elsewhere,
+    /// so does [`new_session`](Self::new_session), and on platforms other
+    /// than Linux and macOS, a working directory.
+    #[cfg(unix)]
+    pub fn posix_spawn(mut self, enable: bool) -> Self {
+        self.posix_spawn = enable;
+        self
+    }
+
+    /// Sets what happens to the child when the reader is dropped before it
+    /// has exited. Defaults to [`DropPolicy::Detach`].
+    pub fn on_drop(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Sets whether every [`Event`](crate::Event) also records the wall-clock
+    /// time in addition to the monotonic instant. Defaults to `false`.
+    pub fn wall_clock(mut self, enable: bool) -> Self {
+        self.wall_clock = enable;
+        self
+    }
+
+    /// Sets whether the CPU time, peak memory and wall-clock duration of the
+    /// child are reported in [`Out::ResourceUsage`](crate::Out::ResourceUsage)
+    /// once it has exited. Defaults to `false`.
+    ///
+    /// This is not supported with a
+    /// [`process_backend`](Self::process_backend), which reaps the child.
+    pub fn resource_usage(mut self, enable: bool) -> Self {
+        self.resource_usage = enable;
+        self
+    }
+
+    /// Sets whether the core file of a child that dumped core is looked up
+    /// and reported in [`Out::CoreDumped`](crate::Out::CoreDumped). Defaults
+    /// to `false`.
+    ///
+    /// This is only supported on Linux, where the location is derived from
+    /// `/proc/sys/kernel/core_pattern`. Cores handed to a program such as
+    /// `systemd-coredump` cannot be located, and neither can cores named
+    /// after anything but the child's PID, signal, user, group, executable
+    /// name or host name.
+    pub fn locate_core_dumps(mut self, enable: bool) -> Self {
+        self.locate_core_dumps = enable;
+        self
+    }
+
+    /// Makes the reader yield [`Error::NonZeroExit`](crate::Error::NonZeroExit)
+    /// in place of [`Out::Done`](crate::Out::Done) if the child does not exit
+    /// successfully, with the last `stderr_tail` lines of stderr attached.
+    /// Defaults to yielding the exit status either way.
+    ///
+    /// Only lines yielded as [`Out::Stderr`](crate::Out::Stderr) are kept,
+    /// so nothing is attached in modes that yield bytes or with
+    /// [`merge_stderr`](Self::merge_stderr).
+    pub fn check(mut self, stderr_tail: usize) -> Self {
+        self.check = Some(stderr_tail);
+        self
+    }
+
+    /// Calls `hook` with the PID of the child right after it has been
+    /// spawned, before any of its output is read.
+    pub fn on_spawn(mut self, hook: impl FnOnce(u32) + Send + 'static) -> Self {
+        self.hooks.spawn = Some(Hook(Box::new(hook)));
+        self
+    }
+
+    /// Calls `hook` with every [`Out::Stdout`](crate::Out::Stdout) and
+    /// [`Out::Stderr`](crate::Out::Stderr) line as soon as it has been read,
+    /// before it is queued to be yielded. Lines that a
+    /// [`queue_capacity`](Self::queue_capacity) policy drops are included.
+    pub fn on_line(mut self, hook: impl FnMut(&Out) + Send + 'static) -> Self {
+        let mut hook = hook;
+        let on_output = move |out: &Out| {
+            if let Out::Stdout(_) | Out::Stderr(_) = out {
+                hook(out);
+            }
+        };
+        self.hooks.output = Some(Hook(Box::new(on_output)));
+        self
+    }
+
+    /// Calls `hook` with the exit status of the child once it has been
+    /// reaped, before [`Out::Done`](crate::Out::Done) is yielded.
+    pub fn on_exit(mut self, hook: impl FnOnce(ExitStatus) + Send + 'static) -> Self {
+        self.hooks.exit = Some(Hook(Box::new(hook)));
+        self
+    }
+
+    /// Sets how many items may wait to be yielded before `policy` applies.
+    /// Defaults to no limit.
+    ///
+    /// The reader only reads from the child while its queue is empty, so a
+    /// slow consumer already holds back the child. The capacity bounds how
+    /// much a chatty child can queue up in one go while its pipes are drained.
+    ///
+    /// Only output counts towards the limit and can be dropped, including the
+    /// lines of [captured file descriptors](Self::capture_fd),
+    /// [sources](Self::source) and [followed files](Self::follow_file); items such as
+    /// [`Out::Done`](crate::Out::Done) are always yielded. A single read may
+    /// still produce more items than fit, which are queued regardless with
+    /// [`QueuePolicy::Backpressure`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn queue_capacity(mut self, capacity: usize, policy: QueuePolicy) -> Self {
+        assert!(capacity > 0, "queue capacity must be non-zero");
+        self.queue_capacity = Some((capacity, policy));
+        self
+    }
+
+    /// Yields only a sample of the output of each stream, as picked by
+    /// `sampling`. Defaults to yielding everything.
+    ///
+    /// Sampling happens before [`output_limit`](Self::output_limit) and
+    /// [`rate_limit`](Self::rate_limit) apply. Items that are sampled out are
+    /// not reported, but still counted in
+    /// [`Stats::stdout_lines`](crate::Stats::stdout_lines) and
+    /// [`Stats::stderr_lines`](crate::Stats::stderr_lines), and the
+    /// [`on_line`](Self::on_line) hook still sees every line. Only stdout
+    /// and stderr are sampled, while the lines of
+    /// [captured file descriptors](Self::capture_fd),
+    /// [sources](Self::source) and [followed files](Self::follow_file) are always yielded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sampling` is [`Sampling::Nth`] with zero.
+    pub fn sample(mut self, sampling: Sampling) -> Self {
+        assert!(
+            sampling != Sampling::Nth(0),
+            "sampling interval must be non-zero"
+        );
+        self.sampling = Some(sampling);
+        self
+    }
+
+    /// Sets how much output of each stream is yielded at most, and which
+    /// part of it `truncation` drops beyond that. Defaults to no limit.
+    ///
+    /// Once anything has been dropped from a stream,
+    /// [`Out::StdoutElided`](crate::Out::StdoutElided) or
+    /// [`Out::StderrElided`](crate::Out::StderrElided) reports how much right
+    /// before [`Out::Done`](crate::Out::Done), so that output is never lost
+    /// silently. The [`on_line`](Self::on_line) hook and the stderr tail kept
+    /// by [`check`](Self::check) still see every line. Only stdout and stderr
+    /// are limited, while the lines of
+    /// [captured file descriptors](Self::capture_fd),
+    /// [sources](Self::source) and [followed files](Self::follow_file) are always yielded.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::{OutputLimit, ProcessReader, Truncation};
+    ///
+    /// let reader = ProcessReader::builder(Command::new("make"))
+    ///     .output_limit(OutputLimit::Lines(1000), Truncation::Middle)
+    ///     .start()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn output_limit(mut self, limit: OutputLimit, truncation: Truncation) -> Self {
+        self.output_limit = Some((limit, truncation));
+        self
+    }
+
+    /// Sets how fast output of each stream is yielded at most, and what
+    /// `policy` does with output beyond that. Defaults to no limit.
+    ///
+    /// Up to a second's worth of output is yielded in a burst. Items that
+    /// were suppressed are counted in
+    /// [`Out::StdoutSuppressed`](crate::Out::StdoutSuppressed) or
+    /// [`Out::StderrSuppressed`](crate::Out::StderrSuppressed), which is
+    /// yielded before the next item of the stream once the rate allows it
+    /// again, or before [`Out::Done`](crate::Out::Done). The
+    /// [`on_line`](Self::on_line) hook and the stderr tail kept by
+    /// [`check`](Self::check) still see every line. Only stdout and stderr
+    /// are limited, while the lines of
+    /// [captured file descriptors](Self::capture_fd),
+    /// [sources](Self::source) and [followed files](Self::follow_file) are always yielded.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::{ProcessReader, RateLimit, RatePolicy};
+    ///
+    /// let reader = ProcessReader::builder(Command::new("yes"))
+    ///     .rate_limit(RateLimit::Lines(100), RatePolicy::Coalesce)
+    ///     .start()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the rate is zero.
+    pub fn rate_limit(mut self, limit: RateLimit, policy: RatePolicy) -> Self {
+        let rate = match limit {
+            RateLimit::Lines(rate) => rate as u64,
+            RateLimit::Bytes(rate) => rate,
+        };
+        assert!(rate > 0, "rate limit must be non-zero");
+        self.rate_limit = Some((limit, policy));
+        self
+    }
+
+    /// Sets the maximum time a single poll waits for output before checking
+    /// whether the child has exited. Defaults to waiting indefinitely.
+    ///
+    /// On Linux the child's exit always wakes up the reader. Elsewhere, exit
+    /// is only noticed on output or once both streams are closed, so this is
+    /// useful if subprocesses of the child keep its streams open.
+    pub fn poll_timeout(mut self, timeout: Duration) -> Self {
+        self.poll_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum time the child may run. Once it is exceeded the child
+    /// is killed and [`Out::TimedOut`](crate::Out::TimedOut) is emitted before
+    /// the final exit status.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long the child may go without writing to stdout, stderr or
+    /// a [captured](Self::capture_fd) file descriptor before `action` is
+    /// taken. Lines read from sources and followed files do not count.
+    pub fn idle_timeout(mut self, timeout: Duration, action: IdleAction) -> Self {
+        self.idle_timeout = Some((timeout, action));
+        self
+    }
+
+    /// Spawns the child with the configured options.
+    pub fn start(self) -> Result<ProcessReader, io::Error> {
+        ProcessReader::from_builder(self)
+    }
+
+    /// Reads the output of `child`, which was spawned elsewhere, instead of
+    /// spawning the command, as configured by this builder.
+    ///
+    /// Only the options for reading the output and handling the child apply,
+    /// not those that configure how it is spawned, such as
+    /// [`env`](Self::env), [`pty`](Self::pty) or
+    /// [`capture_stdout`](Self::capture_stdout). Which of its streams are
+    /// read depends on which were set to
+    /// [`Stdio::piped`](std::process::Stdio::piped), and signals are sent
+    /// to its process group if it leads one. The command only describes the
+    /// child, such as for tracing.
+    ///
+    /// See [`ProcessReader::from_child`].
+    pub fn adopt(self, child: Child) -> Result<ProcessReader, io::Error> {
+        ProcessReader::from_child_with(self, child)
+    }
+
+    /// Reads the output that the running process `pid`, which was not
+    /// spawned by this one, writes from now on, instead of spawning the
+    /// command, as configured by this builder.
+    ///
+    /// Only the options that shape the items apply, such as the
+    /// [`mode`](Self::mode), patterns and tees, not those that concern the
+    /// child or how it is spawned. See [`AttachedReader`] for what can be
+    /// read.
+    #[cfg(target_os = "linux")]
+    pub fn attach(self, pid: u32) -> Result<AttachedReader, io::Error> {
+        AttachedReader::new(self, pid)
+    }
+
+    /// Spawns the command fully detached from this process, keeping the job
+    /// in `dir` instead of reading its output, so that it keeps running
+    /// after this process exits.
+    ///
+    /// The command runs in a new session, without a controlling terminal,
+    /// and as a grandchild of this process that is left to init. Its stdin
+    /// is connected to `/dev/null`, and its stdout and stderr are written to
+    /// files in `dir`, which is created if needed and must not hold another
+    /// job. A fork of this process stays behind to record its exit status
+    /// there.
+    ///
+    /// Options that configure the spawned process apply, such as
+    /// [`env`](Self::env), [`uid`](Self::uid), [`rlimit`](Self::rlimit) or
+    /// [`seccomp`](Self::seccomp). Those for reading the output apply to
+    /// [`reattach`](Self::reattach). See [`DetachedJob`].
+    ///
+    /// This fails with [`io::ErrorKind::Unsupported`] if an option is set
+    /// that cannot apply to a job that outlives this process:
+    /// [`cgroup`](Self::cgroup), [`unshare`](Self::unshare),
+    /// [`process_backend`](Self::process_backend), [`pty`](Self::pty),
+    /// [`pipe_stdin`](Self::pipe_stdin),
+    /// [`merge_stderr`](Self::merge_stderr),
+    /// [`capture_fd`](Self::capture_fd), [`source`](Self::source),
+    /// [`follow_file`](Self::follow_file),
+    /// [`process_group`](Self::process_group), since the job leads a
+    /// session of its own anyway, or [`posix_spawn`](Self::posix_spawn),
+    /// since the job is forked to record its status.
+    #[cfg(unix)]
+    pub fn detach(self, dir: impl Into<PathBuf>) -> Result<DetachedJob, io::Error> {
+        DetachedJob::spawn(self, dir.into())
+    }
+
+    /// Reads the output of the detached `job` from the start, instead of
+    /// spawning the command, as configured by this builder.
+    ///
+    /// Only the options that shape the items apply, such as the
+    /// [`mode`](Self::mode) and tees, not those that concern the child or
+    /// how it is spawned. See [`DetachedReader`].
+    #[cfg(unix)]
+    pub fn reattach(self, job: &DetachedJob) -> Result<DetachedReader, io::Error> {
+        DetachedReader::new(self, job)
+    }
+}
+
+/// What [`ProcessReaderBuilder::prepare`] set up besides the command.
+pub(crate) struct Prepared {
+    /// The cgroup that the child moves itself into, if any.
+    #[cfg(target_os = "linux")]
+    pub(crate) cgroup: Option<crate::Cgroup>,
+}
+
+pub(crate) fn non_empty_delimiter(delimiter: Vec<u8>) -> Vec<u8> {
+    assert!(!delimiter.is_empty(), "delimiter must not be empty");
+    delimiter
+}
+
+impl From<Command> for ProcessReaderBuilder {
+    fn from(cmd: Command) -> Self {
+        Self::new(cmd)
+    }
+}
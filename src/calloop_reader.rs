@@ -0,0 +1,194 @@
+use std::{
+    io,
+    os::unix::io::{AsFd, BorrowedFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use calloop::{
+    generic::Generic,
+    timer::{TimeoutAction, Timer},
+    EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
+};
+
+use crate::{Out, ProcessReader};
+
+/// A [`ProcessReader`] that is a [`calloop`] event source, calling back with
+/// every item as it is read.
+///
+/// Created with [`ProcessReader::into_calloop`]. The callback also receives
+/// the reader itself, for example to write to the child's stdin. The source
+/// removes itself from the event loop after [`Out::Done`].
+///
+/// ```no_run
+/// use std::process::Command;
+/// use calloop::EventLoop;
+/// use incremental_command::{Out, ProcessReader};
+///
+/// let mut event_loop: EventLoop<bool> = EventLoop::try_new()?;
+/// let reader = ProcessReader::start(Command::new("ls"))?.into_calloop();
+/// event_loop
+///     .handle()
+///     .insert_source(reader, |out, _reader, done| match out {
+///         Out::Done(_) => *done = true,
+///         out => println!("{out:?}"),
+///     })
+///     .map_err(|err| err.error)?;
+///
+/// let mut done = false;
+/// while !done {
+///     event_loop.dispatch(None, &mut done)?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct CalloopProcessReader {
+    // Declared before the reader so that it is unregistered before the fd it
+    // borrows is closed.
+    fd: Generic<PollFd>,
+    /// Wakes up the loop for timeouts, and when it does so.
+    timer: Option<(Timer, Instant)>,
+    reader: ProcessReader,
+}
+
+/// The file descriptor of the reader's own poll, which is readable whenever
+/// the child has written something.
+struct PollFd(RawFd);
+
+impl AsFd for PollFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // The fd is owned by the reader, which outlives this.
+        unsafe { BorrowedFd::borrow_raw(self.0) }
+    }
+}
+
+impl ProcessReader {
+    /// Turns the reader into a [`calloop`] event source.
+    pub fn into_calloop(self) -> CalloopProcessReader {
+        CalloopProcessReader {
+            fd: Generic::new(PollFd(self.poll_fd()), Interest::READ, Mode::Level),
+            timer: None,
+            reader: self,
+        }
+    }
+}
+
+impl CalloopProcessReader {
+    /// Returns the underlying reader.
+    pub fn reader(&mut self) -> &mut ProcessReader {
+        &mut self.reader
+    }
+
+    fn register_timer(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        if let Some(timeout) = self.reader.drive_timeout() {
+            let deadline = Instant::now() + timeout;
+            let mut timer = Timer::from_deadline(deadline);
+            timer.register(poll, token_factory)?;
+            self.timer = Some((timer, deadline));
+        }
+
+        Ok(())
+    }
+}
+
+impl EventSource for CalloopProcessReader {
+    type Event = Out;
+    type Metadata = ProcessReader;
+    type Ret = ();
+    type Error = io::Error;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> Result<PostAction, Self::Error>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let mut ours = false;
+        self.fd.process_events(readiness, token, |_, _| {
+            ours = true;
+            Ok(PostAction::Continue)
+        })?;
+        if let Some((timer, _)) = &mut self.timer {
+            let mut fired = false;
+            timer.process_events(readiness, token, |_, _| {
+                fired = true;
+                TimeoutAction::Drop
+            })?;
+            if fired {
+                self.timer = None;
+                ours = true;
+            }
+        }
+
+        if !ours {
+            return Ok(PostAction::Continue);
+        }
+
+        loop {
+            self.reader.drive()?;
+            while let Some(out) = self.reader.try_next()? {
+                let done = matches!(out, Out::Done(_));
+                callback(out, &mut self.reader);
+                if done {
+                    return Ok(PostAction::Remove);
+                }
+            }
+
+            // Output may have been left unread because the queue was full.
+            if self.reader.drive_timeout() != Some(Duration::ZERO) {
+                break;
+            }
+        }
+
+        // A timer that fires too early only causes a spurious read, so it is
+        // only replaced if it would fire too late.
+        let due = self
+            .reader
+            .drive_timeout()
+            .map(|timeout| Instant::now() + timeout);
+        let replace = match (due, &self.timer) {
+            (Some(due), Some((_, deadline))) => due < *deadline,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        Ok(if replace {
+            PostAction::Reregister
+        } else {
+            PostAction::Continue
+        })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.fd.register(poll, token_factory)?;
+        self.register_timer(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.fd.reregister(poll, token_factory)?;
+        if let Some((mut timer, _)) = self.timer.take() {
+            timer.unregister(poll)?;
+        }
+        self.register_timer(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+        self.fd.unregister(poll)?;
+        if let Some((mut timer, _)) = self.timer.take() {
+            timer.unregister(poll)?;
+        }
+        Ok(())
+    }
+}
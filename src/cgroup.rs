@@ -0,0 +1,206 @@
+use std::{
+    ffi::CString,
+    fs, io,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Tells apart the cgroups created by this process.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A cgroup v2 that a child was placed in with
+/// [`cgroup`](crate::ProcessReaderBuilder::cgroup), along with every process
+/// it spawns.
+///
+/// The cgroup accounts for the resources used by the whole process tree and
+/// can kill it as a whole, even processes that left the child's process
+/// group or were reparented. It is removed once the last clone is dropped,
+/// if no process is left in it by then.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::ProcessReader;
+///
+/// let reader = ProcessReader::builder(Command::new("make"))
+///     .cgroup("/sys/fs/cgroup/builds")
+///     .start()?;
+/// let cgroup = reader.cgroup().unwrap().clone();
+/// for out in reader {
+///     out?;
+///     let stats = cgroup.stats()?;
+///     println!("{:?} CPU, {} bytes", stats.cpu_time, stats.memory_current);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cgroup {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    path: PathBuf,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// The resources used by the processes in a [`Cgroup`] so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CgroupStats {
+    /// CPU time spent in both user and kernel mode.
+    pub cpu_time: Duration,
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in kernel mode.
+    pub system_time: Duration,
+    /// The memory currently in use, in bytes.
+    pub memory_current: u64,
+    /// The most memory that was in use at once, in bytes, if the kernel
+    /// records it.
+    pub memory_peak: Option<u64>,
+    /// The bytes read from block devices.
+    pub io_read_bytes: u64,
+    /// The bytes written to block devices.
+    pub io_write_bytes: u64,
+}
+
+/// Creates a cgroup under `parent`, if any, that the child moves itself
+/// into when it is spawned from `cmd`.
+pub(crate) fn place(
+    parent: Option<PathBuf>,
+    cmd: &mut Command,
+) -> Result<Option<Cgroup>, io::Error> {
+    let Some(parent) = parent else {
+        return Ok(None);
+    };
+
+    let cgroup = Cgroup::create(&parent)?;
+    cgroup.enter_on_exec(cmd)?;
+    Ok(Some(cgroup))
+}
+
+impl Cgroup {
+    /// Creates a new cgroup under `parent`.
+    fn create(parent: &Path) -> Result<Self, io::Error> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = parent.join(format!("incremental-command-{}-{id}", std::process::id()));
+        fs::create_dir(&path)?;
+
+        Ok(Self {
+            inner: Arc::new(Inner { path }),
+        })
+    }
+
+    /// Makes the child move itself into the cgroup before it runs the
+    /// program, so that none of its own children can escape it.
+    fn enter_on_exec(&self, cmd: &mut Command) -> Result<(), io::Error> {
+        let procs = CString::new(self.inner.path.join("cgroup.procs").as_os_str().as_bytes())?;
+
+        unsafe {
+            cmd.pre_exec(move || {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                // Writing zero moves the process that writes it.
+                let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                match written {
+                    -1 => Err(err),
+                    _ => Ok(()),
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the path of the cgroup's directory.
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// Returns the IDs of the processes that are currently in the cgroup.
+    pub fn pids(&self) -> Result<Vec<u32>, io::Error> {
+        let procs = fs::read_to_string(self.inner.path.join("cgroup.procs"))?;
+        Ok(procs.lines().filter_map(|pid| pid.parse().ok()).collect())
+    }
+
+    /// Returns the resources used by the processes in the cgroup so far,
+    /// including those that have exited.
+    ///
+    /// Memory and IO are only accounted if the `memory` and `io` controllers
+    /// are enabled for the cgroup, in the `cgroup.subtree_control` of its
+    /// parent. Otherwise they are reported as zero.
+    pub fn stats(&self) -> Result<CgroupStats, io::Error> {
+        let mut stats = CgroupStats::default();
+
+        for line in fs::read_to_string(self.inner.path.join("cpu.stat"))?.lines() {
+            let Some((key, micros)) = line.split_once(' ') else {
+                continue;
+            };
+            let time = Duration::from_micros(micros.parse().unwrap_or_default());
+            match key {
+                "usage_usec" => stats.cpu_time = time,
+                "user_usec" => stats.user_time = time,
+                "system_usec" => stats.system_time = time,
+                _ => {}
+            }
+        }
+
+        stats.memory_current = self.read_u64("memory.current")?.unwrap_or_default();
+        stats.memory_peak = self.read_u64("memory.peak")?;
+
+        if let Some(io_stat) = self.read("io.stat")? {
+            for field in io_stat.split_whitespace() {
+                let Some((key, bytes)) = field.split_once('=') else {
+                    continue;
+                };
+                let bytes: u64 = bytes.parse().unwrap_or_default();
+                match key {
+                    "rbytes" => stats.io_read_bytes += bytes,
+                    "wbytes" => stats.io_write_bytes += bytes,
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Kills every process in the cgroup with `SIGKILL`, including any that
+    /// are being spawned at the same time.
+    ///
+    /// This requires Linux 5.14 or later.
+    pub fn kill(&self) -> Result<(), io::Error> {
+        fs::write(self.inner.path.join("cgroup.kill"), "1")
+    }
+
+    /// Reads one of the cgroup's files, or returns `None` if its controller
+    /// is not enabled.
+    fn read(&self, name: &str) -> Result<Option<String>, io::Error> {
+        match fs::read_to_string(self.inner.path.join(name)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_u64(&self, name: &str) -> Result<Option<u64>, io::Error> {
+        Ok(self
+            .read(name)?
+            .and_then(|contents| contents.trim().parse().ok()))
+    }
+}
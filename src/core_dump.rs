@@ -0,0 +1,118 @@
+use std::{
+    ffi::OsString,
+    path::PathBuf,
+    process::{Command, ExitStatus},
+};
+
+use crate::{ExitKind, Out};
+
+/// Where to look for the core file of a child, captured from its command
+/// before it is spawned.
+#[derive(Debug)]
+pub(crate) struct CoreLocator {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    program: OsString,
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    cwd: Option<PathBuf>,
+}
+
+impl CoreLocator {
+    pub(crate) fn new(cmd: &Command) -> Self {
+        Self {
+            program: cmd.get_program().to_owned(),
+            cwd: cmd.get_current_dir().map(PathBuf::from),
+        }
+    }
+
+    /// Returns the path of the core file that the child `pid` dumped after
+    /// being killed by `signal`, if it was written to a file that exists.
+    ///
+    /// This expands `/proc/sys/kernel/core_pattern` the way the kernel does,
+    /// giving up on patterns that pipe the core to a program or that depend
+    /// on something not known here, such as the time of the dump.
+    #[cfg(target_os = "linux")]
+    fn locate(&self, pid: u32, signal: i32) -> Option<PathBuf> {
+        use std::{fs, os::unix::ffi::OsStringExt, path::Path};
+
+        let pattern = fs::read("/proc/sys/kernel/core_pattern").ok()?;
+        let pattern = pattern.strip_suffix(b"\n").unwrap_or(&pattern);
+        if pattern.starts_with(b"|") {
+            return None;
+        }
+
+        let mut name = Vec::new();
+        let mut has_pid = false;
+        let mut bytes = pattern.iter();
+        while let Some(&byte) = bytes.next() {
+            if byte != b'%' {
+                name.push(byte);
+                continue;
+            }
+
+            match bytes.next()? {
+                b'%' => name.push(b'%'),
+                b'p' | b'P' => {
+                    name.extend_from_slice(pid.to_string().as_bytes());
+                    has_pid = true;
+                }
+                b's' => name.extend_from_slice(signal.to_string().as_bytes()),
+                b'u' => name.extend_from_slice(unsafe { libc::getuid() }.to_string().as_bytes()),
+                b'g' => name.extend_from_slice(unsafe { libc::getgid() }.to_string().as_bytes()),
+                b'e' => {
+                    // The kernel uses the name of the thread, which defaults
+                    // to the first 15 bytes of the executable's name.
+                    let program = Path::new(&self.program).file_name()?;
+                    let program = program.as_encoded_bytes();
+                    name.extend_from_slice(&program[..program.len().min(15)]);
+                }
+                b'h' => name.extend_from_slice(&hostname()?),
+                _ => return None,
+            }
+        }
+
+        let uses_pid =
+            fs::read("/proc/sys/kernel/core_uses_pid").is_ok_and(|value| value.starts_with(b"1"));
+        if uses_pid && !has_pid {
+            name.extend_from_slice(format!(".{pid}").as_bytes());
+        }
+
+        let name = PathBuf::from(OsString::from_vec(name));
+        let path = match &self.cwd {
+            Some(cwd) => cwd.join(name),
+            None => name,
+        };
+        path.is_file().then_some(path)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn locate(&self, _pid: u32, _signal: i32) -> Option<PathBuf> {
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn hostname() -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+
+    let len = buf.iter().position(|&byte| byte == 0)?;
+    buf.truncate(len);
+    Some(buf)
+}
+
+/// Returns [`Out::CoreDumped`] if the child `pid` dumped core, looking up the
+/// core file with `locator` if there is one.
+pub(crate) fn report(status: ExitStatus, pid: u32, locator: Option<&CoreLocator>) -> Option<Out> {
+    let ExitKind::Signaled {
+        signal,
+        core_dumped: true,
+    } = ExitKind::from(status)
+    else {
+        return None;
+    };
+
+    let path = locator.and_then(|locator| locator.locate(pid, signal));
+    Some(Out::CoreDumped(path))
+}
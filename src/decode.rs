@@ -0,0 +1,140 @@
+use std::io;
+
+#[cfg(feature = "encoding")]
+use encoding_rs::Encoding;
+
+/// What happens to output that is not valid UTF-8, or not valid in the
+/// configured encoding, in strict mode.
+///
+/// See [`ProcessReaderBuilder::strict_utf8`](crate::ProcessReaderBuilder::strict_utf8).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+    /// Replace invalid bytes with U+FFFD.
+    Replace,
+    /// Discard the whole item and yield an error of kind
+    /// [`io::ErrorKind::InvalidData`] in its place. Reading continues with
+    /// the next item.
+    Error,
+}
+
+/// Turns the bytes of a line into text.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Decoder {
+    /// The encoding the child writes in, or `None` for UTF-8.
+    #[cfg(feature = "encoding")]
+    encoding: Option<&'static Encoding>,
+    /// How invalid output is handled in strict mode, if enabled.
+    strict: Option<InvalidUtf8>,
+}
+
+impl Decoder {
+    #[cfg(feature = "encoding")]
+    pub(crate) fn set_encoding(&mut self, encoding: &'static Encoding) {
+        self.encoding = Some(encoding);
+    }
+
+    pub(crate) fn set_strict(&mut self, invalid: InvalidUtf8) {
+        self.strict = Some(invalid);
+    }
+
+    pub(crate) fn is_strict(&self) -> bool {
+        self.strict.is_some()
+    }
+
+    /// Decodes `bytes`, replacing anything invalid with U+FFFD.
+    pub(crate) fn decode(&self, bytes: &[u8]) -> String {
+        self.decode_lossy(bytes).0
+    }
+
+    /// Decodes `bytes` into the text of an item, which fails if they are
+    /// invalid and strict mode asks for an error.
+    pub(crate) fn decode_item(&self, bytes: &[u8]) -> Result<String, io::Error> {
+        let (text, had_errors) = self.decode_lossy(bytes);
+        if had_errors && self.strict == Some(InvalidUtf8::Error) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "output is not valid in the expected encoding",
+            ));
+        }
+
+        Ok(text)
+    }
+
+    /// Like [`decode_item`](Self::decode_item), but reuses the allocation
+    /// of `buf` for valid UTF-8.
+    pub(crate) fn decode_item_into(
+        &self,
+        bytes: &[u8],
+        mut buf: String,
+    ) -> Result<String, io::Error> {
+        #[cfg(feature = "encoding")]
+        if self.encoding.is_some() {
+            return self.decode_item(bytes);
+        }
+
+        match std::str::from_utf8(bytes) {
+            Ok(text) => {
+                buf.clear();
+                buf.push_str(text);
+                Ok(buf)
+            }
+            Err(_) => self.decode_item(bytes),
+        }
+    }
+
+    fn decode_lossy(&self, bytes: &[u8]) -> (String, bool) {
+        #[cfg(feature = "encoding")]
+        if let Some(encoding) = self.encoding {
+            let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+            return (text.into_owned(), had_errors);
+        }
+
+        match String::from_utf8_lossy(bytes) {
+            std::borrow::Cow::Borrowed(text) => (text.to_owned(), false),
+            std::borrow::Cow::Owned(text) => (text, true),
+        }
+    }
+
+    /// Returns how many bytes at the end of `bytes` are the start of a UTF-8
+    /// sequence that has not been read completely yet.
+    pub(crate) fn incomplete_suffix(&self, bytes: &[u8]) -> usize {
+        #[cfg(feature = "encoding")]
+        if self
+            .encoding
+            .is_some_and(|encoding| encoding != encoding_rs::UTF_8)
+        {
+            return 0;
+        }
+
+        for len in 1..=bytes.len().min(3) {
+            let byte = bytes[bytes.len() - len];
+            if byte & 0xc0 == 0x80 {
+                continue;
+            }
+
+            let needed = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            return if needed > len { len } else { 0 };
+        }
+
+        0
+    }
+}
+
+/// Returns the encoding named by the locale in `LC_ALL`, `LC_CTYPE` or
+/// `LANG`, such as `ja_JP.SJIS`, if it is known and compatible with ASCII.
+#[cfg(feature = "encoding")]
+pub(crate) fn locale_encoding() -> Option<&'static Encoding> {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())?;
+
+    let (_, codeset) = locale.split_once('.')?;
+    let codeset = codeset.split('@').next().unwrap_or(codeset);
+    Encoding::for_label(codeset.as_bytes()).filter(|encoding| encoding.is_ascii_compatible())
+}
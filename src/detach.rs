@@ -0,0 +1,556 @@
+use std::{
+    ffi::CString,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+        process::{CommandExt, ExitStatusExt},
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+};
+
+use crate::{
+    builder::grow_read_buf,
+    event::OutputQueue,
+    follow::FOLLOW_INTERVAL,
+    framing::{Framer, Stream},
+    sys,
+    tee::Tee,
+    Out, ProcessReaderBuilder,
+};
+
+const PID: &str = "pid";
+const STATUS: &str = "status";
+/// Where the exit status is written before it is moved to [`STATUS`], so
+/// that it is never read half-written.
+const STATUS_TMP: &str = ".status";
+const STDOUT: &str = "stdout";
+const STDERR: &str = "stderr";
+
+/// A command that was spawned detached from this process, with
+/// [`ProcessReaderBuilder::detach`], which keeps running after this process
+/// exits.
+///
+/// The job is kept in a directory, which holds its process ID, the files
+/// its stdout and stderr are written to, and its exit status once it has
+/// exited. Any process can open the job from that directory with
+/// [`open`](Self::open), to check on it or to
+/// [`reattach`](Self::reattach) to its output.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{DetachedJob, Out, ProcessReader};
+///
+/// let mut cmd = Command::new("backup");
+/// cmd.arg("/home");
+/// let job = ProcessReader::builder(cmd).detach("/var/lib/jobs/backup")?;
+/// println!("started backup as {}", job.id());
+///
+/// // Later, possibly from another process.
+/// let job = DetachedJob::open("/var/lib/jobs/backup")?;
+/// for out in job.reattach()? {
+///     match out? {
+///         Out::Stdout(line) | Out::Stderr(line) => println!("{line}"),
+///         Out::Done(status) => println!("backup exited with {status}"),
+///         _ => {}
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct DetachedJob {
+    dir: PathBuf,
+    pid: u32,
+}
+
+impl DetachedJob {
+    pub(crate) fn spawn(
+        mut builder: ProcessReaderBuilder,
+        dir: PathBuf,
+    ) -> Result<Self, io::Error> {
+        if let Some(option) = unsupported_option(&builder) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{option} is not supported for a detached job"),
+            ));
+        }
+
+        let created = !dir.exists();
+        fs::create_dir_all(&dir)?;
+        // Creating the file that holds the process ID fails if the directory
+        // already belongs to a job.
+        let pid_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join(PID))?;
+
+        let mut child = match spawn_job(&mut builder, &dir, pid_file) {
+            Ok(child) => child,
+            Err(err) => {
+                // Nothing is left behind that would make the directory look
+                // like it holds a job.
+                for name in [PID, STATUS_TMP, STATUS, STDOUT, STDERR] {
+                    let _ = fs::remove_file(dir.join(name));
+                }
+                if created {
+                    let _ = fs::remove_dir(&dir);
+                }
+                return Err(err);
+            }
+        };
+
+        // The child that was spawned exits as soon as the job's process is
+        // running, once it is no longer its parent.
+        child.wait()?;
+        Self::open(dir)
+    }
+
+    /// Opens the job that was detached into `dir`.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, io::Error> {
+        let dir = dir.into();
+        let pid = read_number(&dir.join(PID))?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the job's process ID is missing",
+            )
+        })?;
+
+        Ok(Self {
+            dir,
+            pid: pid as u32,
+        })
+    }
+
+    /// Returns the process identifier of the job's process.
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Returns the directory that the job is kept in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the path of the file that the job's stdout is written to.
+    pub fn stdout_path(&self) -> PathBuf {
+        self.dir.join(STDOUT)
+    }
+
+    /// Returns the path of the file that the job's stderr is written to.
+    pub fn stderr_path(&self) -> PathBuf {
+        self.dir.join(STDERR)
+    }
+
+    /// Returns the job's exit status, or `None` if it is still running.
+    pub fn try_status(&self) -> Result<Option<ExitStatus>, io::Error> {
+        let status = read_number(&self.dir.join(STATUS))?;
+        Ok(status.map(ExitStatus::from_raw))
+    }
+
+    /// Waits for the job to exit and returns its exit status.
+    ///
+    /// The status is checked every 100ms, since the job is not a child of
+    /// this process.
+    pub fn wait(&self) -> Result<ExitStatus, io::Error> {
+        loop {
+            if let Some(status) = self.try_status()? {
+                return Ok(status);
+            }
+            thread::sleep(FOLLOW_INTERVAL);
+        }
+    }
+
+    /// Sends `signal`, such as `libc::SIGTERM`, to the job's process.
+    ///
+    /// Nothing is sent once its exit status has been recorded, since its
+    /// process ID may already have been reused.
+    pub fn signal(&self, signal: i32) -> Result<(), io::Error> {
+        match self.try_status()? {
+            Some(_) => Ok(()),
+            None => sys::send_signal(self.pid as libc::pid_t, signal),
+        }
+    }
+
+    /// Forcefully kills the job's process.
+    ///
+    /// Nothing happens once its exit status has been recorded.
+    pub fn kill(&self) -> Result<(), io::Error> {
+        self.signal(libc::SIGKILL)
+    }
+
+    /// Reads the job's output from the start, with the default options,
+    /// until it has exited.
+    ///
+    /// Use [`ProcessReaderBuilder::reattach`] to configure how the output is
+    /// read. See [`DetachedReader`].
+    pub fn reattach(&self) -> Result<DetachedReader, io::Error> {
+        ProcessReaderBuilder::new(Command::new("")).reattach(self)
+    }
+}
+
+/// Reads the output of a [`DetachedJob`] from the files it was written to,
+/// yielding the same items as a [`ProcessReader`](crate::ProcessReader).
+///
+/// Created with [`DetachedJob::reattach`] or
+/// [`ProcessReaderBuilder::reattach`].
+///
+/// The files are read from their start and followed until the job has
+/// exited, after which the rest of its output is yielded, followed by
+/// [`Out::Done`]. They are checked for appended output every 100ms, since
+/// they cannot be polled for it.
+pub struct DetachedReader {
+    job: DetachedJob,
+    stdout: File,
+    stderr: File,
+    stdout_framer: Framer,
+    stderr_framer: Framer,
+    output_buf: OutputQueue,
+    read_buf: Vec<u8>,
+    done: bool,
+}
+
+impl DetachedReader {
+    pub(crate) fn new(builder: ProcessReaderBuilder, job: &DetachedJob) -> Result<Self, io::Error> {
+        let ProcessReaderBuilder {
+            buffer_size,
+            mode,
+            stdout: stdout_options,
+            stderr: stderr_options,
+            max_line_length,
+            decoder,
+            line_pool,
+            tee_stdout,
+            tee_stderr,
+            tee_rotation,
+            tee_only,
+            wall_clock,
+            ..
+        } = builder;
+
+        let stdout = File::open(job.stdout_path())?;
+        let stderr = File::open(job.stderr_path())?;
+
+        let (stdout_tee, stderr_tee) = Tee::open(
+            tee_stdout.as_deref(),
+            tee_stderr.as_deref(),
+            tee_rotation.as_ref(),
+            tee_only,
+        )?;
+        let stdout_framer = Framer::new(
+            Stream::Stdout,
+            mode,
+            stdout_options,
+            max_line_length,
+            decoder,
+            stdout_tee,
+            line_pool.clone(),
+        );
+        let stderr_framer = Framer::new(
+            Stream::Stderr,
+            mode,
+            stderr_options,
+            max_line_length,
+            decoder,
+            stderr_tee,
+            line_pool,
+        );
+
+        Ok(Self {
+            job: job.clone(),
+            stdout,
+            stderr,
+            stdout_framer,
+            stderr_framer,
+            output_buf: OutputQueue::new(wall_clock, None),
+            read_buf: vec![0; buffer_size],
+            done: false,
+        })
+    }
+
+    /// Returns the job that is read.
+    pub fn job(&self) -> &DetachedJob {
+        &self.job
+    }
+
+    /// Reads what the job appended to its files, or waits for it to append
+    /// more.
+    fn step(&mut self) -> Result<(), io::Error> {
+        // The status is checked first, so that all output written before it
+        // was recorded is read below.
+        let status = self.job.try_status()?;
+
+        let mut read = drain(
+            &mut self.stdout,
+            &mut self.stdout_framer,
+            &mut self.output_buf,
+            &mut self.read_buf,
+        )?;
+        read += drain(
+            &mut self.stderr,
+            &mut self.stderr_framer,
+            &mut self.output_buf,
+            &mut self.read_buf,
+        )?;
+
+        if let Some(status) = status {
+            self.stdout_framer.flush(&mut self.output_buf);
+            self.stderr_framer.flush(&mut self.output_buf);
+            self.output_buf.push_back(Out::Done(status));
+            self.done = true;
+        } else if read == 0 {
+            thread::sleep(FOLLOW_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+/// Reads `file` up to its current end, returning how many bytes were read.
+fn drain(
+    file: &mut File,
+    framer: &mut Framer,
+    out_buf: &mut OutputQueue,
+    buf: &mut Vec<u8>,
+) -> Result<usize, io::Error> {
+    let mut total = 0;
+    loop {
+        let n = match file.read(buf) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => res?,
+        };
+        if n == 0 {
+            return Ok(total);
+        }
+
+        out_buf.stamp();
+        total += n;
+        framer.push(&buf[..n], out_buf);
+        grow_read_buf(buf, n);
+    }
+}
+
+impl Iterator for DetachedReader {
+    type Item = Result<Out, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.output_buf.pop_front() {
+                return Some(event.map(|event| event.out));
+            }
+            if self.done {
+                return None;
+            }
+
+            if let Err(err) = self.step() {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+/// Reads the decimal number in the file at `path`, or returns `None` if it
+/// does not exist or is empty.
+fn read_number(path: &Path) -> Result<Option<i32>, io::Error> {
+    let contents = match fs::read_to_string(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        res => res?,
+    };
+    if contents.is_empty() {
+        return Ok(None);
+    }
+
+    contents
+        .trim()
+        .parse()
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Returns the first option set on `builder` that cannot apply to a
+/// detached job, if any.
+///
+/// A job outlives any cgroup or namespace set up for it, and its streams are
+/// connected to files in its directory rather than to this process.
+fn unsupported_option(builder: &ProcessReaderBuilder) -> Option<&'static str> {
+    #[cfg(target_os = "linux")]
+    if builder.cgroup.is_some() {
+        return Some("cgroup");
+    }
+    #[cfg(target_os = "linux")]
+    if !builder.namespaces.is_empty() {
+        return Some("unshare");
+    }
+
+    let options = [
+        (builder.process_backend.is_some(), "process_backend"),
+        (builder.pty, "pty"),
+        (builder.pipe_stdin, "pipe_stdin"),
+        (builder.merge_stderr, "merge_stderr"),
+        (!builder.capture_fds.is_empty(), "capture_fd"),
+        (!builder.sources.is_empty(), "source"),
+        (!builder.follow.is_empty(), "follow_file"),
+        (builder.process_group, "process_group"),
+        (builder.posix_spawn, "posix_spawn"),
+    ];
+    options
+        .into_iter()
+        .find_map(|(set, option)| set.then_some(option))
+}
+
+/// Spawns the builder's command as a job in `dir`, returning the child that
+/// exits once the job's process is running.
+fn spawn_job(
+    builder: &mut ProcessReaderBuilder,
+    dir: &Path,
+    pid_file: File,
+) -> Result<Child, io::Error> {
+    let status_tmp = File::create(dir.join(STATUS_TMP))?;
+    let stdout = File::create(dir.join(STDOUT))?;
+    let stderr = File::create(dir.join(STDERR))?;
+    // Every process forked for the job holds the other end until it exits
+    // or runs the command, which closes it on exec.
+    let (mut forks_done, forks) = UnixStream::pair()?;
+
+    builder.prepare()?;
+    let cmd = &mut builder.cmd;
+    cmd.stdin(Stdio::null()).stdout(stdout).stderr(stderr);
+    set_detached(cmd, dir, pid_file, status_tmp, forks.as_raw_fd())?;
+    // Only the job's process returns from the forks in `set_detached`, so
+    // the filter applies to it alone.
+    #[cfg(target_os = "linux")]
+    crate::seccomp::install(cmd, builder.seccomp.take());
+
+    let spawned = cmd.spawn();
+    drop(forks);
+    if spawned.is_err() {
+        // The process that records the exit status may still be writing it
+        // for the command that failed to run, so the job's files are only
+        // removed once it has exited.
+        let _ = forks_done.read_to_end(&mut Vec::new());
+    }
+    spawned
+}
+
+/// Makes the child start the command in a new session, as the child of a
+/// process that records its exit status, and exit itself.
+///
+/// Both forks happen right before the command is executed, so that errors up
+/// to and including executing it are still returned by [`Command::spawn`].
+/// Only async-signal-safe functions are called after forking, and everything
+/// they need is prepared beforehand.
+///
+/// The process that records the exit status keeps `forks` open until it
+/// exits.
+fn set_detached(
+    cmd: &mut Command,
+    dir: &Path,
+    pid_file: File,
+    status_tmp: File,
+    forks: RawFd,
+) -> Result<(), io::Error> {
+    let path = |name: &str| CString::new(dir.join(name).as_os_str().as_bytes());
+    let (status_tmp_path, status_path) = (path(STATUS_TMP)?, path(STATUS)?);
+    let max_fd = max_fd();
+
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // The child exits, which leaves the others to init once they no
+            // longer have a parent.
+            match libc::fork() {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => {}
+                _ => libc::_exit(0),
+            }
+
+            let pid = match libc::fork() {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => {
+                    // Written before executing the command, so that it can
+                    // be read once spawning it has succeeded.
+                    let mut buf = [0; 12];
+                    write_all(
+                        pid_file.as_raw_fd(),
+                        format_number(libc::getpid(), &mut buf),
+                    );
+                    return Ok(());
+                }
+                pid => pid,
+            };
+
+            // This process only records the command's exit status, so it
+            // closes everything else, including the pipe that spawning
+            // reports errors through, which is otherwise held open.
+            let (status_fd, forks_fd) = (3, 4);
+            let forks = libc::fcntl(forks, libc::F_DUPFD, forks_fd + 1);
+            if forks == -1
+                || libc::dup2(status_tmp.as_raw_fd(), status_fd) == -1
+                || libc::dup2(forks, forks_fd) == -1
+            {
+                libc::_exit(1);
+            }
+            close_from(forks_fd + 1, max_fd);
+
+            let mut status = 0;
+            while libc::waitpid(pid, &mut status, 0) == -1 {
+                if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                    libc::_exit(1);
+                }
+            }
+            let mut buf = [0; 12];
+            write_all(status_fd, format_number(status, &mut buf));
+            libc::close(status_fd);
+            libc::rename(status_tmp_path.as_ptr(), status_path.as_ptr());
+            libc::_exit(0)
+        });
+    }
+    Ok(())
+}
+
+/// Returns an upper bound for the file descriptors of this process.
+fn max_fd() -> RawFd {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    match unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } {
+        0 if limit.rlim_cur != libc::RLIM_INFINITY => limit.rlim_cur.min(1 << 20) as RawFd,
+        _ => 1 << 20,
+    }
+}
+
+/// Closes the file descriptors from `first` on.
+unsafe fn close_from(first: RawFd, max_fd: RawFd) {
+    #[cfg(target_os = "linux")]
+    if libc::syscall(libc::SYS_close_range, first, libc::c_uint::MAX, 0) == 0 {
+        return;
+    }
+    for fd in first..max_fd {
+        libc::close(fd);
+    }
+}
+
+/// Writes `bytes` to `fd`, giving up on errors other than interruptions.
+unsafe fn write_all(fd: RawFd, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        match libc::write(fd, bytes.as_ptr().cast(), bytes.len()) {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            n if n <= 0 => return,
+            n => bytes = &bytes[n as usize..],
+        }
+    }
+}
+
+/// Formats `n` in decimal into `buf`, without allocating.
+fn format_number(n: i32, buf: &mut [u8; 12]) -> &[u8] {
+    let mut cursor = io::Cursor::new(&mut buf[..]);
+    let _ = write!(cursor, "{n}");
+    let len = cursor.position() as usize;
+    &buf[..len]
+}
@@ -0,0 +1,59 @@
+use std::{collections::BTreeMap, env, ffi::OsString, process::Command};
+
+/// The environment configured with
+/// [`env_clear`](crate::ProcessReaderBuilder::env_clear),
+/// [`env_allow`](crate::ProcessReaderBuilder::env_allow) and
+/// [`env`](crate::ProcessReaderBuilder::env).
+#[derive(Debug, Default)]
+pub(crate) struct EnvOptions {
+    pub(crate) clear: bool,
+    /// The variables inherited from the parent, or `None` for all of them.
+    pub(crate) allow: Option<Vec<OsString>>,
+    pub(crate) vars: Vec<(OsString, OsString)>,
+}
+
+impl EnvOptions {
+    fn is_set(&self) -> bool {
+        self.clear || self.allow.is_some() || !self.vars.is_empty()
+    }
+
+    /// Returns the environment `cmd` is spawned with: what is inherited from
+    /// the parent, then what `cmd` sets or removes itself, then the
+    /// variables set on the builder.
+    pub(crate) fn resolve(&self, cmd: &Command) -> BTreeMap<OsString, OsString> {
+        let mut resolved: BTreeMap<_, _> = match (&self.allow, self.clear) {
+            (_, true) => BTreeMap::new(),
+            (Some(allow), false) => allow
+                .iter()
+                .filter_map(|key| Some((key.clone(), env::var_os(key)?)))
+                .collect(),
+            (None, false) => env::vars_os().collect(),
+        };
+
+        for (key, value) in cmd.get_envs() {
+            match value {
+                Some(value) => resolved.insert(key.to_owned(), value.to_owned()),
+                None => resolved.remove(key),
+            };
+        }
+        resolved.extend(self.vars.iter().cloned());
+        resolved
+    }
+
+    /// Moves the variables set on the builder to `cmd` itself, so that a
+    /// backend passes them on with the ones set on the [`Command`].
+    pub(crate) fn forward(&mut self, cmd: &mut Command) {
+        cmd.envs(self.vars.drain(..));
+    }
+
+    /// Replaces the environment of `cmd` with the resolved one, unless it
+    /// is left as configured on the [`Command`].
+    pub(crate) fn apply(&self, cmd: &mut Command) {
+        if !self.is_set() {
+            return;
+        }
+
+        let resolved = self.resolve(cmd);
+        cmd.env_clear().envs(resolved);
+    }
+}
@@ -0,0 +1,58 @@
+use std::{error, fmt, io, process::ExitStatus};
+
+/// Errors specific to this crate.
+///
+/// Readers yield these wrapped in an [`io::Error`] of kind
+/// [`io::ErrorKind::Other`], like any other error, and they can be
+/// recovered with [`Error::from_io`].
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{Error, ProcessReader};
+///
+/// let reader = ProcessReader::builder(Command::new("make")).check(5).start()?;
+/// for out in reader {
+///     if let Err(err) = out {
+///         if let Some(Error::NonZeroExit { captured_stderr_tail, .. }) = Error::from_io(&err) {
+///             eprintln!("{}", captured_stderr_tail.join("\n"));
+///         }
+///         return Err(err);
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The child exited with a non-zero code or was killed by a signal, with
+    /// [`check`](crate::ProcessReaderBuilder::check) enabled.
+    NonZeroExit {
+        /// The exit status of the child.
+        status: ExitStatus,
+        /// The last lines the child wrote to stderr, oldest first.
+        captured_stderr_tail: Vec<String>,
+    },
+}
+
+impl Error {
+    /// Returns the error of this crate that `err` wraps, if any.
+    pub fn from_io(err: &io::Error) -> Option<&Error> {
+        err.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NonZeroExit { status, .. } => write!(f, "child exited unsuccessfully: {status}"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::other(err)
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    collections::VecDeque,
+    io,
+    time::{Instant, SystemTime},
+};
+
+use crate::{
+    framing::Stream, hooks::OutputHook, limit::Limiter, sample::Sampler, throttle::Throttle, Out,
+    QueuePolicy,
+};
+
+/// An [`Out`] item together with when it was produced.
+///
+/// Returned by [`ProcessReader::next_event`](crate::ProcessReader::next_event).
+///
+/// With the `serde` feature, [`at`](Self::at) is skipped, since it only has a
+/// meaning within the current process, and set to the time of
+/// deserialization. Enable [`wall_clock`](crate::ProcessReaderBuilder::wall_clock)
+/// to serialize when the item was read.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Event {
+    /// The item itself.
+    pub out: Out,
+    /// When the bytes that make up the item were read, or when the item was
+    /// produced for items that do not carry output.
+    #[cfg_attr(feature = "serde", serde(skip, default = "Instant::now"))]
+    pub at: Instant,
+    /// The wall-clock time corresponding to [`at`](Self::at), if enabled with
+    /// [`wall_clock`](crate::ProcessReaderBuilder::wall_clock).
+    pub time: Option<SystemTime>,
+    /// The position of the item in the reader's output, starting at zero and
+    /// increasing by one for every item that is queued. Items dropped because
+    /// the queue was full, with a
+    /// [`queue_capacity`](crate::ProcessReaderBuilder::queue_capacity) that
+    /// drops output, still use up their number, so a gap means that items
+    /// were dropped.
+    pub seq: u64,
+}
+
+/// The queue of items and errors waiting to be yielded, which stamps every
+/// item with the time of the last call to [`stamp`](Self::stamp) and its
+/// sequence number.
+#[derive(Debug)]
+pub(crate) struct OutputQueue {
+    events: VecDeque<Result<Event, io::Error>>,
+    wall_clock: bool,
+    at: Instant,
+    time: Option<SystemTime>,
+    next_seq: u64,
+    capacity: Option<(usize, QueuePolicy)>,
+    dropped: u64,
+    /// How many lines of stdout and stderr have been popped.
+    lines: u64,
+    /// How many lines of stdout and stderr have been pushed, including those
+    /// that were not queued.
+    read_lines: (u64, u64),
+    /// How many lines of stderr to keep, and the last ones queued.
+    stderr_tail: Option<(usize, VecDeque<String>)>,
+    on_output: Option<OutputHook>,
+    /// The samplers of stdout and stderr, if any.
+    samplers: Option<(Sampler, Sampler)>,
+    /// The limits of stdout and stderr, if any.
+    limits: Option<(Limiter, Limiter)>,
+    /// The rate limits of stdout and stderr, if any.
+    throttles: Option<(Throttle, Throttle)>,
+    /// The stream of the last output item, which an [`Out::Truncated`]
+    /// after it belongs to, or `None` if it was read from a stream that is
+    /// not limited.
+    last_stream: Option<Stream>,
+}
+
+impl OutputQueue {
+    pub(crate) fn new(wall_clock: bool, capacity: Option<(usize, QueuePolicy)>) -> Self {
+        let mut queue = Self {
+            events: VecDeque::new(),
+            wall_clock,
+            at: Instant::now(),
+            time: None,
+            next_seq: 0,
+            capacity,
+            dropped: 0,
+            lines: 0,
+            read_lines: (0, 0),
+            stderr_tail: None,
+            on_output: None,
+            samplers: None,
+            limits: None,
+            throttles: None,
+            last_stream: None,
+        };
+        queue.stamp();
+        queue
+    }
+
+    /// Sets the time that items pushed from now on are stamped with to now.
+    pub(crate) fn stamp(&mut self) {
+        self.at = Instant::now();
+        self.time = self.wall_clock.then(SystemTime::now);
+    }
+
+    /// Calls `hook` with every output item that is queued from now on.
+    pub(crate) fn set_output_hook(&mut self, hook: Option<OutputHook>) {
+        self.on_output = hook;
+    }
+
+    /// Starts keeping the last `lines` lines of stderr that are queued, even
+    /// if they are dropped.
+    pub(crate) fn keep_stderr_tail(&mut self, lines: usize) {
+        self.stderr_tail = Some((lines, VecDeque::with_capacity(lines)));
+    }
+
+    /// Samples the output queued from each stream from now on.
+    pub(crate) fn sample_output(&mut self, stdout: Sampler, stderr: Sampler) {
+        self.samplers = Some((stdout, stderr));
+    }
+
+    /// Limits the output queued from each stream from now on.
+    pub(crate) fn limit_output(&mut self, stdout: Limiter, stderr: Limiter) {
+        self.limits = Some((stdout, stderr));
+    }
+
+    /// Limits the rate of output queued from each stream from now on.
+    pub(crate) fn throttle_output(&mut self, stdout: Throttle, stderr: Throttle) {
+        self.throttles = Some((stdout, stderr));
+    }
+
+    /// Returns when output that the rate limits held back is due, if any.
+    pub(crate) fn throttle_deadline(&self) -> Option<Instant> {
+        let (stdout, stderr) = self.throttles.as_ref()?;
+        [stdout.deadline(), stderr.deadline()]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Queues whatever the rate limits held back that is due by now.
+    pub(crate) fn release_throttled(&mut self) {
+        let Some((stdout, stderr)) = &mut self.throttles else {
+            return;
+        };
+
+        let now = Instant::now();
+        let released = [
+            (Stream::Stdout, stdout.release(now)),
+            (Stream::Stderr, stderr.release(now)),
+        ];
+        for (which, (count, held)) in released {
+            self.release(which, count, held);
+        }
+    }
+
+    /// Queues whatever the limits and rate limits held back, each preceded by
+    /// a marker if anything was dropped from its stream.
+    pub(crate) fn flush_limits(&mut self) {
+        if let Some((stdout, stderr)) = &mut self.throttles {
+            let finished = [
+                (Stream::Stdout, stdout.finish()),
+                (Stream::Stderr, stderr.finish()),
+            ];
+            for (which, (count, held)) in finished {
+                self.release(which, count, held);
+            }
+        }
+
+        let Some((stdout, stderr)) = &mut self.limits else {
+            return;
+        };
+
+        let finished = [
+            (Stream::Stdout, stdout.finish()),
+            (Stream::Stderr, stderr.finish()),
+        ];
+        let mut released = Vec::new();
+        for (which, (dropped_lines, held)) in finished {
+            if dropped_lines > 0 {
+                let marker = match which {
+                    Stream::Stdout => Out::StdoutElided { dropped_lines },
+                    Stream::Stderr => Out::StderrElided { dropped_lines },
+                };
+                released.push(self.event(marker));
+            }
+            released.extend(held);
+        }
+
+        for event in released {
+            self.enqueue(event);
+        }
+    }
+
+    /// Queues a summary of `count` items that the rate limit of `which`
+    /// suppressed, unless there were none, followed by `events`.
+    fn release(&mut self, which: Stream, count: u64, events: impl IntoIterator<Item = Event>) {
+        if count > 0 {
+            let summary = match which {
+                Stream::Stdout => Out::StdoutSuppressed { count },
+                Stream::Stderr => Out::StderrSuppressed { count },
+            };
+            let summary = self.event(summary);
+            self.enqueue(summary);
+        }
+
+        for event in events {
+            self.enqueue(event);
+        }
+    }
+
+    /// Returns the lines of stderr kept so far, oldest first.
+    pub(crate) fn take_stderr_tail(&mut self) -> Vec<String> {
+        match &mut self.stderr_tail {
+            Some((_, tail)) => tail.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Queues `out`, unless it is output that is sampled out or beyond the
+    /// limit or rate limit of its stream, and drops an item if the queue is
+    /// full and its policy says so. Only output is ever dropped; other items
+    /// and errors are always queued. Items dropped because the queue is full
+    /// still use up a sequence number.
+    pub(crate) fn push_back(&mut self, out: Out) {
+        if let Some(hook) = &mut self.on_output {
+            if is_output(&out) {
+                (hook.0)(&out);
+            }
+        }
+        if let (Out::Stderr(line), Some((lines, tail))) = (&out, &mut self.stderr_tail) {
+            if tail.len() == *lines {
+                tail.pop_front();
+            }
+            if *lines > 0 {
+                tail.push_back(line.clone());
+            }
+        }
+
+        match &out {
+            Out::Stdout(_) => self.read_lines.0 += 1,
+            Out::Stderr(_) => self.read_lines.1 += 1,
+            _ => {}
+        }
+
+        let mut event = self.event(out);
+        if self.samplers.is_none() && self.limits.is_none() && self.throttles.is_none() {
+            return self.enqueue(event);
+        }
+
+        let which = match (stream(&event.out), &event.out) {
+            (Some(which), _) => which,
+            (None, Out::Truncated(_)) => match self.last_stream {
+                Some(which) => which,
+                None => return self.enqueue(event),
+            },
+            (None, Out::Fd { .. } | Out::Source { .. } | Out::Followed { .. }) => {
+                self.last_stream = None;
+                return self.enqueue(event);
+            }
+            (None, _) => return self.enqueue(event),
+        };
+        self.last_stream = Some(which);
+
+        if let Some((stdout, stderr)) = &mut self.samplers {
+            let sampler = match which {
+                Stream::Stdout => stdout,
+                Stream::Stderr => stderr,
+            };
+            if !sampler.keep(&event.out, event.at) {
+                return;
+            }
+        }
+
+        if let Some((stdout, stderr)) = &mut self.limits {
+            let limiter = match which {
+                Stream::Stdout => stdout,
+                Stream::Stderr => stderr,
+            };
+            match limiter.admit(event) {
+                Some(admitted) => event = admitted,
+                None => return,
+            }
+        }
+
+        if let Some((stdout, stderr)) = &mut self.throttles {
+            let throttle = match which {
+                Stream::Stdout => stdout,
+                Stream::Stderr => stderr,
+            };
+            let (count, admitted) = throttle.admit(event);
+            return self.release(which, count, admitted);
+        }
+
+        self.enqueue(event);
+    }
+
+    /// Stamps `out` with the current time. Its sequence number is only
+    /// assigned once it is queued.
+    fn event(&self, out: Out) -> Event {
+        Event {
+            out,
+            at: self.at,
+            time: self.time,
+            seq: 0,
+        }
+    }
+
+    /// Queues `event` with the next sequence number, dropping an item if the
+    /// queue is full and its policy says so.
+    fn enqueue(&mut self, mut event: Event) {
+        event.seq = self.next_seq;
+        self.next_seq += 1;
+
+        if let Some((capacity, policy)) = self.capacity {
+            if self.events.len() >= capacity && is_output(&event.out) {
+                match policy {
+                    QueuePolicy::Backpressure => {}
+                    QueuePolicy::DropNewest => {
+                        self.dropped += 1;
+                        return;
+                    }
+                    QueuePolicy::DropOldest => {
+                        let oldest = self
+                            .events
+                            .iter()
+                            .position(|event| matches!(event, Ok(event) if is_output(&event.out)));
+                        if let Some(oldest) = oldest {
+                            self.events.remove(oldest);
+                            self.dropped += 1;
+                            #[cfg(feature = "metrics")]
+                            crate::recorder::record_queued(-1.0);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.events.push_back(Ok(event));
+        #[cfg(feature = "metrics")]
+        crate::recorder::record_queued(1.0);
+    }
+
+    /// Queues an error to be yielded in order with the items around it.
+    pub(crate) fn push_error(&mut self, err: io::Error) {
+        self.events.push_back(Err(err));
+        #[cfg(feature = "metrics")]
+        crate::recorder::record_queued(1.0);
+    }
+
+    pub(crate) fn pop_front(&mut self) -> Option<Result<Event, io::Error>> {
+        let event = self.events.pop_front();
+        if let Some(Ok(Event {
+            out: Out::Stdout(_) | Out::Stderr(_),
+            ..
+        })) = &event
+        {
+            self.lines += 1;
+        }
+        #[cfg(feature = "metrics")]
+        if event.is_some() {
+            crate::recorder::record_queued(-1.0);
+        }
+        event
+    }
+
+    pub(crate) fn front(&self) -> Option<&Result<Event, io::Error>> {
+        self.events.front()
+    }
+
+    /// Returns when the first queued item was read, or `Some(None)` if it is
+    /// an error.
+    pub(crate) fn front_at(&self) -> Option<Option<Instant>> {
+        let front = self.events.front()?;
+        Some(front.as_ref().ok().map(|event| event.at))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Returns whether more output may be read, which is only ever not the
+    /// case with [`QueuePolicy::Backpressure`].
+    pub(crate) fn has_room(&self) -> bool {
+        match self.capacity {
+            Some((capacity, QueuePolicy::Backpressure)) => self.events.len() < capacity,
+            _ => true,
+        }
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub(crate) fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    /// Returns how many lines of stdout and stderr have been read, whether
+    /// they were queued or not.
+    pub(crate) fn read_lines(&self) -> (u64, u64) {
+        self.read_lines
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for OutputQueue {
+    fn drop(&mut self) {
+        if !self.events.is_empty() {
+            crate::recorder::record_queued(-(self.events.len() as f64));
+        }
+    }
+}
+
+/// Returns whether `err` only replaces a single item, after which reading
+/// goes on, such as a line that was too long with [`LineOverflow::Error`] or
+/// failed to decode with [`InvalidUtf8::Error`].
+///
+/// [`LineOverflow::Error`]: crate::LineOverflow::Error
+/// [`InvalidUtf8::Error`]: crate::InvalidUtf8::Error
+pub(crate) fn is_recoverable(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::InvalidData
+}
+
+/// Returns the stream that `out` was read from, if it is output of a single
+/// stream.
+fn stream(out: &Out) -> Option<Stream> {
+    match out {
+        Out::Stdout(_) | Out::StdoutProgress(_) | Out::StdoutBytes(_) => Some(Stream::Stdout),
+        Out::Stderr(_) | Out::StderrProgress(_) | Out::StderrBytes(_) => Some(Stream::Stderr),
+        #[cfg(feature = "bytes")]
+        Out::StdoutShared(_) => Some(Stream::Stdout),
+        #[cfg(feature = "bytes")]
+        Out::StderrShared(_) => Some(Stream::Stderr),
+        _ => None,
+    }
+}
+
+/// Returns whether `out` was read from the child or from a stream read
+/// along with it, which counts towards the capacity of the queue.
+fn is_output(out: &Out) -> bool {
+    stream(out).is_some()
+        || matches!(
+            out,
+            Out::Fd { .. }
+                | Out::Source { .. }
+                | Out::Followed { .. }
+                | Out::Match { .. }
+                | Out::Truncated(_)
+        )
+}
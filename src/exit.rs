@@ -0,0 +1,68 @@
+use std::process::ExitStatus;
+
+/// How a child exited, without the platform-specific details of
+/// [`ExitStatus`].
+///
+/// Obtained from the status in [`Out::Done`](crate::Out::Done) with
+/// [`Out::exit_kind`](crate::Out::exit_kind) or [`ExitKind::from`].
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{ExitKind, ProcessReader};
+///
+/// for out in ProcessReader::start(Command::new("./crashy"))? {
+///     match out?.exit_kind() {
+///         Some(ExitKind::Code(code)) => println!("exited with {code}"),
+///         Some(ExitKind::Signaled { signal, .. }) => println!("killed by {signal}"),
+///         None => {}
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ExitKind {
+    /// The child exited on its own with this exit code.
+    Code(i32),
+    /// The child was terminated by a signal. Only happens on Unix.
+    Signaled {
+        /// The number of the signal, such as `libc::SIGSEGV`.
+        signal: i32,
+        /// Whether the child dumped core.
+        core_dumped: bool,
+    },
+}
+
+impl ExitKind {
+    /// Returns whether the child exited with code zero.
+    pub fn success(&self) -> bool {
+        *self == ExitKind::Code(0)
+    }
+}
+
+impl From<ExitStatus> for ExitKind {
+    fn from(status: ExitStatus) -> Self {
+        if let Some(code) = status.code() {
+            return ExitKind::Code(code);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            // Only statuses of stopped children have neither a code nor a
+            // signal, and those are never reported.
+            ExitKind::Signaled {
+                signal: status.signal().unwrap_or_default(),
+                core_dumped: status.core_dumped(),
+            }
+        }
+
+        #[cfg(not(unix))]
+        unreachable!("exit status without a code")
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+#[cfg(target_os = "linux")]
+use std::{
+    ffi::CString,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, OwnedFd},
+    },
+    path::Path,
+};
+
+#[cfg(target_os = "linux")]
+use crate::sys::PipeReader;
+use crate::{
+    builder::grow_read_buf,
+    event::OutputQueue,
+    streams::{LineFramer, LineFraming},
+    Out,
+};
+
+/// How often followed files are checked for appended lines, since they
+/// cannot be polled for them, where their directories cannot be watched.
+pub(crate) const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A file that is followed alongside the child's output, whose appended
+/// lines are yielded as [`Out::Followed`].
+pub(crate) struct FollowedFile {
+    name: Arc<str>,
+    path: PathBuf,
+    /// The file as it was opened, or `None` while it does not exist.
+    file: Option<File>,
+    framer: LineFramer,
+}
+
+impl FollowedFile {
+    /// Starts following the file at `path` from its current end, or from
+    /// its start once it is created if it does not exist yet.
+    pub(crate) fn open(
+        name: Arc<str>,
+        path: PathBuf,
+        framing: &LineFraming,
+    ) -> Result<Self, io::Error> {
+        let file = match File::open(&path) {
+            Ok(mut file) => {
+                file.seek(SeekFrom::End(0))?;
+                Some(file)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            name,
+            path,
+            file,
+            framer: framing.framer(),
+        })
+    }
+
+    /// Reads the lines appended since the last call. Unless `bounded` is
+    /// false, this stops early once the queue is full, returning whether it
+    /// did.
+    ///
+    /// A file that was truncated is read again from its start. Once another
+    /// file has been moved to its path, such as when logs are rotated, the
+    /// rest of the old file is read before following the new one from its
+    /// start.
+    pub(crate) fn read(
+        &mut self,
+        out_buf: &mut OutputQueue,
+        read_buf: &mut Vec<u8>,
+        bounded: bool,
+    ) -> Result<bool, io::Error> {
+        if let Some(file) = &mut self.file {
+            if file.metadata()?.len() < file.stream_position()? {
+                file.rewind()?;
+            }
+            if !self.drain(out_buf, read_buf, bounded)? {
+                return Ok(true);
+            }
+        }
+
+        let replaced = match (&self.file, fs::metadata(&self.path)) {
+            (_, Err(err)) if err.kind() == io::ErrorKind::NotFound => false,
+            (_, Err(err)) => return Err(err),
+            (None, Ok(_)) => true,
+            (Some(file), Ok(metadata)) => !same_file(&file.metadata()?, &metadata),
+        };
+        if replaced {
+            self.flush(out_buf);
+            self.file = match File::open(&self.path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                res => Some(res?),
+            };
+            return Ok(!self.drain(out_buf, read_buf, bounded)?);
+        }
+
+        Ok(false)
+    }
+
+    /// Reads the current file up to its end, returning whether it got there
+    /// before the queue was full, if `bounded`.
+    fn drain(
+        &mut self,
+        out_buf: &mut OutputQueue,
+        read_buf: &mut Vec<u8>,
+        bounded: bool,
+    ) -> Result<bool, io::Error> {
+        let Self {
+            name, file, framer, ..
+        } = self;
+        let Some(file) = file else {
+            return Ok(true);
+        };
+
+        loop {
+            if bounded && !out_buf.has_room() {
+                return Ok(false);
+            }
+
+            let n = match file.read(read_buf) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                res => res?,
+            };
+            if n == 0 {
+                return Ok(true);
+            }
+
+            out_buf.stamp();
+            framer.push(&read_buf[..n], out_buf, |line| Out::Followed {
+                name: name.clone(),
+                line,
+            });
+            grow_read_buf(read_buf, n);
+        }
+    }
+
+    /// Queues the incomplete last line, if any.
+    pub(crate) fn flush(&mut self, out_buf: &mut OutputQueue) {
+        let name = &self.name;
+        self.framer.flush(out_buf, |line| Out::Followed {
+            name: name.clone(),
+            line,
+        });
+    }
+}
+
+/// Watches the directories of the followed files with inotify, which makes
+/// it readable whenever one of the files may have changed, so that they do
+/// not have to be checked periodically.
+#[cfg(target_os = "linux")]
+pub(crate) struct FollowWatcher {
+    pub(crate) events: PipeReader,
+}
+
+#[cfg(target_os = "linux")]
+impl FollowWatcher {
+    /// Starts watching the directories of `files`, or returns `None` if
+    /// any of them cannot be watched, such as when it does not exist yet.
+    ///
+    /// Directories are watched rather than the files, so that files that are
+    /// created or moved into place are noticed too.
+    pub(crate) fn new(files: &[FollowedFile]) -> Option<Self> {
+        if files.is_empty() {
+            return None;
+        }
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd == -1 {
+            return None;
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mask = libc::IN_MODIFY | libc::IN_CREATE | libc::IN_MOVED_TO;
+        for file in files {
+            let dir = match file.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let dir = CString::new(dir.as_os_str().as_bytes()).ok()?;
+            if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), dir.as_ptr(), mask) } == -1 {
+                return None;
+            }
+        }
+
+        Some(Self {
+            events: PipeReader::from_fd(fd).ok()?,
+        })
+    }
+
+    /// Discards the events that have been reported, which only tell that
+    /// the files are to be checked.
+    pub(crate) fn drain(&mut self) -> Result<(), io::Error> {
+        // Large enough for any single event, which includes a file name.
+        let mut buf = [0; 4096];
+        loop {
+            match self.events.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Returns whether both metadata belong to the same file.
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+/// Files cannot be told apart on this platform, so a file that was replaced
+/// is only noticed if it is shorter.
+#[cfg(not(unix))]
+fn same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
@@ -0,0 +1,511 @@
+use std::{borrow::Cow, io};
+
+use memchr::{memchr, memchr2};
+
+use crate::{
+    ansi, decode::Decoder, event::OutputQueue, patterns::Patterns, tee::Tee, LineOverflow,
+    LinePool, Mode, Out,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+fn push_line(out_buf: &mut OutputQueue, which: Stream, line: String) {
+    match which {
+        Stream::Stdout => out_buf.push_back(Out::Stdout(line)),
+        Stream::Stderr => out_buf.push_back(Out::Stderr(line)),
+    };
+}
+
+fn push_bytes(out_buf: &mut OutputQueue, which: Stream, bytes: &[u8]) {
+    let chunk = bytes.to_vec();
+    match which {
+        Stream::Stdout => out_buf.push_back(Out::StdoutBytes(chunk)),
+        Stream::Stderr => out_buf.push_back(Out::StderrBytes(chunk)),
+    };
+}
+
+#[cfg(feature = "bytes")]
+fn push_shared(out_buf: &mut OutputQueue, which: Stream, line: bytes::Bytes) {
+    match which {
+        Stream::Stdout => out_buf.push_back(Out::StdoutShared(line)),
+        Stream::Stderr => out_buf.push_back(Out::StderrShared(line)),
+    };
+}
+
+/// How a record ended, which decides what is emitted for it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Record {
+    /// Terminated by the delimiter.
+    Delimited,
+    /// Cut off by the end of the stream or by splitting an overlong line.
+    Unterminated,
+    /// Terminated by a `\r` in [`Mode::Progress`].
+    Update,
+}
+
+/// The settings that can differ between stdout and stderr.
+#[derive(Clone, Debug)]
+pub(crate) struct StreamOptions {
+    pub(crate) delimiter: Vec<u8>,
+    pub(crate) patterns: Patterns,
+    pub(crate) strip_ansi: bool,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b"\n".to_vec(),
+            patterns: Patterns::default(),
+            strip_ansi: false,
+        }
+    }
+}
+
+/// Turns the bytes read from one stream into output items.
+#[derive(Debug)]
+pub(crate) struct Framer {
+    which: Stream,
+    mode: Mode,
+    delimiter: Vec<u8>,
+    max_line_length: Option<(usize, LineOverflow)>,
+    patterns: Patterns,
+    strip_ansi: bool,
+    tee: Option<Tee>,
+    decoder: Decoder,
+    /// The pool that buffers for lines are taken from, if any.
+    pool: Option<LinePool>,
+    /// The incomplete record read so far, without its delimiter.
+    buf: Vec<u8>,
+    /// Whether the last byte was a `\r` that has not been handled yet, in
+    /// [`Mode::Progress`].
+    pending_cr: bool,
+    /// The number of bytes discarded from the current record once it has
+    /// grown past the maximum length, if it has.
+    discarded: Option<usize>,
+    /// The last bytes of a record that is being discarded, which may be the
+    /// start of its delimiter.
+    tail: Vec<u8>,
+    /// The output read but not yet yielded in [`Mode::SharedLines`], which
+    /// lines are split off from.
+    #[cfg(feature = "bytes")]
+    shared: bytes::BytesMut,
+}
+
+impl Framer {
+    pub(crate) fn new(
+        which: Stream,
+        mode: Mode,
+        options: StreamOptions,
+        max_line_length: Option<(usize, LineOverflow)>,
+        decoder: Decoder,
+        tee: Option<Tee>,
+        pool: Option<LinePool>,
+    ) -> Self {
+        Self {
+            which,
+            mode,
+            delimiter: options.delimiter,
+            max_line_length,
+            patterns: options.patterns,
+            strip_ansi: options.strip_ansi,
+            tee,
+            decoder,
+            pool,
+            buf: Vec::new(),
+            pending_cr: false,
+            discarded: None,
+            tail: Vec::new(),
+            #[cfg(feature = "bytes")]
+            shared: bytes::BytesMut::new(),
+        }
+    }
+
+    /// Turns freshly read `bytes` into output items according to the mode,
+    /// keeping an incomplete record for the next call.
+    pub(crate) fn push(&mut self, bytes: &[u8], out_buf: &mut OutputQueue) {
+        if let Some(tee) = &self.tee {
+            if let Err(err) = tee.write(bytes) {
+                out_buf.push_error(err);
+            }
+            if tee.is_only() {
+                return;
+            }
+        }
+
+        match self.mode {
+            Mode::Lines | Mode::ByteLines => {}
+            Mode::Progress => return self.push_progress(bytes, out_buf),
+            Mode::RawLines => return self.push_slice(bytes, false, out_buf),
+            Mode::Raw => return self.push_raw(bytes, out_buf),
+            Mode::Bytes => return push_bytes(out_buf, self.which, bytes),
+            #[cfg(feature = "bytes")]
+            Mode::SharedLines => return self.push_shared(bytes, out_buf),
+        }
+
+        // Carriage returns are only dropped for the default delimiter, where
+        // they come from `\r\n` line endings. With any other delimiter they
+        // may well be part of the data.
+        let strip_cr = self.delimiter == b"\n";
+        self.push_slice(bytes, strip_cr, out_buf);
+    }
+
+    /// Emits `bytes` as a chunk of text. In strict mode, a UTF-8 sequence
+    /// that is cut off at the end is held back until the rest is read.
+    fn push_raw(&mut self, bytes: &[u8], out_buf: &mut OutputQueue) {
+        if !self.decoder.is_strict() {
+            let bytes = match self.strip_ansi {
+                true => ansi::strip(bytes),
+                false => Cow::Borrowed(bytes),
+            };
+            return push_line(out_buf, self.which, self.decoder.decode(&bytes));
+        }
+
+        self.buf.extend_from_slice(bytes);
+        let incomplete = self.decoder.incomplete_suffix(&self.buf);
+        let rest = self.buf.split_off(self.buf.len() - incomplete);
+        if !self.buf.is_empty() {
+            self.end_raw(out_buf);
+        }
+        self.buf = rest;
+    }
+
+    fn end_raw(&mut self, out_buf: &mut OutputQueue) {
+        self.strip_escapes();
+        match self.decoder.decode_item(&self.buf) {
+            Ok(text) => push_line(out_buf, self.which, text),
+            Err(err) => out_buf.push_error(err),
+        }
+        self.buf.clear();
+    }
+
+    /// Splits off every complete line from the shared buffer without copying
+    /// it.
+    #[cfg(feature = "bytes")]
+    fn push_shared(&mut self, bytes: &[u8], out_buf: &mut OutputQueue) {
+        let delimiter_len = self.delimiter.len();
+        // Only the end of what was already read can still be the start of a
+        // delimiter.
+        let mut from = self.shared.len().saturating_sub(delimiter_len - 1);
+        self.shared.extend_from_slice(bytes);
+
+        while let Some(pos) = memchr::memmem::find(&self.shared[from..], &self.delimiter) {
+            let end = from + pos;
+            let mut line = self.shared.split_to(end + delimiter_len).freeze();
+            line.truncate(end);
+            if self.delimiter == b"\n" && line.ends_with(b"\r") {
+                line.truncate(end - 1);
+            }
+            push_shared(out_buf, self.which, line);
+            from = 0;
+        }
+    }
+
+    /// Like [`Mode::Lines`], but a `\r` that is not part of `\r\n` ends a
+    /// progress update instead of being dropped.
+    fn push_progress(&mut self, bytes: &[u8], out_buf: &mut OutputQueue) {
+        let mut rest = bytes;
+        while let Some(&next) = rest.first() {
+            if self.pending_cr {
+                self.pending_cr = false;
+                if next != b'\n' {
+                    self.end_unterminated(Record::Update, out_buf);
+                }
+            }
+
+            let Some(cr) = memchr(b'\r', rest) else {
+                return self.push_slice(rest, false, out_buf);
+            };
+            self.push_slice(&rest[..cr], false, out_buf);
+            self.pending_cr = true;
+            rest = &rest[cr + 1..];
+        }
+    }
+
+    /// Like calling [`push_byte`](Self::push_byte) for every byte, skipping
+    /// `\r` if `strip_cr`, but copies everything up to the next byte that
+    /// can end a record at once.
+    fn push_slice(&mut self, bytes: &[u8], strip_cr: bool, out_buf: &mut OutputQueue) {
+        let last = self.delimiter[self.delimiter.len() - 1];
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let end = match strip_cr {
+                true => memchr2(last, b'\r', rest),
+                false => memchr(last, rest),
+            };
+            let Some(end) = end else {
+                return self.extend(rest, out_buf);
+            };
+
+            self.extend(&rest[..end], out_buf);
+            if !(strip_cr && rest[end] == b'\r') {
+                self.push_byte(rest[end], out_buf);
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+
+    /// Appends `bytes`, which cannot end the record, to the current record,
+    /// or discards them if it has grown past the maximum length.
+    fn extend(&mut self, bytes: &[u8], out_buf: &mut OutputQueue) {
+        let delimiter_len = self.delimiter.len();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            if let Some(discarded) = &mut self.discarded {
+                // Only keep as much as could still turn out to be the
+                // delimiter.
+                self.tail.extend_from_slice(rest);
+                let excess = self.tail.len().saturating_sub(delimiter_len - 1);
+                *discarded += excess;
+                self.tail.drain(..excess);
+                return;
+            }
+
+            // Copy as much as fits before the record is too long, and leave
+            // the byte that makes it too long to `push_byte`.
+            let room = match self.max_line_length {
+                Some((max, _)) => (max + delimiter_len - 1).saturating_sub(self.buf.len()),
+                None => rest.len(),
+            };
+            let (fits, excess) = rest.split_at(room.min(rest.len()));
+            self.buf.extend_from_slice(fits);
+            let Some((&byte, excess)) = excess.split_first() else {
+                return;
+            };
+            self.push_byte(byte, out_buf);
+            rest = excess;
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8, out_buf: &mut OutputQueue) {
+        let delimiter_len = self.delimiter.len();
+
+        if let Some(discarded) = &mut self.discarded {
+            self.tail.push(byte);
+            if self.tail.ends_with(&self.delimiter) {
+                return self.end_record(Record::Delimited, out_buf);
+            }
+
+            // Only keep as much as could still turn out to be the delimiter.
+            let excess = self.tail.len() - (delimiter_len - 1);
+            *discarded += excess;
+            self.tail.drain(..excess);
+            return;
+        }
+
+        self.buf.push(byte);
+        if self.buf.ends_with(&self.delimiter) {
+            self.buf.truncate(self.buf.len() - delimiter_len);
+            return self.end_record(Record::Delimited, out_buf);
+        }
+
+        // The record is only known to be too long once the bytes past the
+        // maximum can no longer be the start of its delimiter.
+        let Some((max, overflow)) = self.max_line_length else {
+            return;
+        };
+        if self.buf.len() < max + delimiter_len {
+            return;
+        }
+
+        self.overflow(max, overflow, delimiter_len - 1, out_buf);
+    }
+
+    /// Handles a record that has grown past `max` bytes, where the last
+    /// `held_back` bytes may still be the start of the delimiter.
+    fn overflow(
+        &mut self,
+        max: usize,
+        overflow: LineOverflow,
+        held_back: usize,
+        out_buf: &mut OutputQueue,
+    ) {
+        match overflow {
+            LineOverflow::Split => {
+                while self.buf.len() > max + held_back {
+                    let rest = self.buf.split_off(max);
+                    self.end_record(Record::Unterminated, out_buf);
+                    self.buf = rest;
+                }
+            }
+            LineOverflow::Truncate | LineOverflow::Error => {
+                self.tail = self.buf.split_off(max);
+                let excess = self.tail.len() - held_back.min(self.tail.len());
+                self.discarded = Some(excess);
+                self.tail.drain(..excess);
+
+                if overflow == LineOverflow::Error {
+                    out_buf.push_error(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "line exceeds the maximum length",
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Ends the current record without a delimiter, first handling the bytes
+    /// past the maximum length that were held back in case they started one.
+    fn end_unterminated(&mut self, record: Record, out_buf: &mut OutputQueue) {
+        if let (None, Some((max, overflow))) = (self.discarded, self.max_line_length) {
+            if self.buf.len() > max {
+                self.overflow(max, overflow, 0, out_buf);
+            }
+        }
+
+        self.end_record(record, out_buf);
+    }
+
+    /// Emits the current record, followed by [`Out::Truncated`] if part of it
+    /// was discarded.
+    fn end_record(&mut self, record: Record, out_buf: &mut OutputQueue) {
+        let discarded = self.discarded.take().map(|discarded| {
+            let delimiter_len = match record {
+                Record::Delimited => self.delimiter.len(),
+                _ => 0,
+            };
+            discarded + self.tail.len() - delimiter_len
+        });
+        self.tail.clear();
+
+        // With `LineOverflow::Error` the error has already been emitted in
+        // place of the record.
+        if discarded.is_some() && matches!(self.max_line_length, Some((_, LineOverflow::Error))) {
+            self.buf.clear();
+            return;
+        }
+
+        // Empty updates, such as the one before a leading `\r`, are skipped.
+        if record == Record::Update && self.buf.is_empty() && discarded.is_none() {
+            return;
+        }
+
+        if self.mode != Mode::RawLines {
+            self.strip_escapes();
+        }
+
+        let line = match self.mode {
+            Mode::RawLines | Mode::ByteLines => String::new(),
+            _ => match self.decode_line() {
+                Ok(line) => line,
+                Err(err) => {
+                    out_buf.push_error(err);
+                    self.buf.clear();
+                    return;
+                }
+            },
+        };
+
+        match record {
+            Record::Update => {
+                match self.which {
+                    Stream::Stdout => out_buf.push_back(Out::StdoutProgress(line)),
+                    Stream::Stderr => out_buf.push_back(Out::StderrProgress(line)),
+                };
+            }
+            _ if self.mode == Mode::ByteLines => push_bytes(out_buf, self.which, &self.buf),
+            _ if self.mode == Mode::RawLines => {
+                if record == Record::Delimited {
+                    self.buf.extend_from_slice(&self.delimiter);
+                }
+                push_bytes(out_buf, self.which, &self.buf);
+            }
+            _ => {
+                let (keep, matches) = self.patterns.apply(&line);
+                if keep {
+                    push_line(out_buf, self.which, line);
+                } else if let Some(pool) = &self.pool {
+                    pool.put(line);
+                }
+                for found in matches {
+                    out_buf.push_back(found);
+                }
+            }
+        }
+        self.buf.clear();
+
+        if let Some(discarded) = discarded {
+            out_buf.push_back(Out::Truncated(discarded));
+        }
+    }
+
+    /// Returns which of the child's streams this frames.
+    pub(crate) fn which(&self) -> Stream {
+        self.which
+    }
+
+    /// Returns the file that output is only written to, if it is not
+    /// yielded.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn tee_only(&self) -> Option<&Tee> {
+        self.tee.as_ref().filter(|tee| tee.is_only())
+    }
+
+    /// Decodes the current record as a line, into a buffer from the pool if
+    /// there is one.
+    fn decode_line(&self) -> Result<String, io::Error> {
+        match &self.pool {
+            Some(pool) => self.decoder.decode_item_into(&self.buf, pool.take()),
+            None => self.decoder.decode_item(&self.buf),
+        }
+    }
+
+    /// Removes escape sequences from the current record, if enabled.
+    fn strip_escapes(&mut self) {
+        if !self.strip_ansi {
+            return;
+        }
+
+        if let Cow::Owned(stripped) = ansi::strip(&self.buf) {
+            self.buf = stripped;
+        }
+    }
+
+    /// Returns the incomplete record read so far as text, unless part of it
+    /// has already been discarded.
+    #[cfg_attr(not(feature = "regex"), allow(dead_code))]
+    pub(crate) fn partial(&self) -> Option<String> {
+        match self.discarded {
+            Some(_) => None,
+            None => Some(self.decoder.decode(&self.buf)),
+        }
+    }
+
+    /// Discards the incomplete record read so far.
+    #[cfg_attr(not(feature = "regex"), allow(dead_code))]
+    pub(crate) fn clear_partial(&mut self) {
+        self.buf.clear();
+        self.pending_cr = false;
+    }
+
+    /// Emits whatever is left as a final, unterminated record.
+    pub(crate) fn flush(&mut self, out_buf: &mut OutputQueue) {
+        #[cfg(feature = "bytes")]
+        if self.mode == Mode::SharedLines {
+            if !self.shared.is_empty() {
+                let rest = self.shared.split().freeze();
+                push_shared(out_buf, self.which, rest);
+            }
+            return;
+        }
+
+        if self.mode == Mode::Raw {
+            if !self.buf.is_empty() {
+                self.end_raw(out_buf);
+            }
+            return;
+        }
+
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.end_unterminated(Record::Update, out_buf);
+        }
+
+        if !self.buf.is_empty() || self.discarded.is_some() {
+            self.end_unterminated(Record::Unterminated, out_buf);
+        }
+    }
+}
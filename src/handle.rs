@@ -0,0 +1,171 @@
+use std::{
+    io,
+    process::ExitStatus,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+#[cfg(windows)]
+use std::os::windows::io::{AsHandle, OwnedHandle};
+
+use crate::sys;
+#[cfg(target_os = "linux")]
+use crate::Cgroup;
+#[cfg(unix)]
+use crate::ProcessBackend;
+
+/// What the reader knows about its child, shared with its handles.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum State {
+    Running,
+    Exited(ExitStatus),
+    /// The reader was dropped without reaping the child.
+    Detached,
+}
+
+#[derive(Debug)]
+pub(crate) struct Shared {
+    pid: u32,
+    /// What signals the child.
+    #[cfg(unix)]
+    backend: Arc<dyn ProcessBackend>,
+    /// A handle to the child that keeps it from being reused while open.
+    #[cfg(windows)]
+    process: OwnedHandle,
+    /// The cgroup the child was placed in, if any.
+    #[cfg(target_os = "linux")]
+    pub(crate) cgroup: Option<Cgroup>,
+    /// Held while the child is reaped, so that handles never signal a process
+    /// ID that may already have been reused.
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    pub(crate) fn new(
+        child: &sys::Child,
+        #[cfg(unix)] backend: Arc<dyn ProcessBackend>,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            pid: child.id(),
+            #[cfg(unix)]
+            backend,
+            #[cfg(windows)]
+            process: child.as_handle().try_clone_to_owned()?,
+            #[cfg(target_os = "linux")]
+            cgroup: None,
+            state: Mutex::new(State::Running),
+            changed: Condvar::new(),
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_cgroup(self, cgroup: Option<Cgroup>) -> Self {
+        Self { cgroup, ..self }
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Records a new state and wakes up anyone waiting on it.
+    pub(crate) fn set(&self, state: &mut MutexGuard<'_, State>, new: State) {
+        **state = new;
+        self.changed.notify_all();
+    }
+}
+
+/// A handle for controlling a [`ProcessReader`](crate::ProcessReader)'s child
+/// from other threads while its output is being read.
+///
+/// Created with [`ProcessReader::handle`](crate::ProcessReader::handle).
+/// Handles can be cloned freely and outlive the reader.
+#[derive(Clone, Debug)]
+pub struct ProcessHandle {
+    shared: Arc<Shared>,
+}
+
+impl ProcessHandle {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        Self { shared }
+    }
+
+    /// Returns the OS-assigned process identifier of the child.
+    pub fn id(&self) -> u32 {
+        self.shared.pid
+    }
+
+    /// Forcefully kills the child, along with its process group if it leads
+    /// one. On Unix, this sends `SIGKILL` like [`signal`](Self::signal).
+    ///
+    /// Nothing happens once the child has exited. The reader still yields
+    /// the remaining output and the exit status.
+    pub fn kill(&self) -> Result<(), io::Error> {
+        let state = self.shared.lock();
+        if !matches!(*state, State::Running) {
+            return Ok(());
+        }
+
+        #[cfg(unix)]
+        return self.shared.backend.signal(self.shared.pid, libc::SIGKILL);
+
+        #[cfg(windows)]
+        return sys::terminate(&self.shared.process);
+    }
+
+    /// Sends `signal` to the child, or to its process group if it leads one.
+    /// With a [`process_backend`](crate::ProcessReaderBuilder::process_backend),
+    /// the backend sends it.
+    ///
+    /// Nothing is sent once the child has exited. See
+    /// [`ProcessReader::signal`](crate::ProcessReader::signal).
+    #[cfg(unix)]
+    pub fn signal(&self, signal: i32) -> Result<(), io::Error> {
+        let state = self.shared.lock();
+        if !matches!(*state, State::Running) {
+            return Ok(());
+        }
+
+        self.shared.backend.signal(self.shared.pid, signal)
+    }
+
+    /// Returns the cgroup the child was placed in with
+    /// [`cgroup`](crate::ProcessReaderBuilder::cgroup), if any.
+    #[cfg(target_os = "linux")]
+    pub fn cgroup(&self) -> Option<&Cgroup> {
+        self.shared.cgroup.as_ref()
+    }
+
+    /// Returns the child's exit status if the reader has seen it exit.
+    pub fn try_wait(&self) -> Option<ExitStatus> {
+        match *self.shared.lock() {
+            State::Exited(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Blocks until the child has exited and returns its exit status.
+    ///
+    /// The child is reaped by the reader, so this only returns while its
+    /// output is being read. It fails if the reader is dropped without
+    /// waiting for the child, as with [`DropPolicy::Detach`](crate::DropPolicy::Detach).
+    pub fn wait(&self) -> Result<ExitStatus, io::Error> {
+        let mut state = self.shared.lock();
+        loop {
+            match *state {
+                State::Running => {}
+                State::Exited(status) => return Ok(status),
+                State::Detached => {
+                    return Err(io::Error::other(
+                        "reader was dropped without waiting for the child",
+                    ))
+                }
+            }
+
+            state = self
+                .shared
+                .changed
+                .wait(state)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+}
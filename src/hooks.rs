@@ -0,0 +1,26 @@
+use std::{fmt, process::ExitStatus};
+
+use crate::Out;
+
+/// A boxed callback, which debug-prints without its contents.
+pub(crate) struct Hook<F: ?Sized>(pub(crate) Box<F>);
+
+impl<F: ?Sized> fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hook(..)")
+    }
+}
+
+pub(crate) type OutputHook = Hook<dyn FnMut(&Out) + Send>;
+
+/// Callbacks registered with
+/// [`on_spawn`](crate::ProcessReaderBuilder::on_spawn),
+/// [`on_line`](crate::ProcessReaderBuilder::on_line) and
+/// [`on_exit`](crate::ProcessReaderBuilder::on_exit).
+#[derive(Debug, Default)]
+pub(crate) struct Hooks {
+    pub(crate) spawn: Option<Hook<dyn FnOnce(u32) + Send>>,
+    /// Called with every output item as it is queued.
+    pub(crate) output: Option<OutputHook>,
+    pub(crate) exit: Option<Hook<dyn FnOnce(ExitStatus) + Send>>,
+}
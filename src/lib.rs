@@ -0,0 +1,310 @@
+//! Incrementally read the output of a child process.
+//!
+//! [`ProcessReader`] spawns a [`Command`](std::process::Command) with its
+//! stdout and stderr attached to non-blocking pipes and yields each line as it
+//! arrives, followed by the exit status once the child has finished.
+//!
+//! ```no_run
+//! use std::process::Command;
+//! use incremental_command::{Out, ProcessReader};
+//!
+//! let reader = ProcessReader::start(Command::new("ls"))?;
+//! for out in reader {
+//!     match out? {
+//!         Out::Stdout(line) => println!("{line}"),
+//!         Out::Stderr(line) => eprintln!("{line}"),
+//!         Out::Done(status) => println!("exited with {status}"),
+//!         _ => {}
+//!     }
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! With the `tracing` feature, every child also reports its spawn, each line
+//! of output and its exit as [`tracing`](https://docs.rs/tracing) events, in
+//! a `process` span with its PID and program. With the `opentelemetry`
+//! feature, every run is recorded as a span of the global tracer provider,
+//! with the command, its arguments, exit code and duration as attributes and
+//! an event for every pattern match.
+//!
+//! The `metrics` feature records the following through the
+//! [`metrics`](https://docs.rs/metrics) facade, to be exported by whichever
+//! recorder is installed, such as a Prometheus exporter:
+//!
+//! - `incremental_command_spawned_total`: children spawned.
+//! - `incremental_command_running`: children whose reader is still alive and
+//!   has not reported their exit yet.
+//! - `incremental_command_exited_total`: children that exited, by `code`.
+//! - `incremental_command_signaled_total`: children killed by a signal, by
+//!   `signal`.
+//! - `incremental_command_read_bytes_total`: bytes read, by `stream`.
+//! - `incremental_command_queued`: items read but not yet yielded, across all
+//!   readers.
+
+use std::{path::PathBuf, process::ExitStatus, sync::Arc};
+
+mod ansi;
+#[cfg(feature = "futures")]
+mod async_reader;
+#[cfg(target_os = "linux")]
+mod attach;
+mod backend;
+mod background;
+mod builder;
+#[cfg(all(unix, feature = "calloop"))]
+mod calloop_reader;
+#[cfg(target_os = "linux")]
+mod cgroup;
+mod core_dump;
+mod decode;
+#[cfg(unix)]
+mod detach;
+mod env;
+mod error;
+mod event;
+mod exit;
+mod follow;
+mod framing;
+mod handle;
+mod hooks;
+mod limit;
+#[cfg(unix)]
+mod mux;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+mod patterns;
+mod pipeline;
+mod pool;
+mod process;
+#[cfg(unix)]
+mod process_backend;
+mod reader;
+#[cfg(feature = "metrics")]
+mod recorder;
+mod rlimit;
+mod sample;
+#[cfg(target_os = "linux")]
+mod seccomp;
+#[cfg(feature = "serde")]
+mod serialize;
+mod set;
+mod sink;
+mod spill;
+mod split;
+mod stats;
+mod stdin;
+mod streams;
+mod supervisor;
+mod sys;
+mod tee;
+mod throttle;
+#[cfg(feature = "tokio")]
+mod tokio_reader;
+#[cfg(feature = "tracing")]
+mod trace;
+mod usage;
+
+#[cfg(feature = "futures")]
+pub use async_reader::AsyncProcessReader;
+#[cfg(target_os = "linux")]
+pub use attach::AttachedReader;
+#[cfg(unix)]
+pub use backend::Ssh;
+#[cfg(target_os = "linux")]
+pub use backend::Systemd;
+pub use backend::{Backend, Container, Local};
+pub use background::ReaderThread;
+#[cfg(target_os = "linux")]
+pub use builder::Namespace;
+pub use builder::{
+    DropPolicy, IdleAction, LineOverflow, Mode, OutputLimit, ProcessReaderBuilder, QueuePolicy,
+    RateLimit, RatePolicy, Sampling, SchedulingPolicy, Truncation,
+};
+#[cfg(all(unix, feature = "calloop"))]
+pub use calloop_reader::CalloopProcessReader;
+#[cfg(target_os = "linux")]
+pub use cgroup::{Cgroup, CgroupStats};
+pub use decode::InvalidUtf8;
+#[cfg(unix)]
+pub use detach::{DetachedJob, DetachedReader};
+pub use error::Error;
+pub use event::Event;
+pub use exit::ExitKind;
+pub use handle::ProcessHandle;
+#[cfg(unix)]
+pub use mux::{Multiplexer, StreamConfig, StreamId, StreamOut};
+#[cfg(feature = "regex")]
+pub use patterns::OnMatch;
+pub use pipeline::Pipeline;
+pub use pool::{LinePool, Pooled};
+#[cfg(unix)]
+pub use process_backend::{ChildStreams, Pipes, ProcessBackend, StreamRequest};
+pub use reader::{Batches, Completion, Next, ProcessReader};
+pub use rlimit::Resource;
+#[cfg(target_os = "linux")]
+pub use seccomp::SeccompFilter;
+#[cfg(feature = "serde")]
+pub use serialize::write_json_line;
+pub use set::{run_all, ProcessId, ProcessSet, RunAll, Tagged};
+#[cfg(feature = "log")]
+pub use sink::LogSink;
+pub use sink::OutputSink;
+pub use spill::{Spill, SpillReader, SpilledCompletion};
+pub use split::StreamLines;
+pub use stats::{Histogram, Stats, StreamTiming};
+pub use stdin::Stdin;
+pub use supervisor::{RestartPolicy, Supervisor, SupervisorEvent};
+pub use tee::Rotation;
+#[cfg(feature = "tokio")]
+pub use tokio_reader::TokioProcessReader;
+pub use usage::ResourceUsage;
+
+/// A single piece of output produced by a [`ProcessReader`].
+///
+/// With the `serde` feature, items are serialized with their kind in a `kind`
+/// field and their contents, if any, in a `data` field, such as
+/// `{"kind":"stdout","data":"hello"}`. Exit statuses are serialized as their
+/// `code` and `signal`.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "kind", content = "data", rename_all = "snake_case")
+)]
+pub enum Out {
+    /// A line written to stdout, without the trailing newline.
+    Stdout(String),
+    /// A line written to stderr, without the trailing newline.
+    Stderr(String),
+    /// An in-place update of the current stdout line, in [`Mode::Progress`].
+    /// It replaces the previous update, if any.
+    StdoutProgress(String),
+    /// An in-place update of the current stderr line, in [`Mode::Progress`].
+    /// It replaces the previous update, if any.
+    StderrProgress(String),
+    /// A chunk of bytes written to stdout, in [`Mode::Bytes`], or a line in
+    /// [`Mode::RawLines`] and [`Mode::ByteLines`].
+    StdoutBytes(Vec<u8>),
+    /// A chunk of bytes written to stderr, in [`Mode::Bytes`], or a line in
+    /// [`Mode::RawLines`] and [`Mode::ByteLines`].
+    StderrBytes(Vec<u8>),
+    /// A line written to stdout, without its delimiter, in
+    /// [`Mode::SharedLines`].
+    #[cfg(feature = "bytes")]
+    StdoutShared(bytes::Bytes),
+    /// A line written to stderr, without its delimiter, in
+    /// [`Mode::SharedLines`].
+    #[cfg(feature = "bytes")]
+    StderrShared(bytes::Bytes),
+    /// A line written to an additional file descriptor captured with
+    /// [`capture_fd`](ProcessReaderBuilder::capture_fd), without its
+    /// delimiter.
+    Fd {
+        /// The child's file descriptor that the line was written to.
+        fd: i32,
+        /// The line itself.
+        line: String,
+    },
+    /// A line read from a file descriptor added with
+    /// [`source`](ProcessReaderBuilder::source), without its delimiter.
+    Source {
+        /// The name that the source was added under.
+        name: Arc<str>,
+        /// The line itself.
+        line: String,
+    },
+    /// A line appended to a file followed with
+    /// [`follow_file`](ProcessReaderBuilder::follow_file), without its
+    /// delimiter.
+    Followed {
+        /// The name that the file is followed under.
+        name: Arc<str>,
+        /// The line itself.
+        line: String,
+    },
+    /// A line matched a pattern registered with
+    /// [`stdout_pattern`](ProcessReaderBuilder::stdout_pattern) or
+    /// [`stderr_pattern`](ProcessReaderBuilder::stderr_pattern).
+    Match {
+        /// The position of the pattern among all registered patterns,
+        /// starting at zero.
+        pattern_id: usize,
+        /// The capture groups of the match, where the first is the whole
+        /// match and groups that did not participate are `None`.
+        captures: Vec<Option<String>>,
+        /// The line that matched.
+        line: String,
+    },
+    /// The preceding line was longer than the configured
+    /// [`max_line_length`](ProcessReaderBuilder::max_line_length), and this
+    /// many bytes were discarded from its end.
+    Truncated(usize),
+    /// Stdout exceeded the configured
+    /// [`output_limit`](ProcessReaderBuilder::output_limit), and this many
+    /// items were dropped from it. With [`Truncation::Middle`], the kept end
+    /// of stdout follows.
+    StdoutElided {
+        /// The number of lines, or chunks in modes that do not split lines,
+        /// that were dropped.
+        dropped_lines: u64,
+    },
+    /// Stderr exceeded the configured
+    /// [`output_limit`](ProcessReaderBuilder::output_limit). See
+    /// [`Out::StdoutElided`].
+    StderrElided {
+        /// The number of lines, or chunks in modes that do not split lines,
+        /// that were dropped.
+        dropped_lines: u64,
+    },
+    /// Stdout exceeded the configured
+    /// [`rate_limit`](ProcessReaderBuilder::rate_limit), and this many items
+    /// were suppressed since the last item that was yielded from it.
+    StdoutSuppressed {
+        /// The number of items suppressed.
+        count: u64,
+    },
+    /// Stderr exceeded the configured
+    /// [`rate_limit`](ProcessReaderBuilder::rate_limit). See
+    /// [`Out::StdoutSuppressed`].
+    StderrSuppressed {
+        /// The number of items suppressed.
+        count: u64,
+    },
+    /// The child ran longer than the configured
+    /// [`timeout`](ProcessReaderBuilder::timeout) and was killed.
+    /// [`Out::Done`] follows once its remaining output has been read.
+    TimedOut,
+    /// Nothing was read from the child for the configured
+    /// [`idle_timeout`](ProcessReaderBuilder::idle_timeout).
+    Idle,
+    /// The resources the child used, right before [`Out::Done`], if
+    /// [`resource_usage`](ProcessReaderBuilder::resource_usage) is enabled.
+    ResourceUsage(ResourceUsage),
+    /// The child dumped core when it was killed by a signal, right before
+    /// [`Out::Done`]. Holds the path of the core file if
+    /// [`locate_core_dumps`](ProcessReaderBuilder::locate_core_dumps) is
+    /// enabled and it was found.
+    CoreDumped(Option<PathBuf>),
+    /// The child was killed for exceeding a limit set with
+    /// [`rlimit`](ProcessReaderBuilder::rlimit), right before [`Out::Done`].
+    ///
+    /// Only [`Resource::Cpu`] and [`Resource::FileSize`] are reported, since
+    /// the kernel signals the child when it exceeds them. Exceeding the
+    /// other limits makes allocations or opening files fail in the child
+    /// instead, which it reports itself.
+    LimitExceeded(Resource),
+    /// The child has exited. This is always the last item, unless
+    /// [`check`](ProcessReaderBuilder::check) replaces it with an error.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::exit_status"))]
+    Done(ExitStatus),
+}
+
+impl Out {
+    /// Returns how the child exited, if this is [`Out::Done`].
+    pub fn exit_kind(&self) -> Option<ExitKind> {
+        match self {
+            Out::Done(status) => Some(ExitKind::from(*status)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+
+use crate::{event::Event, Out, OutputLimit, Truncation};
+
+/// Keeps the output of one stream within an [`OutputLimit`], counting the
+/// items it drops.
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    limit: OutputLimit,
+    truncation: Truncation,
+    /// How much of the limit the items yielded from the start have used.
+    head: usize,
+    /// Whether an item did not fit into the start anymore, after which no
+    /// further items are yielded right away.
+    head_full: bool,
+    /// The last items, held back until the end in [`Truncation::Middle`].
+    tail: VecDeque<Event>,
+    /// How much of the limit the items in `tail` use.
+    tail_size: usize,
+    dropped: u64,
+}
+
+impl Limiter {
+    pub(crate) fn new(limit: OutputLimit, truncation: Truncation) -> Self {
+        Self {
+            limit,
+            truncation,
+            head: 0,
+            head_full: false,
+            tail: VecDeque::new(),
+            tail_size: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Returns `event` if it is to be yielded right away, or `None` if it
+    /// was dropped or held back.
+    ///
+    /// [`Out::Truncated`] shares the fate of the line it follows.
+    pub(crate) fn admit(&mut self, event: Event) -> Option<Event> {
+        let size = self.size(&event.out);
+        let (head_budget, tail_budget) = self.budgets();
+
+        if !self.head_full {
+            if self.head + size <= head_budget {
+                self.head += size;
+                return Some(event);
+            }
+            self.head_full = true;
+        }
+
+        match self.truncation {
+            Truncation::Tail => {
+                if !matches!(event.out, Out::Truncated(_)) {
+                    self.dropped += 1;
+                }
+            }
+            Truncation::Middle => {
+                self.tail_size += size;
+                self.tail.push_back(event);
+                while self.tail_size > tail_budget {
+                    self.evict();
+                }
+            }
+        }
+        None
+    }
+
+    /// Drops the oldest item held back, along with any [`Out::Truncated`]
+    /// that belongs to it.
+    fn evict(&mut self) {
+        let Some(oldest) = self.tail.pop_front() else {
+            return;
+        };
+        self.tail_size -= self.size(&oldest.out);
+        self.dropped += 1;
+
+        while let Some(Event {
+            out: Out::Truncated(_),
+            ..
+        }) = self.tail.front()
+        {
+            self.tail.pop_front();
+        }
+    }
+
+    /// Returns how many items were dropped, and the items held back to be
+    /// yielded after the marker.
+    pub(crate) fn finish(&mut self) -> (u64, VecDeque<Event>) {
+        self.tail_size = 0;
+        (
+            std::mem::take(&mut self.dropped),
+            std::mem::take(&mut self.tail),
+        )
+    }
+
+    /// Returns how much of the limit `out` uses.
+    fn size(&self, out: &Out) -> usize {
+        match (self.limit, out) {
+            (_, Out::Truncated(_)) => 0,
+            (OutputLimit::Lines(_), _) => 1,
+            (
+                OutputLimit::Bytes(_),
+                Out::Stdout(text)
+                | Out::Stderr(text)
+                | Out::StdoutProgress(text)
+                | Out::StderrProgress(text),
+            ) => text.len(),
+            (OutputLimit::Bytes(_), Out::StdoutBytes(bytes) | Out::StderrBytes(bytes)) => {
+                bytes.len()
+            }
+            #[cfg(feature = "bytes")]
+            (OutputLimit::Bytes(_), Out::StdoutShared(bytes) | Out::StderrShared(bytes)) => {
+                bytes.len()
+            }
+            (OutputLimit::Bytes(_), _) => 0,
+        }
+    }
+
+    /// Returns how much of the limit is available to the start and to the
+    /// end of the output.
+    fn budgets(&self) -> (usize, usize) {
+        let max = match self.limit {
+            OutputLimit::Lines(max) | OutputLimit::Bytes(max) => max,
+        };
+        match self.truncation {
+            Truncation::Tail => (max, 0),
+            Truncation::Middle => (max - max / 2, max / 2),
+        }
+    }
+}
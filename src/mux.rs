@@ -0,0 +1,351 @@
+use std::{collections::VecDeque, io, os::unix::io::OwnedFd, sync::Arc, time::Instant};
+
+#[cfg(feature = "encoding")]
+use encoding_rs::Encoding;
+use mio::{Events, Poll, Token};
+
+use crate::{
+    builder::{non_empty_delimiter, DEFAULT_BUFFER_SIZE},
+    decode::Decoder,
+    event::OutputQueue,
+    framing::{Framer, Stream, StreamOptions},
+    streams::{ProcessStream, ReadEnd},
+    sys::PipeReader,
+    InvalidUtf8, LineOverflow, Mode, Out,
+};
+
+/// Identifies a stream in a [`Multiplexer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StreamId(usize);
+
+impl StreamId {
+    /// Returns the position of the stream in the order in which it was
+    /// added, starting at zero.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// How the output of a stream in a [`Multiplexer`] is framed and decoded.
+///
+/// The settings mirror those of the same name on
+/// [`ProcessReaderBuilder`](crate::ProcessReaderBuilder), and default to the
+/// same values.
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+    buffer_size: usize,
+    mode: Mode,
+    options: StreamOptions,
+    max_line_length: Option<(usize, LineOverflow)>,
+    decoder: Decoder,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            mode: Mode::default(),
+            options: StreamOptions::default(),
+            max_line_length: None,
+            decoder: Decoder::default(),
+        }
+    }
+}
+
+impl StreamConfig {
+    /// Creates a configuration with the defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size of the buffer that the stream is read into. Defaults to
+    /// 64 KiB.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "buffer size must be non-zero");
+        self.buffer_size = size;
+        self
+    }
+
+    /// Sets how output is framed. Defaults to [`Mode::Lines`].
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the byte sequence that separates records. Defaults to `\n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delimiter` is empty.
+    pub fn delimiter(mut self, delimiter: impl Into<Vec<u8>>) -> Self {
+        self.options.delimiter = non_empty_delimiter(delimiter.into());
+        self
+    }
+
+    /// Sets the maximum length of a line in bytes, not counting its
+    /// delimiter, and what happens to longer lines. Defaults to no limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn max_line_length(mut self, max: usize, overflow: LineOverflow) -> Self {
+        assert!(max > 0, "maximum line length must be non-zero");
+        self.max_line_length = Some((max, overflow));
+        self
+    }
+
+    /// Sets the encoding that the stream is written in. Defaults to UTF-8.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `encoding` is not compatible with ASCII, such as UTF-16.
+    #[cfg(feature = "encoding")]
+    pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+        assert!(
+            encoding.is_ascii_compatible(),
+            "encoding must be compatible with ASCII"
+        );
+        self.decoder.set_encoding(encoding);
+        self
+    }
+
+    /// Enables strict decoding, where `invalid` decides what happens to
+    /// output that is not valid in the stream's encoding.
+    pub fn strict_utf8(mut self, invalid: InvalidUtf8) -> Self {
+        self.decoder.set_strict(invalid);
+        self
+    }
+
+    /// Sets whether ANSI escape sequences are removed from lines. Defaults to
+    /// `false`.
+    pub fn strip_ansi(mut self, strip: bool) -> Self {
+        self.options.strip_ansi = strip;
+        self
+    }
+}
+
+/// A piece of output read from a stream in a [`Multiplexer`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamOut {
+    /// A line, without its delimiter.
+    Line(String),
+    /// An in-place update of the current line, in [`Mode::Progress`].
+    Progress(String),
+    /// A chunk of bytes in [`Mode::Bytes`], or a line in the modes that
+    /// yield lines as bytes.
+    Bytes(Vec<u8>),
+    /// The preceding line was longer than the configured
+    /// [`max_line_length`](StreamConfig::max_line_length), and this many
+    /// bytes were discarded from its end.
+    Truncated(usize),
+    /// The stream has ended. This is the last item of every stream.
+    Closed,
+}
+
+/// A stream that has not ended yet, read like the output of a process.
+struct Source {
+    stream: ProcessStream,
+    read_buf: Vec<u8>,
+}
+
+/// Reads any number of pipes on a single poll loop, each with its own
+/// buffer, delimiter and decoder.
+///
+/// Where a [`ProcessReader`](crate::ProcessReader) reads the stdout and
+/// stderr of one child, a multiplexer reads whichever streams are added to
+/// it, from one or many sources, such as the output of several children that
+/// were spawned elsewhere, or the read ends of pipes they inherited. Each
+/// stream is named when it is added.
+///
+/// Iterating yields the output of every stream tagged with its
+/// [`StreamId`], in the order in which it was read. Each stream ends with
+/// [`StreamOut::Closed`], and iteration ends once every stream has ended.
+/// Streams can be added at any time, even after iteration has ended.
+///
+/// Errors are not tagged, like those of a
+/// [`ProcessSet`](crate::ProcessSet).
+///
+/// ```no_run
+/// use std::process::{Command, Stdio};
+/// use incremental_command::{Multiplexer, StreamConfig, StreamOut};
+///
+/// let mut find = Command::new("find").arg("-print0").stdout(Stdio::piped()).spawn()?;
+/// let mut make = Command::new("make").stdout(Stdio::piped()).spawn()?;
+///
+/// let mut mux = Multiplexer::new()?;
+/// let config = StreamConfig::new().delimiter(b"\0");
+/// mux.add("find", find.stdout.take().unwrap(), config)?;
+/// mux.add("make", make.stdout.take().unwrap(), StreamConfig::new())?;
+///
+/// while let Some((id, out)) = mux.next().transpose()? {
+///     if let StreamOut::Line(line) = out {
+///         println!("[{}] {line}", mux.name(id));
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Multiplexer {
+    poll: Poll,
+    events: Events,
+    /// The streams by index, or `None` once they have ended.
+    sources: Vec<Option<Source>>,
+    names: Vec<Arc<str>>,
+    /// Items are framed into this queue and moved out of it with their tag
+    /// right away.
+    framed: OutputQueue,
+    queue: VecDeque<Result<(StreamId, StreamOut), io::Error>>,
+}
+
+impl Multiplexer {
+    /// Creates a multiplexer without any streams.
+    pub fn new() -> Result<Self, io::Error> {
+        Ok(Self {
+            poll: Poll::new()?,
+            events: Events::with_capacity(256),
+            sources: Vec::new(),
+            names: Vec::new(),
+            framed: OutputQueue::new(false, None),
+            queue: VecDeque::new(),
+        })
+    }
+
+    /// Adds the stream `fd`, such as a [`ChildStdout`](std::process::ChildStdout)
+    /// or the read end of a pipe, to be read with `config` under `name`.
+    ///
+    /// The stream is switched to non-blocking mode. This fails for regular
+    /// files, which cannot be polled.
+    pub fn add(
+        &mut self,
+        name: impl Into<Arc<str>>,
+        fd: impl Into<OwnedFd>,
+        config: StreamConfig,
+    ) -> Result<StreamId, io::Error> {
+        let StreamConfig {
+            buffer_size,
+            mode,
+            options,
+            max_line_length,
+            decoder,
+        } = config;
+
+        let id = StreamId(self.sources.len());
+        let mut reader = PipeReader::from_fd(fd.into())?;
+        reader.register(self.poll.registry(), Token(id.0))?;
+
+        // Every stream is framed as if it were stdout, and its items are
+        // turned into `StreamOut` once they have been framed.
+        let framer = Framer::new(
+            Stream::Stdout,
+            mode,
+            options,
+            max_line_length,
+            decoder,
+            None,
+            None,
+        );
+        self.sources.push(Some(Source {
+            stream: ProcessStream::output(Some(reader), framer, Instant::now()),
+            read_buf: vec![0; buffer_size],
+        }));
+        self.names.push(name.into());
+        Ok(id)
+    }
+
+    /// Returns the name that the stream was added under.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` belongs to another multiplexer.
+    pub fn name(&self, id: StreamId) -> &str {
+        &self.names[id.0]
+    }
+
+    /// Returns how many streams have not ended yet.
+    pub fn len(&self) -> usize {
+        self.sources.iter().flatten().count()
+    }
+
+    /// Returns whether every stream has ended.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Waits for any of the streams to become readable and reads them.
+    fn step(&mut self) -> Result<(), io::Error> {
+        match self.poll.poll(&mut self.events, None) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            res => res?,
+        }
+
+        let ready: Vec<_> = self.events.iter().map(|event| event.token().0).collect();
+        for index in ready {
+            self.read(StreamId(index))?;
+        }
+        Ok(())
+    }
+
+    /// Reads everything that is available from a stream, dropping it once
+    /// it has ended.
+    fn read(&mut self, id: StreamId) -> Result<(), io::Error> {
+        let Some(source) = self.sources.get_mut(id.0).and_then(Option::as_mut) else {
+            return Ok(());
+        };
+
+        // The queue is emptied right below, so it never fills up.
+        let (_, end) = source
+            .stream
+            .read(&mut self.framed, &mut source.read_buf, false)?;
+
+        while let Some(event) = self.framed.pop_front() {
+            let out = match event {
+                Ok(event) => event.out,
+                Err(err) => {
+                    self.queue.push_back(Err(err));
+                    continue;
+                }
+            };
+            let out = match out {
+                Out::Stdout(line) => StreamOut::Line(line),
+                Out::StdoutProgress(line) => StreamOut::Progress(line),
+                Out::StdoutBytes(bytes) => StreamOut::Bytes(bytes),
+                #[cfg(feature = "bytes")]
+                Out::StdoutShared(bytes) => StreamOut::Bytes(bytes.to_vec()),
+                Out::Truncated(discarded) => StreamOut::Truncated(discarded),
+                _ => continue,
+            };
+            self.queue.push_back(Ok((id, out)));
+        }
+
+        if end == ReadEnd::Eof {
+            let source = self.sources[id.0].take();
+            if let Some(mut reader) = source.and_then(|source| source.stream.read) {
+                reader.deregister(self.poll.registry())?;
+            }
+            self.queue.push_back(Ok((id, StreamOut::Closed)));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Multiplexer {
+    type Item = Result<(StreamId, StreamOut), io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(next) = self.queue.pop_front() {
+                return Some(next);
+            }
+            if self.is_empty() {
+                return None;
+            }
+
+            if let Err(err) = self.step() {
+                return Some(Err(err));
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    process::{Command, ExitStatus},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use opentelemetry::{
+    global::{self, BoxedSpan},
+    trace::{Span, Status, Tracer},
+    KeyValue,
+};
+
+use crate::{
+    hooks::{Hook, Hooks},
+    ExitKind, Out,
+};
+
+/// Wraps `hooks` so that the run of the child `pid` is recorded as an
+/// OpenTelemetry span from the global tracer provider, with an event for
+/// every pattern match.
+pub(crate) fn instrument(hooks: Hooks, pid: u32, cmd: &Command) -> Hooks {
+    let started = Instant::now();
+    let args: Vec<String> = cmd
+        .get_args()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let mut span = global::tracer("incremental-command").start("process");
+    span.set_attributes([
+        KeyValue::new("process.pid", i64::from(pid)),
+        KeyValue::new(
+            "process.command",
+            cmd.get_program().to_string_lossy().into_owned(),
+        ),
+        KeyValue::new("process.command_args", args.join(" ")),
+    ]);
+    let span = Arc::new(Mutex::new(span));
+
+    let Hooks {
+        spawn,
+        output,
+        exit,
+    } = hooks;
+
+    let output_span = span.clone();
+    let mut output = output;
+    let on_output = move |out: &Out| {
+        if let Out::Match {
+            pattern_id, line, ..
+        } = out
+        {
+            lock(&output_span).add_event(
+                "match",
+                vec![
+                    KeyValue::new("pattern_id", *pattern_id as i64),
+                    KeyValue::new("line", line.clone()),
+                ],
+            );
+        }
+        if let Some(hook) = &mut output {
+            (hook.0)(out);
+        }
+    };
+
+    let on_exit = move |status: ExitStatus| {
+        let mut span = lock(&span);
+        span.set_attribute(KeyValue::new(
+            "process.duration",
+            started.elapsed().as_secs_f64(),
+        ));
+        match ExitKind::from(status) {
+            ExitKind::Code(code) => {
+                span.set_attribute(KeyValue::new("process.exit.code", i64::from(code)));
+            }
+            ExitKind::Signaled { signal, .. } => {
+                span.set_attribute(KeyValue::new("process.exit.signal", i64::from(signal)));
+            }
+        }
+        if !status.success() {
+            span.set_status(Status::error(status.to_string()));
+        }
+        span.end();
+        drop(span);
+
+        if let Some(hook) = exit {
+            (hook.0)(status);
+        }
+    };
+
+    Hooks {
+        spawn,
+        output: Some(Hook(Box::new(on_output))),
+        exit: Some(Hook(Box::new(on_exit))),
+    }
+}
+
+fn lock(span: &Mutex<BoxedSpan>) -> std::sync::MutexGuard<'_, BoxedSpan> {
+    span.lock().unwrap_or_else(|err| err.into_inner())
+}
@@ -0,0 +1,81 @@
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+use crate::Out;
+
+/// What happens to a line that matches a pattern.
+///
+/// See [`ProcessReaderBuilder::stdout_pattern`](crate::ProcessReaderBuilder::stdout_pattern).
+#[cfg(feature = "regex")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnMatch {
+    /// Yield the line as usual, followed by [`Out::Match`].
+    Keep,
+    /// Yield only [`Out::Match`] in place of the line.
+    Replace,
+}
+
+#[cfg(feature = "regex")]
+#[derive(Clone, Debug)]
+struct Pattern {
+    id: usize,
+    regex: Regex,
+    on_match: OnMatch,
+}
+
+/// The patterns registered for one stream.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Patterns {
+    #[cfg(feature = "regex")]
+    patterns: Vec<Pattern>,
+}
+
+impl Patterns {
+    #[cfg(feature = "regex")]
+    pub(crate) fn push(&mut self, id: usize, regex: Regex, on_match: OnMatch) {
+        self.patterns.push(Pattern {
+            id,
+            regex,
+            on_match,
+        });
+    }
+
+    #[cfg(feature = "regex")]
+    pub(crate) fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Returns an [`Out::Match`] for every pattern that matches `line`, and
+    /// whether the line itself should still be yielded.
+    pub(crate) fn apply(&self, line: &str) -> (bool, Vec<Out>) {
+        #[cfg(feature = "regex")]
+        {
+            let mut keep = true;
+            let mut matches = Vec::new();
+
+            for pattern in &self.patterns {
+                let Some(captures) = pattern.regex.captures(line) else {
+                    continue;
+                };
+
+                keep &= pattern.on_match == OnMatch::Keep;
+                matches.push(Out::Match {
+                    pattern_id: pattern.id,
+                    captures: captures
+                        .iter()
+                        .map(|group| Some(group?.as_str().to_owned()))
+                        .collect(),
+                    line: line.to_owned(),
+                });
+            }
+
+            (keep, matches)
+        }
+
+        #[cfg(not(feature = "regex"))]
+        {
+            let _ = line;
+            (true, Vec::new())
+        }
+    }
+}
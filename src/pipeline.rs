@@ -0,0 +1,80 @@
+use std::io;
+
+use crate::{ProcessReaderBuilder, ProcessSet};
+
+/// A chain of commands whose stdout is piped into the stdin of the next, like
+/// `a | b | c` in a shell.
+///
+/// Unlike running the chain through a shell, every stage is a process of its
+/// own, so each one's stderr and exit status are reported separately.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{Out, Pipeline};
+///
+/// let pipeline = Pipeline::new(Command::new("ls")).pipe(Command::new("sort"));
+/// for next in pipeline.start()? {
+///     match next? {
+///         (_, Out::Stdout(line)) => println!("{line}"),
+///         (stage, Out::Done(status)) => println!("stage {} exited with {status}", stage.index()),
+///         _ => {}
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Pipeline {
+    stages: Vec<ProcessReaderBuilder>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline whose first stage is `first`.
+    ///
+    /// Stages can be given as a [`Command`](std::process::Command) or as a
+    /// [`ProcessReaderBuilder`] to configure how they are read.
+    pub fn new(first: impl Into<ProcessReaderBuilder>) -> Self {
+        Self {
+            stages: vec![first.into()],
+        }
+    }
+
+    /// Appends a stage that reads the stdout of the previous one.
+    pub fn pipe(mut self, stage: impl Into<ProcessReaderBuilder>) -> Self {
+        self.stages.push(stage.into());
+        self
+    }
+
+    /// Spawns every stage.
+    ///
+    /// The stages are read as a [`ProcessSet`] in which the [`ProcessId`]
+    /// index of each is its position in the pipeline. Only the last stage's
+    /// stdout is captured, while the stdout of every other stage is connected
+    /// to the stdin of the next. Each stage ends with its own
+    /// [`Out::Done`](crate::Out::Done).
+    ///
+    /// [`ProcessId`]: crate::ProcessId
+    pub fn start(self) -> Result<ProcessSet, io::Error> {
+        let mut set = ProcessSet::new()?;
+        let mut stdin = None;
+        let last = self.stages.len() - 1;
+
+        for (index, mut stage) in self.stages.into_iter().enumerate() {
+            stage.apply_backend()?;
+            if let Some(stdin) = stdin.take() {
+                stage.cmd.stdin(stdin);
+                stage.pipe_stdin = false;
+            }
+
+            if index != last {
+                let (read, write) = io::pipe()?;
+                stage.cmd.stdout(write);
+                stage.capture_stdout = false;
+                stdin = Some(read);
+            }
+
+            set.spawn(stage)?;
+        }
+
+        Ok(set)
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::Out;
+
+/// A pool of buffers that lines are stored in, so that a reader that runs
+/// for a long time does not allocate for every line once it has warmed up.
+///
+/// Lines yielded as [`Out::Stdout`], [`Out::Stderr`] and their progress
+/// updates take a buffer from the pool if one is available. Give it back
+/// with [`recycle`](Self::recycle) once the line has been handled, or wrap
+/// the item with [`guard`](Self::guard) to give it back when it is dropped.
+/// Lines that are never given back are simply freed.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{LinePool, ProcessReader};
+///
+/// let pool = LinePool::new(64);
+/// let reader = ProcessReader::builder(Command::new("my-daemon"))
+///     .line_pool(pool.clone())
+///     .start()?;
+/// for out in reader {
+///     let out = pool.guard(out?);
+///     println!("{:?}", *out);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct LinePool {
+    shared: Arc<PoolShared>,
+}
+
+#[derive(Debug)]
+struct PoolShared {
+    buffers: Mutex<Vec<String>>,
+    max_buffers: usize,
+}
+
+impl LinePool {
+    /// Creates an empty pool that keeps at most `max_buffers` buffers that
+    /// were given back. Any further buffers are freed.
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            shared: Arc::new(PoolShared {
+                buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+                max_buffers,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<String>> {
+        self.shared
+            .buffers
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Returns an empty buffer, reusing one from the pool if possible.
+    pub(crate) fn take(&self) -> String {
+        self.lock().pop().unwrap_or_default()
+    }
+
+    /// Gives the buffer of `line` back to the pool.
+    pub fn put(&self, mut line: String) {
+        if line.capacity() == 0 {
+            return;
+        }
+
+        let mut buffers = self.lock();
+        if buffers.len() < self.shared.max_buffers {
+            line.clear();
+            buffers.push(line);
+        }
+    }
+
+    /// Gives the buffer of the line in `out` back to the pool, if it holds
+    /// one.
+    pub fn recycle(&self, out: Out) {
+        match out {
+            Out::Stdout(line)
+            | Out::Stderr(line)
+            | Out::StdoutProgress(line)
+            | Out::StderrProgress(line) => self.put(line),
+            _ => {}
+        }
+    }
+
+    /// Wraps `out` so that its buffer is given back to the pool when it is
+    /// dropped.
+    pub fn guard(&self, out: Out) -> Pooled {
+        Pooled {
+            out: Some(out),
+            pool: self.clone(),
+        }
+    }
+
+    /// Returns how many buffers are waiting to be reused.
+    pub fn available(&self) -> usize {
+        self.lock().len()
+    }
+}
+
+/// An [`Out`] item whose buffer is given back to its [`LinePool`] when it is
+/// dropped, created with [`LinePool::guard`].
+#[derive(Debug)]
+pub struct Pooled {
+    out: Option<Out>,
+    pool: LinePool,
+}
+
+impl Pooled {
+    /// Returns the item, which is then no longer given back to the pool.
+    pub fn into_inner(mut self) -> Out {
+        self.out.take().expect("the item is only taken once")
+    }
+}
+
+impl Deref for Pooled {
+    type Target = Out;
+
+    fn deref(&self) -> &Out {
+        self.out.as_ref().expect("the item is only taken once")
+    }
+}
+
+impl DerefMut for Pooled {
+    fn deref_mut(&mut self) -> &mut Out {
+        self.out.as_mut().expect("the item is only taken once")
+    }
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        if let Some(out) = self.out.take() {
+            self.pool.recycle(out);
+        }
+    }
+}
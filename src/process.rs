@@ -0,0 +1,1018 @@
+use std::{
+    io,
+    process::{Child, ExitStatus},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mio::Token;
+
+#[cfg(target_os = "linux")]
+use crate::follow::FollowWatcher;
+use crate::{
+    core_dump::{self, CoreLocator},
+    event::{Event, OutputQueue},
+    follow::{FollowedFile, FOLLOW_INTERVAL},
+    framing::{Framer, Stream},
+    handle::{ProcessHandle, Shared, State},
+    hooks::Hook,
+    limit::Limiter,
+    rlimit::{self, Resource},
+    sample::Sampler,
+    streams::{LineFraming, ProcessStream, ReadEnd, Tag},
+    sys::{self, ExitWatcher, PipeReader, Register, SpawnOptions, Spawned},
+    tee::Tee,
+    throttle::Throttle,
+    DropPolicy, Error, IdleAction, Out, ProcessReaderBuilder, ResourceUsage, Stats, Stdin,
+};
+#[cfg(unix)]
+use crate::{Pipes, ProcessBackend};
+
+/// How often to check whether the child has exited after it closed its
+/// output streams, on platforms where its exit cannot wake up the poll loop.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Which of a process's sources a readiness event is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Source {
+    Child,
+    /// A change to one of the followed files.
+    Followed,
+    /// One of the streams, by its position.
+    Stream(usize),
+}
+
+/// Streams and exit notifications that woke up the poll loop for a process.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Readiness {
+    pub(crate) exited: bool,
+    pub(crate) followed: bool,
+    /// The streams that are ready, one bit per position.
+    streams: Vec<u64>,
+}
+
+impl Readiness {
+    pub(crate) fn set(&mut self, source: Source) {
+        match source {
+            Source::Child => self.exited = true,
+            Source::Followed => self.followed = true,
+            Source::Stream(index) => {
+                let (word, bit) = (index / 64, index % 64);
+                if self.streams.len() <= word {
+                    self.streams.resize(word + 1, 0);
+                }
+                self.streams[word] |= 1 << bit;
+            }
+        }
+    }
+
+    /// Returns whether the stream at `index` is ready.
+    fn stream(&self, index: usize) -> bool {
+        self.streams
+            .get(index / 64)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+}
+
+/// The sources registered with a poll loop by the token each is registered
+/// under, so that a process can read any number of streams.
+#[derive(Debug, Default)]
+pub(crate) struct Tokens {
+    /// The process and source of each token, or `None` once released.
+    sources: Vec<Option<(usize, Source)>>,
+    free: Vec<usize>,
+}
+
+impl Tokens {
+    /// Returns a new token for `source` of the process at `base`.
+    fn insert(&mut self, base: usize, source: Source) -> Token {
+        let entry = Some((base, source));
+        match self.free.pop() {
+            Some(token) => {
+                self.sources[token] = entry;
+                Token(token)
+            }
+            None => {
+                self.sources.push(entry);
+                Token(self.sources.len() - 1)
+            }
+        }
+    }
+
+    /// Returns the base of the process that `token` belongs to and the
+    /// source it is for, or `None` if it has been released.
+    pub(crate) fn get(&self, token: Token) -> Option<(usize, Source)> {
+        *self.sources.get(token.0)?
+    }
+
+    /// Releases the tokens of the process at `base`, once it is no longer
+    /// polled.
+    pub(crate) fn release(&mut self, base: usize) {
+        for (token, entry) in self.sources.iter_mut().enumerate() {
+            if entry.is_some_and(|(owner, _)| owner == base) {
+                *entry = None;
+                self.free.push(token);
+            }
+        }
+    }
+}
+
+/// A running child and the state of reading its output, independent of the
+/// poll loop it is registered with.
+///
+/// Its sources are registered under tokens from [`Tokens`], for the process
+/// at `base`.
+pub(crate) struct Process {
+    child: sys::Child,
+    /// What waits for, kills and signals the child, which is [`Pipes`]
+    /// unless the child was spawned with a backend.
+    #[cfg(unix)]
+    process_backend: Arc<dyn ProcessBackend>,
+    shared: Arc<Shared>,
+    stdin: Option<Stdin>,
+    /// The tokens the streams are registered under, by position.
+    stream_tokens: Vec<Token>,
+
+    /// The streams that are read, starting with stdout and stderr, followed
+    /// by the captured file descriptors and the sources.
+    streams: Vec<ProcessStream>,
+    /// The files that are followed, and when to check them next, which is
+    /// `None` while waiting for the watcher to report a change.
+    followed: Vec<FollowedFile>,
+    #[cfg(target_os = "linux")]
+    follow_watcher: Option<FollowWatcher>,
+    next_follow: Option<Instant>,
+    paused: bool,
+    suspended: bool,
+    exit_watcher: Option<ExitWatcher>,
+
+    output_buf: OutputQueue,
+    done: bool,
+
+    read_buf: Vec<u8>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    process_group: bool,
+    drop_policy: DropPolicy,
+    poll_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    kill_at: Option<Instant>,
+
+    idle_timeout: Option<(Duration, IdleAction)>,
+    idle_reported: Option<Instant>,
+
+    /// Whether to collect the resources the child used when reaping it, and
+    /// what was collected.
+    resource_usage: bool,
+    usage: Option<ResourceUsage>,
+    spawned: Instant,
+    core_locator: Option<CoreLocator>,
+    /// The resource limits set on the child, to report violations of.
+    rlimits: Vec<(Resource, u64)>,
+    check: bool,
+    /// The bytes read and wakeups so far, for [`Process::stats`].
+    stats: Stats,
+    on_exit: Option<Hook<dyn FnOnce(ExitStatus) + Send>>,
+}
+
+impl Process {
+    pub(crate) fn spawn(
+        builder: ProcessReaderBuilder,
+        registry: &impl Register,
+        tokens: &mut Tokens,
+        base: usize,
+    ) -> Result<Self, io::Error> {
+        Self::start(builder, None, registry, tokens, base)
+    }
+
+    /// Reads the output of `child`, which was spawned elsewhere, instead of
+    /// spawning the builder's command.
+    pub(crate) fn adopt(
+        builder: ProcessReaderBuilder,
+        child: Child,
+        registry: &impl Register,
+        tokens: &mut Tokens,
+        base: usize,
+    ) -> Result<Self, io::Error> {
+        Self::start(builder, Some(child), registry, tokens, base)
+    }
+
+    fn start(
+        mut builder: ProcessReaderBuilder,
+        child: Option<Child>,
+        registry: &impl Register,
+        tokens: &mut Tokens,
+        base: usize,
+    ) -> Result<Self, io::Error> {
+        // An adopted child has already been spawned, so the options that
+        // configure spawning do not apply to it.
+        #[cfg(unix)]
+        let posix_spawn = child.is_none() && builder.posix_spawn;
+        #[cfg(unix)]
+        if let Some(option) = builder.posix_spawn_conflict().filter(|_| posix_spawn) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{option} is not supported with posix_spawn"),
+            ));
+        }
+        let prepared = match child {
+            Some(_) => None,
+            None => Some(builder.prepare()?),
+        };
+        // The environment is applied to `cmd` by now, but `posix_spawn` takes
+        // it as a whole.
+        #[cfg(unix)]
+        let posix_spawn_env = posix_spawn.then(|| builder.env.resolve(&builder.cmd));
+        let ProcessReaderBuilder {
+            mut cmd,
+            #[cfg(unix)]
+            process_backend,
+            rlimits,
+            #[cfg(target_os = "linux")]
+            seccomp,
+            buffer_size,
+            mode,
+            stdout: stdout_options,
+            stderr: stderr_options,
+            max_line_length,
+            decoder,
+            line_pool,
+            tee_stdout,
+            tee_stderr,
+            tee_rotation,
+            tee_only,
+            capture_stdout,
+            capture_stderr,
+            capture_fds,
+            follow,
+            #[cfg(unix)]
+            sources,
+            merge_stderr,
+            pipe_stdin,
+            pty,
+            process_group,
+            new_session,
+            drop_policy,
+            wall_clock,
+            resource_usage,
+            locate_core_dumps,
+            check,
+            hooks,
+            queue_capacity,
+            sampling,
+            output_limit,
+            rate_limit,
+            poll_timeout,
+            timeout,
+            idle_timeout,
+            ..
+        } = builder;
+        let (stdout_tee, stderr_tee) = Tee::open(
+            tee_stdout.as_deref(),
+            tee_stderr.as_deref(),
+            tee_rotation.as_ref(),
+            tee_only,
+        )?;
+        let framing = LineFraming {
+            mode,
+            delimiter: stdout_options.delimiter.clone(),
+            max_line_length,
+            decoder,
+        };
+        // Files are opened before the child is spawned, so that nothing it
+        // appends is missed.
+        let followed = follow
+            .into_iter()
+            .map(|(name, path)| FollowedFile::open(name, path, &framing))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        #[cfg(target_os = "linux")]
+        let placed = prepared.and_then(|prepared| prepared.cgroup);
+        #[cfg(not(target_os = "linux"))]
+        let _ = prepared;
+        let spawned = match child {
+            Some(child) => sys::adopt(child)?,
+            None => 'spawn: {
+                let opts = SpawnOptions {
+                    capture_stdout,
+                    capture_stderr,
+                    merge_stderr,
+                    pipe_stdin,
+                    pty,
+                    process_group,
+                    new_session,
+                    capture_fds,
+                    #[cfg(target_os = "linux")]
+                    seccomp,
+                };
+                #[cfg(unix)]
+                if let Some(backend) = &process_backend {
+                    // The resources are collected when reaping the child,
+                    // which is up to the backend.
+                    if resource_usage {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Unsupported,
+                            "resource_usage is not supported with a process backend",
+                        ));
+                    }
+                    break 'spawn crate::process_backend::spawn(&**backend, &mut cmd, &opts)?;
+                }
+                #[cfg(unix)]
+                if let Some(env) = posix_spawn_env {
+                    break 'spawn sys::posix_spawn(&cmd, env, &opts)?;
+                }
+                sys::spawn(&mut cmd, &opts)?
+            }
+        };
+        let Spawned {
+            child,
+            stdin,
+            stdout: stdout_read,
+            stderr: stderr_read,
+            fds,
+            process_group,
+        } = spawned;
+        let core_locator = locate_core_dumps.then(|| CoreLocator::new(&cmd));
+        #[cfg(feature = "tracing")]
+        let hooks = crate::trace::instrument(hooks, child.id(), &cmd);
+        #[cfg(feature = "opentelemetry")]
+        let hooks = crate::otel::instrument(hooks, child.id(), &cmd);
+        #[cfg(feature = "metrics")]
+        let hooks = crate::recorder::instrument(hooks);
+        if let Some(hook) = hooks.spawn {
+            (hook.0)(child.id());
+        }
+
+        let spawned = Instant::now();
+        let stdout_framer = Framer::new(
+            Stream::Stdout,
+            mode,
+            stdout_options,
+            max_line_length,
+            decoder,
+            stdout_tee,
+            line_pool.clone(),
+        );
+        let stderr_framer = Framer::new(
+            Stream::Stderr,
+            mode,
+            stderr_options,
+            max_line_length,
+            decoder,
+            stderr_tee,
+            line_pool,
+        );
+        let mut streams = vec![
+            ProcessStream::output(stdout_read, stdout_framer, spawned),
+            ProcessStream::output(stderr_read, stderr_framer, spawned),
+        ];
+        for (fd, read) in fds {
+            streams.push(ProcessStream::lines(Tag::Fd(fd), read, &framing, spawned));
+        }
+        #[cfg(unix)]
+        for (name, fd) in sources {
+            let read = PipeReader::from_fd(fd)?;
+            streams.push(ProcessStream::lines(
+                Tag::Source(name),
+                read,
+                &framing,
+                spawned,
+            ));
+        }
+        let mut stream_tokens = Vec::with_capacity(streams.len());
+        for (index, stream) in streams.iter_mut().enumerate() {
+            let token = tokens.insert(base, Source::Stream(index));
+            if let Some(read) = &mut stream.read {
+                registry.register_pipe(read, token)?;
+            }
+            stream_tokens.push(token);
+        }
+        #[cfg(target_os = "linux")]
+        let mut follow_watcher = FollowWatcher::new(&followed);
+        #[cfg(target_os = "linux")]
+        if let Some(watcher) = &mut follow_watcher {
+            let token = tokens.insert(base, Source::Followed);
+            registry.register_pipe(&mut watcher.events, token)?;
+        }
+
+        // Close the parent's copies of the child's ends of the pipes, which
+        // `cmd` still holds, so that EOF is seen once the child closes them.
+        drop(cmd);
+
+        #[cfg(unix)]
+        let process_backend = process_backend.unwrap_or_else(|| Arc::new(Pipes));
+        let shared = Shared::new(
+            &child,
+            #[cfg(unix)]
+            process_backend.clone(),
+        )?;
+        #[cfg(target_os = "linux")]
+        let shared = shared.with_cgroup(placed);
+        let shared = Arc::new(shared);
+
+        let mut exit_watcher = ExitWatcher::new(&child)?;
+        if let Some(exit_watcher) = &mut exit_watcher {
+            let token = tokens.insert(base, Source::Child);
+            registry.register_exit(exit_watcher, token)?;
+        }
+
+        let deadline = timeout.map(|timeout| spawned + timeout);
+
+        let mut output_buf = OutputQueue::new(wall_clock, queue_capacity);
+        if let Some(lines) = check {
+            output_buf.keep_stderr_tail(lines);
+        }
+        output_buf.set_output_hook(hooks.output);
+        if let Some(sampling) = sampling {
+            output_buf.sample_output(Sampler::new(sampling), Sampler::new(sampling));
+        }
+        if let Some((limit, truncation)) = output_limit {
+            output_buf.limit_output(
+                Limiter::new(limit, truncation),
+                Limiter::new(limit, truncation),
+            );
+        }
+        if let Some((limit, policy)) = rate_limit {
+            output_buf.throttle_output(Throttle::new(limit, policy), Throttle::new(limit, policy));
+        }
+
+        Ok(Self {
+            child,
+            #[cfg(unix)]
+            process_backend,
+            shared,
+            stdin,
+            stream_tokens,
+            streams,
+            followed,
+            #[cfg(target_os = "linux")]
+            follow_watcher,
+            next_follow: Some(spawned),
+            paused: false,
+            suspended: false,
+            exit_watcher,
+
+            output_buf,
+            done: false,
+
+            read_buf: vec![0; buffer_size],
+            process_group,
+            drop_policy,
+            poll_timeout,
+            deadline,
+            kill_at: None,
+
+            idle_timeout,
+            idle_reported: None,
+
+            resource_usage,
+            usage: None,
+            spawned,
+            core_locator,
+            rlimits,
+            check: check.is_some(),
+            stats: Stats::default(),
+            on_exit: hooks.exit,
+        })
+    }
+
+    pub(crate) fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Returns when the child was spawned.
+    pub(crate) fn spawned(&self) -> Instant {
+        self.spawned
+    }
+
+    pub(crate) fn handle(&self) -> ProcessHandle {
+        ProcessHandle::new(self.shared.clone())
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn cgroup(&self) -> Option<&crate::Cgroup> {
+        self.shared.cgroup.as_ref()
+    }
+
+    /// Whether the exit status has been queued, after which nothing more is
+    /// read.
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub(crate) fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn shutdown(&mut self, grace: Duration) -> Result<(), io::Error> {
+        if self.done {
+            return Ok(());
+        }
+
+        self.signal_child(libc::SIGTERM)?;
+        self.kill_at = Some(Instant::now() + grace);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn kill_group(&mut self) -> Result<(), io::Error> {
+        if !self.process_group {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "child was not spawned in its own process group",
+            ));
+        }
+
+        self.signal_child(libc::SIGKILL)
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn signal(&mut self, signal: i32) -> Result<(), io::Error> {
+        if self.done {
+            return Ok(());
+        }
+
+        self.signal_child(signal)
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn suspend(&mut self) -> Result<(), io::Error> {
+        if self.done {
+            return Ok(());
+        }
+
+        self.signal_child(libc::SIGSTOP)?;
+        self.suspended = true;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn resume(&mut self) -> Result<(), io::Error> {
+        if self.done {
+            return Ok(());
+        }
+
+        self.signal_child(libc::SIGCONT)?;
+        if self.suspended {
+            // Start a new idle period instead of counting the time stopped.
+            self.suspended = false;
+            self.idle_reported = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Sends `signal` to the child with its backend, which by default sends
+    /// it to its process group if it leads one.
+    #[cfg(unix)]
+    fn signal_child(&self, signal: libc::c_int) -> Result<(), io::Error> {
+        self.process_backend.signal(self.child.id(), signal)
+    }
+
+    /// Forcefully kills the child with its backend, which by default kills
+    /// its process group along with it if it leads one.
+    pub(crate) fn kill(&mut self) -> Result<(), io::Error> {
+        #[cfg(unix)]
+        return match &mut self.child {
+            sys::Child::Std(child) => self.process_backend.kill(child),
+            sys::Child::PosixSpawn(pid) => self.process_backend.signal(*pid, libc::SIGKILL),
+        };
+
+        #[cfg(windows)]
+        return self.child.kill();
+    }
+
+    pub(crate) fn stdin(&mut self) -> Option<&mut Stdin> {
+        self.stdin.as_mut()
+    }
+
+    pub(crate) fn take_stdin(&mut self) -> Option<Stdin> {
+        self.stdin.take()
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.output_buf.dropped()
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        let (stdout_lines, stderr_lines) = self.output_buf.read_lines();
+        Stats {
+            lines: self.output_buf.lines(),
+            stdout_lines,
+            stderr_lines,
+            dropped: self.output_buf.dropped(),
+            elapsed: self.spawned.elapsed(),
+            ..self.stats
+        }
+    }
+
+    pub(crate) fn pause_reading(&mut self, registry: &impl Register) -> Result<(), io::Error> {
+        if self.paused {
+            return Ok(());
+        }
+
+        for stream in &mut self.streams {
+            if let Some(reader) = &mut stream.read {
+                registry.deregister_pipe(reader)?;
+            }
+            stream.pending = false;
+        }
+
+        self.paused = true;
+        Ok(())
+    }
+
+    /// Closes the read ends of every stream, so that a child that is still
+    /// writing to them fails with `EPIPE` instead of blocking once nothing
+    /// reads them anymore.
+    pub(crate) fn close_streams(&mut self) {
+        for stream in &mut self.streams {
+            stream.read = None;
+            stream.pending = false;
+        }
+    }
+
+    pub(crate) fn resume_reading(&mut self, registry: &impl Register) -> Result<(), io::Error> {
+        if !self.paused || self.done {
+            return Ok(());
+        }
+
+        // Output that arrived while paused may not produce a new readiness
+        // event, so read every stream on the next call.
+        for (index, stream) in self.streams.iter_mut().enumerate() {
+            if let Some(reader) = &mut stream.read {
+                registry.register_pipe(reader, self.stream_tokens[index])?;
+                stream.pending = true;
+            }
+        }
+
+        self.paused = false;
+        Ok(())
+    }
+
+    /// Returns the next queued item or error, if any.
+    pub(crate) fn pop(&mut self) -> Option<Result<Event, io::Error>> {
+        self.output_buf.pop_front()
+    }
+
+    /// Returns the next queued item or error without removing it.
+    pub(crate) fn peek(&self) -> Option<&Result<Event, io::Error>> {
+        self.output_buf.front()
+    }
+
+    /// Returns the incomplete line of stdout or stderr that `pattern`
+    /// matches, if any, removing it so that it is not yielded later.
+    #[cfg(feature = "regex")]
+    pub(crate) fn take_partial_match(&mut self, pattern: &regex::Regex) -> Option<String> {
+        for framer in self
+            .streams
+            .iter_mut()
+            .filter_map(ProcessStream::output_framer)
+        {
+            let Some(partial) = framer.partial() else {
+                continue;
+            };
+
+            if pattern.is_match(&partial) {
+                framer.clear_partial();
+                return Some(partial);
+            }
+        }
+
+        None
+    }
+
+    /// Returns when the next queued item was read, or `None` if the queue is
+    /// empty. Errors count as having been read at the start of time, so that
+    /// they are yielded first.
+    pub(crate) fn peek_at(&self) -> Option<Option<Instant>> {
+        self.output_buf.front_at()
+    }
+
+    /// Reads the streams that are ready or pending, checks timeouts and reaps
+    /// the child if it has exited.
+    pub(crate) fn step(
+        &mut self,
+        registry: &impl Register,
+        readiness: &Readiness,
+    ) -> Result<(), io::Error> {
+        if self.done {
+            return Ok(());
+        }
+
+        self.stats.wakeups += 1;
+        for index in 0..self.streams.len() {
+            if readiness.stream(index) || self.streams[index].pending {
+                self.read_stream(registry, index, true)?;
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let (true, Some(watcher)) = (readiness.followed, &mut self.follow_watcher) {
+            watcher.drain()?;
+            self.next_follow = Some(Instant::now());
+        }
+        if !self.paused && self.next_follow.is_some_and(|at| Instant::now() >= at) {
+            self.read_followed(true)?;
+        }
+
+        self.check_timeouts(registry)?;
+
+        // Without an exit watcher, check for exit whenever there is nothing
+        // else to yield.
+        let poll_exit = self.exit_watcher.is_none() && self.output_buf.is_empty();
+        if self.done || !(readiness.exited || poll_exit) {
+            return Ok(());
+        }
+
+        if let Some(status) = self.try_reap()? {
+            self.finish(registry, status)?;
+        }
+        Ok(())
+    }
+
+    /// Reads whatever is available from a stream. Unless `bounded` is false,
+    /// this stops early once the queue is full and marks the stream as
+    /// pending.
+    fn read_stream(
+        &mut self,
+        registry: &impl Register,
+        index: usize,
+        bounded: bool,
+    ) -> Result<(), io::Error> {
+        let stream = &mut self.streams[index];
+        let (n, end) = stream.read(&mut self.output_buf, &mut self.read_buf, bounded)?;
+
+        // Sources are not written to by the child, so they do not keep it
+        // from counting as idle.
+        if n > 0 && !stream.is_source() {
+            stream.activity = Instant::now();
+        }
+        if let (true, Some(which)) = (n > 0, stream.which()) {
+            self.stats.add_read(which, n, stream.activity);
+            #[cfg(feature = "metrics")]
+            crate::recorder::record_read(which, n);
+        }
+
+        stream.pending = end == ReadEnd::QueueFull;
+        if end == ReadEnd::Eof {
+            if let Some(mut reader) = stream.read.take() {
+                if !self.paused {
+                    registry.deregister_pipe(&mut reader)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the lines appended to the followed files, and schedules the next
+    /// check unless the watcher reports changes. Unless `bounded` is false,
+    /// this stops early once the queue is full and checks again right away.
+    ///
+    /// The files may be written to by anyone, so they do not keep the child
+    /// from counting as idle.
+    fn read_followed(&mut self, bounded: bool) -> Result<(), io::Error> {
+        let mut pending = false;
+        for file in &mut self.followed {
+            pending |= file.read(&mut self.output_buf, &mut self.read_buf, bounded)?;
+        }
+
+        #[cfg(target_os = "linux")]
+        let watched = self.follow_watcher.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let watched = false;
+        self.next_follow = match pending {
+            true => Some(Instant::now()),
+            false => (!watched).then(|| Instant::now() + FOLLOW_INTERVAL),
+        };
+        Ok(())
+    }
+
+    /// Returns the point in time at which the child counts as idle.
+    fn idle_deadline(&self) -> Option<Instant> {
+        let (timeout, _) = self.idle_timeout?;
+        if self.suspended {
+            return None;
+        }
+
+        let last = self
+            .streams
+            .iter()
+            .filter(|stream| !stream.is_source())
+            .map(|stream| stream.activity)
+            .max()
+            .unwrap_or(self.spawned);
+        let since = match self.idle_reported {
+            Some(reported) => last.max(reported),
+            None => last,
+        };
+
+        Some(since + timeout)
+    }
+
+    fn streams_closed(&self) -> bool {
+        // Sources may stay open long after the child has exited.
+        self.streams
+            .iter()
+            .all(|stream| stream.read.is_none() || stream.is_source())
+    }
+
+    /// Returns how long the next poll may block, taking the deadline, idle
+    /// timeout and exit polling into account.
+    pub(crate) fn next_poll_timeout(&self) -> Option<Duration> {
+        if self.done {
+            return None;
+        }
+
+        // Streams that were left unread because the queue was full will not
+        // be reported as ready again, so read them without blocking.
+        if self.streams.iter().any(|stream| stream.pending) {
+            return Some(Duration::ZERO);
+        }
+
+        let now = Instant::now();
+        let next_follow = self
+            .next_follow
+            .filter(|_| !self.followed.is_empty() && !self.paused);
+        // Without an exit watcher, nothing wakes us up once both streams are
+        // closed or paused, so fall back to checking for exit periodically.
+        let exit_poll = match self.exit_watcher {
+            None if self.streams_closed() || self.paused => Some(EXIT_POLL_INTERVAL),
+            _ => None,
+        };
+
+        [
+            self.deadline,
+            self.kill_at,
+            self.idle_deadline(),
+            self.output_buf.throttle_deadline(),
+            next_follow,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|at| at.saturating_duration_since(now))
+        .chain(self.poll_timeout)
+        .chain(exit_poll)
+        .min()
+    }
+
+    /// Kills the child if it has run past its deadline, has been idle for too
+    /// long or did not shut down within its grace period, and queues output
+    /// that the rate limits held back once it is due.
+    fn check_timeouts(&mut self, registry: &impl Register) -> Result<(), io::Error> {
+        let now = Instant::now();
+        self.output_buf.stamp();
+        self.output_buf.release_throttled();
+
+        if matches!(self.kill_at, Some(kill_at) if now >= kill_at) {
+            self.kill_at = None;
+            return self.kill_and_finish(registry);
+        }
+
+        if matches!(self.deadline, Some(deadline) if now >= deadline) {
+            self.deadline = None;
+            self.output_buf.push_back(Out::TimedOut);
+            return self.kill_and_finish(registry);
+        }
+
+        if let (Some(at), Some((_, action))) = (self.idle_deadline(), self.idle_timeout) {
+            if now >= at {
+                self.output_buf.push_back(Out::Idle);
+                match action {
+                    IdleAction::Notify => self.idle_reported = Some(now),
+                    IdleAction::Kill => return self.kill_and_finish(registry),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reaps the child if it has exited, letting its handles know.
+    fn try_reap(&mut self) -> Result<Option<ExitStatus>, io::Error> {
+        self.wait_child(false)
+    }
+
+    /// Waits for the child to exit and reaps it, letting its handles know.
+    pub(crate) fn reap(&mut self) -> Result<ExitStatus, io::Error> {
+        let status = self.wait_child(true)?;
+        Ok(status.expect("blocking wait returned without an exit status"))
+    }
+
+    fn wait_child(&mut self, block: bool) -> Result<Option<ExitStatus>, io::Error> {
+        let shared = self.shared.clone();
+        let mut state = shared.lock();
+        // Once reaped without std, the child cannot be waited for again.
+        if let State::Exited(status) = *state {
+            return Ok(Some(status));
+        }
+
+        let status = if self.resource_usage {
+            let Some((status, usage)) = sys::wait_with_usage(&mut self.child, block)? else {
+                return Ok(None);
+            };
+            self.usage = Some(ResourceUsage {
+                user_time: usage.user_time,
+                system_time: usage.system_time,
+                max_rss: usage.max_rss,
+                wall_time: self.spawned.elapsed(),
+            });
+            status
+        } else {
+            match self.wait_for_exit(block)? {
+                Some(status) => status,
+                None => return Ok(None),
+            }
+        };
+
+        shared.set(&mut state, State::Exited(status));
+        Ok(Some(status))
+    }
+
+    /// Waits for the child with its backend.
+    fn wait_for_exit(&mut self, block: bool) -> Result<Option<ExitStatus>, io::Error> {
+        #[cfg(unix)]
+        return match &mut self.child {
+            sys::Child::Std(child) => self.process_backend.wait(child, block),
+            // std does not know about the child, so it is reaped without it.
+            sys::Child::PosixSpawn(_) => {
+                let status = sys::wait_with_usage(&mut self.child, block)?;
+                Ok(status.map(|(status, _)| status))
+            }
+        };
+
+        #[cfg(windows)]
+        match block {
+            true => self.child.wait().map(Some),
+            false => self.child.try_wait(),
+        }
+    }
+
+    fn kill_and_finish(&mut self, registry: &impl Register) -> Result<(), io::Error> {
+        self.kill()?;
+        let status = self.reap()?;
+        self.finish(registry, status)
+    }
+
+    /// Drains anything still left in the pipes after the child has exited and
+    /// emits unterminated lines, followed by the exit status.
+    fn finish(&mut self, registry: &impl Register, status: ExitStatus) -> Result<(), io::Error> {
+        for index in 0..self.streams.len() {
+            self.read_stream(registry, index, false)?;
+        }
+        self.read_followed(false)?;
+
+        for stream in &mut self.streams {
+            stream.flush(&mut self.output_buf);
+        }
+        for file in &mut self.followed {
+            file.flush(&mut self.output_buf);
+        }
+        self.output_buf.flush_limits();
+
+        // Stop waiting for output that is written after the child exited,
+        // such as by a grandchild that inherited the pipes.
+        self.pause_reading(registry)?;
+        if let Some(exit_watcher) = &mut self.exit_watcher {
+            registry.deregister_exit(exit_watcher)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(mut watcher) = self.follow_watcher.take() {
+            registry.deregister_pipe(&mut watcher.events)?;
+        }
+
+        self.output_buf.stamp();
+        if let Some(usage) = self.usage.take() {
+            self.output_buf.push_back(Out::ResourceUsage(usage));
+        }
+        let pid = self.child.id();
+        if let Some(out) = core_dump::report(status, pid, self.core_locator.as_ref()) {
+            self.output_buf.push_back(out);
+        }
+        if let Some(out) = rlimit::report(status, &self.rlimits) {
+            self.output_buf.push_back(out);
+        }
+        if let Some(hook) = self.on_exit.take() {
+            (hook.0)(status);
+        }
+        if self.check && !status.success() {
+            let err = Error::NonZeroExit {
+                status,
+                captured_stderr_tail: self.output_buf.take_stderr_tail(),
+            };
+            self.output_buf.push_error(err.into());
+        } else {
+            self.output_buf.push_back(Out::Done(status));
+        }
+        self.done = true;
+        Ok(())
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        if matches!(*state, State::Running) {
+            self.shared.set(&mut state, State::Detached);
+        }
+    }
+}
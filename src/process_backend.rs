@@ -0,0 +1,224 @@
+use std::{
+    fmt,
+    fs::File,
+    io,
+    os::unix::{io::OwnedFd, process::CommandExt},
+    process::{Child, Command, ExitStatus, Stdio},
+};
+
+use crate::{
+    sys::{self, PipeReader, SpawnOptions, Spawned},
+    Stdin,
+};
+
+/// Which of the child's streams the reader wants to be connected to it.
+///
+/// Passed to [`ProcessBackend::spawn`], following
+/// [`capture_stdout`](crate::ProcessReaderBuilder::capture_stdout),
+/// [`capture_stderr`](crate::ProcessReaderBuilder::capture_stderr) and
+/// [`pipe_stdin`](crate::ProcessReaderBuilder::pipe_stdin).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StreamRequest {
+    /// Whether the reader writes to the child's stdin.
+    pub stdin: bool,
+    /// Whether the reader reads the child's stdout.
+    pub stdout: bool,
+    /// Whether the reader reads the child's stderr.
+    pub stderr: bool,
+}
+
+/// The ends of a child's streams that the reader uses, as returned by
+/// [`ProcessBackend::streams`].
+///
+/// The reader switches the output streams to non-blocking mode and polls
+/// them, so they have to be pipes, sockets or terminals rather than regular
+/// files.
+#[derive(Debug, Default)]
+pub struct ChildStreams {
+    /// Where the reader writes the child's input, if requested.
+    pub stdin: Option<OwnedFd>,
+    /// Where the reader reads the child's stdout from, if requested.
+    pub stdout: Option<OwnedFd>,
+    /// Where the reader reads the child's stderr from, if requested.
+    pub stderr: Option<OwnedFd>,
+}
+
+/// Spawns the child of a reader, hands over its streams, and waits for and
+/// kills it, set with
+/// [`process_backend`](crate::ProcessReaderBuilder::process_backend).
+///
+/// Everything after spawning, such as framing output into
+/// [`Out`](crate::Out) items, timeouts and hooks, works the same for every
+/// backend. Builder options that configure the [`Command`], such as
+/// [`uid`](crate::ProcessReaderBuilder::uid) or
+/// [`rlimit`](crate::ProcessReaderBuilder::rlimit), are applied to it before
+/// it is passed to [`spawn`](Self::spawn), while connecting the streams is
+/// up to the backend.
+///
+/// Only [`spawn`](Self::spawn) has to be implemented. The other methods
+/// default to what std does for a child with piped streams. [`Pipes`] is a
+/// backend that spawns the child the way the reader does by default, and
+/// the reader waits for, kills and signals its child with it unless a
+/// backend is set.
+///
+/// ```no_run
+/// use std::{
+///     io,
+///     process::{Child, Command},
+/// };
+/// use incremental_command::{Pipes, ProcessBackend, ProcessReader, StreamRequest};
+///
+/// /// Spawns children like the default, but logs every spawn.
+/// #[derive(Debug)]
+/// struct Logged;
+///
+/// impl ProcessBackend for Logged {
+///     fn spawn(&self, cmd: &mut Command, streams: StreamRequest) -> io::Result<Child> {
+///         eprintln!("spawning {:?}", cmd.get_program());
+///         Pipes.spawn(cmd, streams)
+///     }
+/// }
+///
+/// let reader = ProcessReader::builder(Command::new("ls"))
+///     .process_backend(Logged)
+///     .start()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait ProcessBackend: fmt::Debug + Send + Sync {
+    /// Spawns `cmd`, with the streams in `streams` connected so that
+    /// [`streams`](Self::streams) can hand them over.
+    fn spawn(&self, cmd: &mut Command, streams: StreamRequest) -> Result<Child, io::Error>;
+
+    /// Takes the ends of the freshly spawned `child`'s streams that the
+    /// reader uses. Defaults to the pipes that std created for it.
+    fn streams(&self, child: &mut Child) -> Result<ChildStreams, io::Error> {
+        Ok(ChildStreams {
+            stdin: child.stdin.take().map(OwnedFd::from),
+            stdout: child.stdout.take().map(OwnedFd::from),
+            stderr: child.stderr.take().map(OwnedFd::from),
+        })
+    }
+
+    /// Reaps `child` if it has exited, or waits for it to exit first if
+    /// `block` is true. Defaults to [`Child::wait`] and [`Child::try_wait`].
+    fn wait(&self, child: &mut Child, block: bool) -> Result<Option<ExitStatus>, io::Error> {
+        match block {
+            true => child.wait().map(Some),
+            false => child.try_wait(),
+        }
+    }
+
+    /// Forcefully kills `child`. Defaults to sending it `SIGKILL` with
+    /// [`signal`](Self::signal).
+    fn kill(&self, child: &mut Child) -> Result<(), io::Error> {
+        self.signal(child.id(), libc::SIGKILL)
+    }
+
+    /// Sends `signal` to the child with the process ID `pid`, for
+    /// [`ProcessReader::signal`](crate::ProcessReader::signal) and the
+    /// methods built on it, as well as
+    /// [`ProcessHandle`](crate::ProcessHandle). Defaults to sending it to
+    /// the child's process group if it leads one.
+    ///
+    /// This is only called while the child has not been reaped, so `pid`
+    /// cannot have been reused.
+    fn signal(&self, pid: u32, signal: i32) -> Result<(), io::Error> {
+        let pid = pid as libc::pid_t;
+        match unsafe { libc::getpgid(pid) } == pid {
+            true => sys::send_signal(-pid, signal),
+            false => sys::send_signal(pid, signal),
+        }
+    }
+}
+
+impl<B: ProcessBackend + ?Sized> ProcessBackend for Box<B> {
+    fn spawn(&self, cmd: &mut Command, streams: StreamRequest) -> Result<Child, io::Error> {
+        (**self).spawn(cmd, streams)
+    }
+
+    fn streams(&self, child: &mut Child) -> Result<ChildStreams, io::Error> {
+        (**self).streams(child)
+    }
+
+    fn wait(&self, child: &mut Child, block: bool) -> Result<Option<ExitStatus>, io::Error> {
+        (**self).wait(child, block)
+    }
+
+    fn kill(&self, child: &mut Child) -> Result<(), io::Error> {
+        (**self).kill(child)
+    }
+
+    fn signal(&self, pid: u32, signal: i32) -> Result<(), io::Error> {
+        (**self).signal(pid, signal)
+    }
+}
+
+/// Spawns the child with its requested streams connected to pipes, like the
+/// reader does by default, and leaves the others as configured on the
+/// [`Command`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Pipes;
+
+impl ProcessBackend for Pipes {
+    fn spawn(&self, cmd: &mut Command, streams: StreamRequest) -> Result<Child, io::Error> {
+        if streams.stdin {
+            cmd.stdin(Stdio::piped());
+        }
+        if streams.stdout {
+            cmd.stdout(Stdio::piped());
+        }
+        if streams.stderr {
+            cmd.stderr(Stdio::piped());
+        }
+        cmd.spawn()
+    }
+}
+
+/// Spawns the child with `backend`, applying the options that do not depend
+/// on how its streams are connected.
+pub(crate) fn spawn(
+    backend: &dyn ProcessBackend,
+    cmd: &mut Command,
+    opts: &SpawnOptions,
+) -> Result<Spawned, io::Error> {
+    if opts.pty || opts.merge_stderr || !opts.capture_fds.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "pty, merge_stderr and capture_fd are not supported with a process backend",
+        ));
+    }
+
+    let process_group = opts.process_group || opts.new_session;
+    if opts.new_session {
+        sys::set_new_session(cmd);
+    } else if process_group {
+        cmd.process_group(0);
+    }
+    #[cfg(target_os = "linux")]
+    crate::seccomp::install(cmd, opts.seccomp.clone());
+
+    let request = StreamRequest {
+        stdin: opts.pipe_stdin,
+        stdout: opts.capture_stdout,
+        stderr: opts.capture_stderr,
+    };
+    let mut child = backend.spawn(cmd, request)?;
+    let streams = match backend.streams(&mut child) {
+        Ok(streams) => streams,
+        Err(err) => {
+            let _ = backend.kill(&mut child);
+            let _ = backend.wait(&mut child, true);
+            return Err(err);
+        }
+    };
+
+    Ok(Spawned {
+        child: sys::Child::Std(child),
+        stdin: streams.stdin.map(|stdin| Stdin::new(File::from(stdin))),
+        stdout: streams.stdout.map(PipeReader::from_fd).transpose()?,
+        stderr: streams.stderr.map(PipeReader::from_fd).transpose()?,
+        fds: Vec::new(),
+        process_group,
+    })
+}
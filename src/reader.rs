@@ -0,0 +1,773 @@
+use std::{
+    io::{self, Write},
+    process::{Child, Command, ExitStatus},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+#[cfg(unix)]
+use mio::unix::SourceFd;
+use mio::Events;
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+use crate::{
+    event::{self, Event},
+    process::{Process, Readiness, Tokens},
+    spill::{Spill, SpilledCompletion},
+    split::{self, StreamLines},
+    DropPolicy, Out, ProcessHandle, ProcessReaderBuilder, Stats, Stdin,
+};
+
+/// Everything a child wrote and how it exited, returned by
+/// [`ProcessReader::run_to_completion`].
+#[derive(Clone, Debug)]
+pub struct Completion {
+    /// The lines written to stdout, without their trailing newlines.
+    pub stdout: Vec<String>,
+    /// The lines written to stderr, without their trailing newlines.
+    pub stderr: Vec<String>,
+    /// The exit status of the child.
+    pub status: ExitStatus,
+    /// How long the child ran, from spawning it until it was seen to exit.
+    pub duration: Duration,
+}
+
+/// What [`ProcessReader::next_timeout`] returns when it does not fail.
+#[derive(Clone, Debug)]
+pub enum Next {
+    /// The next item.
+    Out(Out),
+    /// Nothing arrived within the timeout.
+    Timeout,
+}
+
+/// An iterator over the items of a [`ProcessReader`] in batches, created
+/// with [`ProcessReader::batches`].
+pub struct Batches {
+    reader: ProcessReader,
+}
+
+impl Batches {
+    /// Returns the reader, for example to call
+    /// [`stats`](ProcessReader::stats) on it.
+    pub fn reader(&mut self) -> &mut ProcessReader {
+        &mut self.reader
+    }
+}
+
+impl Iterator for Batches {
+    type Item = Result<Vec<Out>, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.next_batch()
+    }
+}
+
+/// A running child process whose output can be consumed as an iterator.
+///
+/// Each call to [`next`](Iterator::next) blocks until at least one line is
+/// available or the child has exited.
+///
+/// The reader owns the poll loop, so it can be moved to a dedicated thread
+/// while a [`ProcessHandle`] controls the child from elsewhere.
+pub struct ProcessReader {
+    process: Process,
+    poll: mio::Poll,
+    events: mio::Events,
+    tokens: Tokens,
+}
+
+impl ProcessReader {
+    /// Spawns `cmd` with its stdout and stderr captured.
+    ///
+    /// Any stdout or stderr configuration already set on `cmd` is replaced.
+    /// Use [`ProcessReader::builder`] to customize how the child is read.
+    pub fn start(cmd: Command) -> Result<Self, io::Error> {
+        Self::builder(cmd).start()
+    }
+
+    /// Returns a builder for configuring the reader before spawning `cmd`.
+    pub fn builder(cmd: Command) -> ProcessReaderBuilder {
+        ProcessReaderBuilder::new(cmd)
+    }
+
+    /// Reads the output of `child`, which was spawned elsewhere with its
+    /// stdout and stderr set to [`Stdio::piped`](std::process::Stdio::piped),
+    /// with the default options. Streams that were not piped are not read.
+    ///
+    /// Use [`ProcessReaderBuilder::adopt`] to configure how the output is
+    /// read. The child is reaped by the reader, so it must not be waited for
+    /// elsewhere.
+    ///
+    /// Not supported on Windows.
+    ///
+    /// ```no_run
+    /// use std::process::{Command, Stdio};
+    /// use incremental_command::ProcessReader;
+    ///
+    /// let child = Command::new("make")
+    ///     .stdout(Stdio::piped())
+    ///     .stderr(Stdio::piped())
+    ///     .spawn()?;
+    /// for out in ProcessReader::from_child(child)? {
+    ///     println!("{:?}", out?);
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn from_child(child: Child) -> Result<Self, io::Error> {
+        // The command is never spawned, it only stands in for the child's.
+        ProcessReaderBuilder::new(Command::new("")).adopt(child)
+    }
+
+    /// Reads the output that the running process `pid`, which was not
+    /// spawned by this one, writes from now on, with the default options.
+    ///
+    /// Use [`ProcessReaderBuilder::attach`] to configure how the output is
+    /// read. See [`AttachedReader`](crate::AttachedReader) for what can be
+    /// read.
+    #[cfg(target_os = "linux")]
+    pub fn attach(pid: u32) -> Result<crate::AttachedReader, io::Error> {
+        ProcessReaderBuilder::new(Command::new("")).attach(pid)
+    }
+
+    pub(crate) fn from_builder(builder: ProcessReaderBuilder) -> Result<Self, io::Error> {
+        Self::with_process(|registry, tokens| Process::spawn(builder, registry, tokens, 0))
+    }
+
+    pub(crate) fn from_child_with(
+        builder: ProcessReaderBuilder,
+        child: Child,
+    ) -> Result<Self, io::Error> {
+        Self::with_process(|registry, tokens| Process::adopt(builder, child, registry, tokens, 0))
+    }
+
+    fn with_process(
+        process: impl FnOnce(&mio::Registry, &mut Tokens) -> Result<Process, io::Error>,
+    ) -> Result<Self, io::Error> {
+        let poll = mio::Poll::new()?;
+        let events = Events::with_capacity(128);
+        let mut tokens = Tokens::default();
+        let process = process(poll.registry(), &mut tokens)?;
+
+        Ok(Self {
+            process,
+            poll,
+            events,
+            tokens,
+        })
+    }
+
+    /// Returns the OS-assigned process identifier of the child.
+    pub fn id(&self) -> u32 {
+        self.process.id()
+    }
+
+    /// Returns a handle for killing, signalling or waiting for the child from
+    /// another thread while this one reads its output.
+    pub fn handle(&self) -> ProcessHandle {
+        self.process.handle()
+    }
+
+    /// Returns the cgroup the child was placed in with
+    /// [`cgroup`](ProcessReaderBuilder::cgroup), if any.
+    #[cfg(target_os = "linux")]
+    pub fn cgroup(&self) -> Option<&crate::Cgroup> {
+        self.process.cgroup()
+    }
+
+    /// Asks the child to terminate by sending it `SIGTERM`.
+    ///
+    /// Iteration continues as usual, so any remaining output and the exit
+    /// status are still yielded. If the child has not exited within `grace`,
+    /// it is killed with `SIGKILL`.
+    #[cfg(unix)]
+    pub fn shutdown(&mut self, grace: Duration) -> Result<(), io::Error> {
+        self.process.shutdown(grace)
+    }
+
+    /// Kills the child's whole process group with `SIGKILL`.
+    ///
+    /// This fails with [`io::ErrorKind::InvalidInput`] unless the reader was
+    /// built with [`process_group`](ProcessReaderBuilder::process_group).
+    #[cfg(unix)]
+    pub fn kill_group(&mut self) -> Result<(), io::Error> {
+        self.process.kill_group()
+    }
+
+    /// Sends `signal`, such as `libc::SIGHUP`, to the child, or to its
+    /// process group if it leads one.
+    ///
+    /// Nothing is sent once the child has exited and been reaped, since its
+    /// process ID may already have been reused.
+    #[cfg(unix)]
+    pub fn signal(&mut self, signal: i32) -> Result<(), io::Error> {
+        self.process.signal(signal)
+    }
+
+    /// Stops the child, or its process group if it leads one, with `SIGSTOP`.
+    ///
+    /// The child keeps its state and can be continued with
+    /// [`resume`](Self::resume). The idle timeout does not apply while it is
+    /// stopped, but the overall [`timeout`](ProcessReaderBuilder::timeout)
+    /// keeps running.
+    #[cfg(unix)]
+    pub fn suspend(&mut self) -> Result<(), io::Error> {
+        self.process.suspend()
+    }
+
+    /// Continues the child after [`suspend`](Self::suspend) with `SIGCONT`.
+    #[cfg(unix)]
+    pub fn resume(&mut self) -> Result<(), io::Error> {
+        self.process.resume()
+    }
+
+    /// Returns a handle for writing to the child's stdin.
+    ///
+    /// This is only available if the reader was built with
+    /// [`pipe_stdin`](ProcessReaderBuilder::pipe_stdin) or
+    /// [`pty`](ProcessReaderBuilder::pty) and the handle has not been taken.
+    /// Writes block until the child has consumed enough input, so avoid
+    /// writing large amounts without also reading the child's output.
+    pub fn stdin(&mut self) -> Option<&mut Stdin> {
+        self.process.stdin()
+    }
+
+    /// Takes ownership of the child's stdin handle, e.g. to write to it from
+    /// another thread. Dropping the handle closes the child's stdin.
+    pub fn take_stdin(&mut self) -> Option<Stdin> {
+        self.process.take_stdin()
+    }
+
+    /// Returns how many items have been dropped because the queue was full.
+    ///
+    /// This is only ever non-zero with a
+    /// [`queue_capacity`](ProcessReaderBuilder::queue_capacity) that drops
+    /// output.
+    pub fn dropped(&self) -> u64 {
+        self.process.dropped()
+    }
+
+    /// Returns how much has been read from the child so far.
+    pub fn stats(&self) -> Stats {
+        self.process.stats()
+    }
+
+    /// Stops reading from the child's stdout and stderr until
+    /// [`resume_reading`](Self::resume_reading) is called.
+    ///
+    /// Output then accumulates in the pipe buffers, and once they are full the
+    /// child blocks on its next write. [`next`](Iterator::next) still reports
+    /// timeouts and the child's exit, after which all remaining output is
+    /// read regardless.
+    pub fn pause_reading(&mut self) -> Result<(), io::Error> {
+        self.process.pause_reading(self.poll.registry())
+    }
+
+    /// Resumes reading after [`pause_reading`](Self::pause_reading).
+    pub fn resume_reading(&mut self) -> Result<(), io::Error> {
+        self.process.resume_reading(self.poll.registry())
+    }
+
+    /// Like [`next`](Iterator::next), but yields the item together with the
+    /// time it was read and its sequence number.
+    pub fn next_event(&mut self) -> Option<Result<Event, io::Error>> {
+        loop {
+            if let Some(next) = self.process.pop() {
+                return Some(next);
+            }
+
+            if self.process.is_done() {
+                return None;
+            }
+
+            let timeout = self.process.next_poll_timeout();
+            if let Err(err) = self.poll_once(timeout) {
+                return Some(Err(err));
+            }
+        }
+    }
+
+    /// Like [`next`](Iterator::next), but yields every item that is ready at
+    /// once, which is everything gathered in one wakeup, so that consumers
+    /// forwarding items elsewhere can amortize the cost per item.
+    ///
+    /// A batch is never empty, and ends before an error, which is yielded on
+    /// its own by the following call.
+    pub fn next_batch(&mut self) -> Option<Result<Vec<Out>, io::Error>> {
+        let first = match self.next_event()? {
+            Ok(event) => event.out,
+            Err(err) => return Some(Err(err)),
+        };
+
+        let mut batch = vec![first];
+        while let Some(Ok(_)) = self.process.peek() {
+            if let Some(Ok(event)) = self.process.pop() {
+                batch.push(event.out);
+            }
+        }
+        Some(Ok(batch))
+    }
+
+    /// Turns the reader into an iterator over batches of items, see
+    /// [`next_batch`](Self::next_batch).
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::ProcessReader;
+    ///
+    /// let reader = ProcessReader::start(Command::new("cargo"))?;
+    /// for batch in reader.batches() {
+    ///     println!("{} items", batch?.len());
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn batches(self) -> Batches {
+        Batches { reader: self }
+    }
+
+    /// Like [`next`](Iterator::next), but waits for at most `timeout` and
+    /// returns [`Next::Timeout`] if no item arrived in time, so that a UI can
+    /// refresh while the child is quiet.
+    ///
+    /// ```no_run
+    /// use std::{process::Command, time::Duration};
+    /// use incremental_command::{Next, ProcessReader};
+    ///
+    /// let mut reader = ProcessReader::start(Command::new("cargo"))?;
+    /// while let Some(next) = reader.next_timeout(Duration::from_millis(100)) {
+    ///     match next? {
+    ///         Next::Out(out) => println!("{out:?}"),
+    ///         Next::Timeout => print!("."),
+    ///     }
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Result<Next, io::Error>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(next) = self.process.pop() {
+                return Some(next.map(|event| Next::Out(event.out)));
+            }
+
+            if self.process.is_done() {
+                return None;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Some(Ok(Next::Timeout));
+            }
+
+            let timeout = match self.process.next_poll_timeout() {
+                Some(timeout) => timeout.min(deadline - now),
+                None => deadline - now,
+            };
+            if let Err(err) = self.poll_once(Some(timeout)) {
+                return Some(Err(err));
+            }
+        }
+    }
+
+    /// Returns the next item if one is available without blocking, for
+    /// driving the reader from another event loop.
+    ///
+    /// This reads whatever the child has written so far and returns
+    /// `Ok(None)` if that did not complete an item, as well as once every
+    /// item, including [`Out::Done`], has been yielded.
+    pub fn try_next(&mut self) -> Result<Option<Out>, io::Error> {
+        if let Some(next) = self.process.pop() {
+            return next.map(|event| Some(event.out));
+        }
+
+        if self.process.is_done() {
+            return Ok(None);
+        }
+
+        self.poll_once(Some(Duration::ZERO))?;
+        let next = self.process.pop().transpose()?;
+        Ok(next.map(|event| event.out))
+    }
+
+    /// Reads whatever the child has written so far without blocking, for
+    /// when the reader is registered with another [`mio::Poll`].
+    ///
+    /// Call this whenever that poll reports the reader as readable, and at
+    /// the latest after [`drive_timeout`](Self::drive_timeout), then take the
+    /// items that were read with [`try_next`](Self::try_next) until it
+    /// returns `Ok(None)`.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::{Out, ProcessReader};
+    /// use mio::{Events, Interest, Poll, Token};
+    ///
+    /// let mut poll = Poll::new()?;
+    /// let mut events = Events::with_capacity(16);
+    /// let mut reader = ProcessReader::start(Command::new("ls"))?;
+    /// poll.registry().register(&mut reader, Token(0), Interest::READABLE)?;
+    ///
+    /// loop {
+    ///     poll.poll(&mut events, reader.drive_timeout())?;
+    ///     reader.drive()?;
+    ///     while let Some(out) = reader.try_next()? {
+    ///         if let Out::Done(_) = out {
+    ///             return Ok(());
+    ///         }
+    ///         println!("{out:?}");
+    ///     }
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(unix)]
+    pub fn drive(&mut self) -> Result<(), io::Error> {
+        self.read_now()
+    }
+
+    /// Returns how long another poll that the reader is registered with may
+    /// wait before [`drive`](Self::drive) has to be called regardless of
+    /// readiness, for timeouts and for output that was left unread because
+    /// the queue was full.
+    #[cfg(unix)]
+    pub fn drive_timeout(&self) -> Option<Duration> {
+        self.next_poll_timeout()
+    }
+
+    /// Reads whatever the child has written so far without blocking.
+    #[cfg(any(unix, feature = "tokio"))]
+    pub(crate) fn read_now(&mut self) -> Result<(), io::Error> {
+        if self.process.is_done() {
+            return Ok(());
+        }
+
+        self.poll_once(Some(Duration::ZERO))
+    }
+
+    /// Returns how long to wait for the child at most before reading again.
+    #[cfg(any(unix, feature = "tokio"))]
+    pub(crate) fn next_poll_timeout(&self) -> Option<Duration> {
+        self.process.next_poll_timeout()
+    }
+
+    /// Returns the next queued item or error, if any, without reading.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn pop_event(&mut self) -> Option<Result<Event, io::Error>> {
+        self.process.pop()
+    }
+
+    /// Returns the next queued item or error without removing it.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn peek_event(&self) -> Option<&Result<Event, io::Error>> {
+        self.process.peek()
+    }
+
+    /// Whether the exit status has been queued, after which nothing more is
+    /// read.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn is_done(&self) -> bool {
+        self.process.is_done()
+    }
+
+    /// Returns what happens to the child when the reader is dropped.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn drop_policy(&self) -> DropPolicy {
+        self.process.drop_policy()
+    }
+
+    /// Returns the file descriptor of the reader's poll, which is readable
+    /// whenever [`drive`](Self::drive) has something to read.
+    #[cfg(unix)]
+    pub(crate) fn poll_fd(&self) -> RawFd {
+        self.poll.as_raw_fd()
+    }
+
+    /// Waits for the child for at most `timeout` and reads whatever it wrote.
+    fn poll_once(&mut self, timeout: Option<Duration>) -> Result<(), io::Error> {
+        self.poll.poll(&mut self.events, timeout)?;
+
+        let mut readiness = Readiness::default();
+        for event in self.events.iter() {
+            if let Some((_, source)) = self.tokens.get(event.token()) {
+                readiness.set(source);
+            }
+        }
+
+        self.process.step(self.poll.registry(), &readiness)
+    }
+
+    /// Blocks until a line of stdout or stderr matches `pattern` and returns
+    /// it, for scripting interactive children together with
+    /// [`send_line`](Self::send_line).
+    ///
+    /// The line that is still being written counts as well, so prompts that
+    /// do not end with a newline can be matched. All output up to and
+    /// including the match is consumed. This fails with
+    /// [`io::ErrorKind::TimedOut`] if nothing matched within `timeout`, and
+    /// with [`io::ErrorKind::UnexpectedEof`] if the child exited first, in
+    /// which case its exit status is still yielded by
+    /// [`next`](Iterator::next).
+    ///
+    /// Only lines yielded as [`Out::Stdout`] and [`Out::Stderr`] are matched.
+    #[cfg(feature = "regex")]
+    pub fn wait_for(&mut self, pattern: &Regex, timeout: Duration) -> Result<String, io::Error> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self.process.peek() {
+                Some(Ok(Event {
+                    out: Out::Done(_), ..
+                })) => break,
+                Some(_) => {}
+                None if self.process.is_done() => break,
+                None => {
+                    if let Some(partial) = self.process.take_partial_match(pattern) {
+                        return Ok(partial);
+                    }
+
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "timed out waiting for the pattern to match",
+                        ));
+                    }
+
+                    let timeout = match self.process.next_poll_timeout() {
+                        Some(timeout) => timeout.min(deadline - now),
+                        None => deadline - now,
+                    };
+                    self.poll_once(Some(timeout))?;
+                    continue;
+                }
+            }
+
+            match self.process.pop() {
+                Some(Ok(Event {
+                    out: Out::Stdout(line) | Out::Stderr(line),
+                    ..
+                })) if pattern.is_match(&line) => return Ok(line),
+                Some(Err(err)) => return Err(err),
+                _ => {}
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the child exited before the pattern matched",
+        ))
+    }
+
+    /// Writes `line` followed by a newline to the child's stdin.
+    ///
+    /// This fails with [`io::ErrorKind::NotConnected`] if stdin is not
+    /// available, see [`stdin`](Self::stdin).
+    pub fn send_line(&mut self, line: &str) -> Result<(), io::Error> {
+        let Some(stdin) = self.process.stdin() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the child's stdin is not available",
+            ));
+        };
+
+        stdin.write_all(line.as_bytes())?;
+        stdin.write_all(b"\n")?;
+        stdin.flush()
+    }
+
+    /// Splits the reader into separate iterators over the lines of stdout
+    /// and stderr, which can be consumed from different threads.
+    ///
+    /// Only [`Out::Stdout`] and [`Out::Stderr`] lines are yielded and other
+    /// items are discarded. The exit status is available from the returned
+    /// handle once either iterator has reached its end.
+    ///
+    /// ```no_run
+    /// use std::{process::Command, thread};
+    /// use incremental_command::ProcessReader;
+    ///
+    /// let (stdout, stderr, handle) = ProcessReader::start(Command::new("make"))?.split();
+    /// let errors = thread::spawn(move || stderr.filter_map(Result::ok).count());
+    /// for line in stdout {
+    ///     println!("{}", line?);
+    /// }
+    /// println!("{} lines of stderr", errors.join().unwrap());
+    /// println!("exited with {}", handle.wait()?);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn split(self) -> (StreamLines, StreamLines, ProcessHandle) {
+        let handle = self.handle();
+        let (stdout, stderr) = split::split(self);
+        (stdout, stderr, handle)
+    }
+
+    /// Reads the child's output until it exits and returns all of it at
+    /// once, for when the output is not needed as it arrives.
+    ///
+    /// Only [`Out::Stdout`] and [`Out::Stderr`] lines are collected, so
+    /// nothing is collected in modes that yield bytes or progress updates.
+    /// The first error ends reading and is returned.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::ProcessReader;
+    ///
+    /// let completion = ProcessReader::start(Command::new("ls"))?.run_to_completion()?;
+    /// println!("{} files in {:?}", completion.stdout.len(), completion.duration);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn run_to_completion(mut self) -> Result<Completion, io::Error> {
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        while let Some(event) = self.next_event() {
+            let event = event?;
+            match event.out {
+                Out::Stdout(line) => stdout.push(line),
+                Out::Stderr(line) => stderr.push(line),
+                Out::Done(status) => {
+                    return Ok(Completion {
+                        stdout,
+                        stderr,
+                        status,
+                        duration: event.at.saturating_duration_since(self.process.spawned()),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Err(io::Error::other("the child's exit status was not reported"))
+    }
+
+    /// Like [`run_to_completion`](Self::run_to_completion), but keeps at
+    /// most `threshold` bytes of each stream in memory and moves the rest of
+    /// it to a temporary file, so that very long output does not exhaust
+    /// memory.
+    ///
+    /// Lines are kept with a trailing `\n`, while bytes yielded in
+    /// [`Out::StdoutBytes`] and [`Out::StderrBytes`] are kept as they are.
+    /// The first error ends reading and is returned.
+    ///
+    /// ```no_run
+    /// use std::{io::BufRead, process::Command};
+    /// use incremental_command::ProcessReader;
+    ///
+    /// let completion = ProcessReader::start(Command::new("make"))?
+    ///     .run_to_completion_spilled(16 << 20)?;
+    /// for line in completion.stderr.into_reader()?.lines() {
+    ///     println!("{}", line?);
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn run_to_completion_spilled(
+        mut self,
+        threshold: usize,
+    ) -> Result<SpilledCompletion, io::Error> {
+        let mut stdout = Spill::new(threshold);
+        let mut stderr = Spill::new(threshold);
+        while let Some(event) = self.next_event() {
+            let event = event?;
+            match event.out {
+                Out::Stdout(line) => {
+                    stdout.write(line.as_bytes())?;
+                    stdout.write(b"\n")?;
+                }
+                Out::Stderr(line) => {
+                    stderr.write(line.as_bytes())?;
+                    stderr.write(b"\n")?;
+                }
+                Out::StdoutBytes(bytes) => stdout.write(&bytes)?,
+                Out::StderrBytes(bytes) => stderr.write(&bytes)?,
+                #[cfg(feature = "bytes")]
+                Out::StdoutShared(bytes) => {
+                    stdout.write(&bytes)?;
+                    stdout.write(b"\n")?;
+                }
+                #[cfg(feature = "bytes")]
+                Out::StderrShared(bytes) => {
+                    stderr.write(&bytes)?;
+                    stderr.write(b"\n")?;
+                }
+                Out::Done(status) => {
+                    return Ok(SpilledCompletion {
+                        stdout,
+                        stderr,
+                        status,
+                        duration: event.at.saturating_duration_since(self.process.spawned()),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Err(io::Error::other("the child's exit status was not reported"))
+    }
+}
+
+/// Registers the reader with another [`mio::Poll`], which reports it as
+/// readable whenever the child has written something, so that it can be read
+/// with [`drive`](ProcessReader::drive) from that poll's loop.
+///
+/// The reader keeps its own poll for its pipes, which is registered in its
+/// place, so only [`Interest::READABLE`](mio::Interest::READABLE) is
+/// meaningful.
+#[cfg(unix)]
+impl mio::event::Source for ProcessReader {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> Result<(), io::Error> {
+        SourceFd(&self.poll_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> Result<(), io::Error> {
+        SourceFd(&self.poll_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> Result<(), io::Error> {
+        SourceFd(&self.poll_fd()).deregister(registry)
+    }
+}
+
+impl Iterator for ProcessReader {
+    type Item = Result<Out, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.next_event()?;
+        Some(event.map(|event| event.out))
+    }
+}
+
+impl Drop for ProcessReader {
+    fn drop(&mut self) {
+        if self.process.is_done() {
+            return;
+        }
+
+        match self.process.drop_policy() {
+            DropPolicy::Detach => {}
+            DropPolicy::Kill => {
+                let _ = self.process.kill();
+                let _ = self.process.reap();
+            }
+            DropPolicy::Wait => {
+                drop(self.process.take_stdin());
+                for out in self.by_ref() {
+                    if out.is_err_and(|err| !event::is_recoverable(&err)) {
+                        self.process.close_streams();
+                        let _ = self.process.reap();
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::process::ExitStatus;
+
+use metrics::{counter, gauge};
+
+use crate::{
+    framing::Stream,
+    hooks::{Hook, Hooks},
+    ExitKind,
+};
+
+/// Wraps `hooks` so that a child that was just spawned is counted, and
+/// counted as running until its exit status is reported or its reader is
+/// dropped.
+pub(crate) fn instrument(hooks: Hooks) -> Hooks {
+    counter!("incremental_command_spawned_total").increment(1);
+    let running = Running::start();
+
+    let Hooks {
+        spawn,
+        output,
+        exit,
+    } = hooks;
+
+    let on_exit = move |status: ExitStatus| {
+        drop(running);
+        match ExitKind::from(status) {
+            ExitKind::Code(code) => {
+                counter!("incremental_command_exited_total", "code" => code.to_string())
+                    .increment(1);
+            }
+            ExitKind::Signaled { signal, .. } => {
+                counter!("incremental_command_signaled_total", "signal" => signal.to_string())
+                    .increment(1);
+            }
+        }
+
+        if let Some(hook) = exit {
+            (hook.0)(status);
+        }
+    };
+
+    Hooks {
+        spawn,
+        output,
+        exit: Some(Hook(Box::new(on_exit))),
+    }
+}
+
+/// Counts `n` bytes read from one of the child's streams.
+pub(crate) fn record_read(which: Stream, n: usize) {
+    let stream = match which {
+        Stream::Stdout => "stdout",
+        Stream::Stderr => "stderr",
+    };
+    counter!("incremental_command_read_bytes_total", "stream" => stream).increment(n as u64);
+}
+
+/// Adds `delta` to the number of items queued across all readers.
+pub(crate) fn record_queued(delta: f64) {
+    gauge!("incremental_command_queued").increment(delta);
+}
+
+/// Counts a child as running for as long as it is alive.
+struct Running;
+
+impl Running {
+    fn start() -> Self {
+        gauge!("incremental_command_running").increment(1.0);
+        Self
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        gauge!("incremental_command_running").decrement(1.0);
+    }
+}
@@ -0,0 +1,66 @@
+use std::process::ExitStatus;
+
+use crate::{ExitKind, Out};
+
+/// A resource whose use by the child can be limited with
+/// [`rlimit`](crate::ProcessReaderBuilder::rlimit).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Resource {
+    /// CPU time in seconds, `RLIMIT_CPU`. The child is sent `SIGXCPU` once
+    /// it is used up, and killed a second later if it keeps running.
+    Cpu,
+    /// The size of the child's virtual memory in bytes, `RLIMIT_AS`.
+    AddressSpace,
+    /// The size of the child's data segment and heap in bytes,
+    /// `RLIMIT_DATA`.
+    Data,
+    /// The number of files the child can have open at once,
+    /// `RLIMIT_NOFILE`.
+    OpenFiles,
+    /// The size of the largest file the child can write in bytes,
+    /// `RLIMIT_FSIZE`. Writing past it sends the child `SIGXFSZ`.
+    FileSize,
+}
+
+impl Resource {
+    #[cfg(unix)]
+    pub(crate) fn as_raw(self) -> libc::c_int {
+        (match self {
+            Resource::Cpu => libc::RLIMIT_CPU,
+            Resource::AddressSpace => libc::RLIMIT_AS,
+            Resource::Data => libc::RLIMIT_DATA,
+            Resource::OpenFiles => libc::RLIMIT_NOFILE,
+            Resource::FileSize => libc::RLIMIT_FSIZE,
+        }) as libc::c_int
+    }
+}
+
+/// Returns [`Out::LimitExceeded`] if the child was killed for exceeding one
+/// of `limits`.
+pub(crate) fn report(status: ExitStatus, limits: &[(Resource, u64)]) -> Option<Out> {
+    let ExitKind::Signaled { signal, .. } = ExitKind::from(status) else {
+        return None;
+    };
+
+    #[cfg(unix)]
+    let resource = match signal {
+        libc::SIGXCPU => Resource::Cpu,
+        libc::SIGXFSZ => Resource::FileSize,
+        _ => return None,
+    };
+    #[cfg(not(unix))]
+    let resource = {
+        let _ = signal;
+        return None;
+    };
+
+    limits
+        .iter()
+        .any(|&(limited, _)| limited == resource)
+        .then_some(Out::LimitExceeded(resource))
+}
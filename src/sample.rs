@@ -0,0 +1,48 @@
+use std::time::Instant;
+
+use crate::{Out, Sampling};
+
+/// Picks the output of one stream that is yielded according to a
+/// [`Sampling`].
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    sampling: Sampling,
+    /// How many items have been seen so far.
+    seen: u64,
+    /// When the last item that was kept was read.
+    last_kept: Option<Instant>,
+    /// Whether the last item was kept, which an [`Out::Truncated`] after it
+    /// shares.
+    kept: bool,
+}
+
+impl Sampler {
+    pub(crate) fn new(sampling: Sampling) -> Self {
+        Self {
+            sampling,
+            seen: 0,
+            last_kept: None,
+            kept: true,
+        }
+    }
+
+    /// Returns whether `out`, read at `at`, is yielded.
+    pub(crate) fn keep(&mut self, out: &Out, at: Instant) -> bool {
+        if let Out::Truncated(_) = out {
+            return self.kept;
+        }
+
+        self.kept = match self.sampling {
+            Sampling::Nth(n) => self.seen.is_multiple_of(n),
+            Sampling::Interval(interval) => match self.last_kept {
+                Some(last_kept) => at.saturating_duration_since(last_kept) >= interval,
+                None => true,
+            },
+        };
+        self.seen += 1;
+        if self.kept {
+            self.last_kept = Some(at);
+        }
+        self.kept
+    }
+}
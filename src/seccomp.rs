@@ -0,0 +1,181 @@
+use std::{fmt, io, os::unix::process::CommandExt, process::Command};
+
+/// The most instructions that a seccomp filter may have, `BPF_MAXINSNS`.
+const MAX_LEN: usize = 4096;
+
+/// Set in the system call numbers of the x32 ABI, which is reported as the
+/// same architecture as x86-64.
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// The architecture that system calls are made for, as reported to
+/// filters in `seccomp_data.arch`.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+#[cfg(target_arch = "riscv64")]
+const AUDIT_ARCH: u32 = 0xc000_00f3;
+
+/// The offsets of the fields of `seccomp_data`.
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+mod offset {
+    pub(super) const NR: u32 = 0;
+    pub(super) const ARCH: u32 = 4;
+    /// The lower half of the first argument, on little-endian platforms.
+    pub(super) const ARG0: u32 = 16;
+}
+
+/// A seccomp-bpf filter that restricts which system calls the child can
+/// make, installed with [`seccomp`](crate::ProcessReaderBuilder::seccomp).
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{ProcessReader, SeccompFilter};
+///
+/// let reader = ProcessReader::builder(Command::new("./submission"))
+///     .seccomp(SeccompFilter::deny_network())
+///     .start()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct SeccompFilter {
+    program: Vec<libc::sock_filter>,
+}
+
+impl fmt::Debug for SeccompFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeccompFilter")
+            .field("len", &self.program.len())
+            .finish()
+    }
+}
+
+impl SeccompFilter {
+    /// Creates a filter from a compiled BPF program, in the format written
+    /// by libseccomp's `seccomp_export_bpf`: one 8-byte `sock_filter` per
+    /// instruction, in native byte order.
+    ///
+    /// Fails if `program` is empty, too long or not a whole number of
+    /// instructions. The program itself is only checked by the kernel, when
+    /// the child is spawned.
+    pub fn from_bpf(program: &[u8]) -> Result<Self, io::Error> {
+        if program.is_empty() || !program.len().is_multiple_of(8) || program.len() / 8 > MAX_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a seccomp filter must consist of 1 to 4096 instructions of 8 bytes",
+            ));
+        }
+
+        let program = program
+            .chunks_exact(8)
+            .map(|insn| libc::sock_filter {
+                code: u16::from_ne_bytes([insn[0], insn[1]]),
+                jt: insn[2],
+                jf: insn[3],
+                k: u32::from_ne_bytes([insn[4], insn[5], insn[6], insn[7]]),
+            })
+            .collect();
+        Ok(Self { program })
+    }
+
+    /// A filter that keeps the child from using the network, by failing
+    /// with `EPERM` to create any socket other than a Unix domain socket,
+    /// or to set up an io_uring, which could create them as well.
+    ///
+    /// System calls made for another architecture or ABI than the child's
+    /// own, which could get around the filter, kill the child instead.
+    #[cfg(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    ))]
+    pub fn deny_network() -> Self {
+        use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_JSET, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+        let deny = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        let program = vec![
+            stmt(BPF_LD | BPF_W | BPF_ABS, offset::ARCH),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(BPF_LD | BPF_W | BPF_ABS, offset::NR),
+            jump(BPF_JMP | BPF_JSET | BPF_K, X32_SYSCALL_BIT, 4, 0),
+            jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                libc::SYS_io_uring_setup as u32,
+                3,
+                0,
+            ),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_socket as u32, 0, 3),
+            stmt(BPF_LD | BPF_W | BPF_ABS, offset::ARG0),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, libc::AF_UNIX as u32, 1, 0),
+            stmt(BPF_RET | BPF_K, deny),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW),
+        ];
+        Self { program }
+    }
+}
+
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// Makes the child install `filter`, if any, right before it runs the
+/// program.
+///
+/// This has to be the last of the child's setup, since the filter may deny
+/// the system calls that the rest of it makes.
+pub(crate) fn install(cmd: &mut Command, filter: Option<SeccompFilter>) {
+    let Some(SeccompFilter { program }) = filter else {
+        return;
+    };
+
+    unsafe {
+        cmd.pre_exec(move || {
+            // Without privileges, a filter can only be installed once the
+            // child can no longer gain any, such as by running a setuid
+            // program.
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let prog = libc::sock_fprog {
+                len: program.len() as u16,
+                filter: program.as_ptr().cast_mut(),
+            };
+            let mode = libc::SECCOMP_SET_MODE_FILTER;
+            if libc::syscall(libc::SYS_seccomp, mode, 0, &prog) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,99 @@
+use std::io::{self, Write};
+
+use crate::event::Event;
+
+/// Writes `event` to `writer` as a single line of JSON, for the JSON Lines
+/// format.
+///
+/// ```no_run
+/// use std::{io, process::Command};
+/// use incremental_command::{write_json_line, ProcessReader};
+///
+/// let mut reader = ProcessReader::start(Command::new("ls"))?;
+/// let mut stdout = io::stdout().lock();
+/// while let Some(event) = reader.next_event() {
+///     write_json_line(&mut stdout, &event?)?;
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn write_json_line<W: Write>(mut writer: W, event: &Event) -> Result<(), io::Error> {
+    serde_json::to_writer(&mut writer, event)?;
+    writer.write_all(b"\n")
+}
+
+/// (De)serializes an [`ExitStatus`](std::process::ExitStatus) as its exit
+/// code and, on Unix, the signal that terminated the child and whether it
+/// dumped core.
+pub(crate) mod exit_status {
+    use std::process::ExitStatus;
+
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::ExitKind;
+
+    #[derive(Serialize, Deserialize)]
+    struct Status {
+        code: Option<i32>,
+        #[serde(default)]
+        signal: Option<i32>,
+        #[serde(default)]
+        core_dumped: bool,
+    }
+
+    pub(crate) fn serialize<S: Serializer>(
+        status: &ExitStatus,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let (signal, core_dumped) = match ExitKind::from(*status) {
+            ExitKind::Code(_) => (None, false),
+            ExitKind::Signaled {
+                signal,
+                core_dumped,
+            } => (Some(signal), core_dumped),
+        };
+
+        Status {
+            code: status.code(),
+            signal,
+            core_dumped,
+        }
+        .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<ExitStatus, D::Error> {
+        let status = Status::deserialize(deserializer)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            match status {
+                Status {
+                    signal: Some(signal),
+                    core_dumped,
+                    ..
+                } => Ok(ExitStatus::from_raw(
+                    signal & 0x7f | if core_dumped { 0x80 } else { 0 },
+                )),
+                Status {
+                    code: Some(code), ..
+                } => Ok(ExitStatus::from_raw((code & 0xff) << 8)),
+                _ => Err(de::Error::custom(
+                    "exit status has neither a code nor a signal",
+                )),
+            }
+        }
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::ExitStatusExt;
+
+            match status.code {
+                Some(code) => Ok(ExitStatus::from_raw(code as u32)),
+                None => Err(de::Error::custom("exit status has no code")),
+            }
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use std::{io, path::Path, process::Command, sync::Arc, time::Duration};
+
+use mio::{Events, Token};
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::sys::Ring;
+use crate::{
+    event::{self, Event},
+    process::{Process, Readiness, Tokens},
+    sys::{ExitWatcher, PipeReader, Register},
+    DropPolicy, Out, ProcessHandle, ProcessReader, ProcessReaderBuilder, Stdin,
+};
+
+/// Identifies a process in a [`ProcessSet`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessId(usize);
+
+impl ProcessId {
+    /// Returns the position of the process in the order in which the set
+    /// started them, starting at zero.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Reads the output of many child processes on a single poll loop.
+///
+/// Iterating yields the output of every process tagged with its
+/// [`ProcessId`], in the order in which it was read. Each process ends with
+/// its own [`Out::Done`], and iteration ends once every process has finished.
+/// Processes can be added at any time, even after iteration has ended.
+///
+/// Errors are not tagged, since reading one process may fail for reasons
+/// unrelated to it.
+///
+/// Dropping the set applies the [`DropPolicy`] of each unfinished process,
+/// waiting for all processes with [`DropPolicy::Wait`] together.
+pub struct ProcessSet {
+    /// The processes by index, or `None` once they have finished.
+    processes: Vec<Option<Process>>,
+    readiness: Vec<Readiness>,
+    tokens: Tokens,
+    backend: Backend,
+}
+
+/// How a [`ProcessSet`] waits for its processes.
+enum Backend {
+    Epoll {
+        poll: mio::Poll,
+        events: mio::Events,
+    },
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    IoUring(Box<Ring>),
+}
+
+impl Backend {
+    /// Waits for at most `timeout` and calls `ready` with the token of every
+    /// source that became ready.
+    fn wait(
+        &mut self,
+        timeout: Option<Duration>,
+        mut ready: impl FnMut(Token),
+    ) -> Result<(), io::Error> {
+        match self {
+            Self::Epoll { poll, events } => {
+                poll.poll(events, timeout)?;
+                events.iter().for_each(|event| ready(event.token()));
+                Ok(())
+            }
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Self::IoUring(ring) => ring.wait(timeout, ready),
+        }
+    }
+}
+
+impl Register for Backend {
+    fn register_pipe(&self, reader: &mut PipeReader, token: Token) -> Result<(), io::Error> {
+        match self {
+            Self::Epoll { poll, .. } => poll.registry().register_pipe(reader, token),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Self::IoUring(ring) => ring.register_pipe(reader, token),
+        }
+    }
+
+    fn deregister_pipe(&self, reader: &mut PipeReader) -> Result<(), io::Error> {
+        match self {
+            Self::Epoll { poll, .. } => poll.registry().deregister_pipe(reader),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Self::IoUring(ring) => ring.deregister_pipe(reader),
+        }
+    }
+
+    fn register_exit(&self, watcher: &mut ExitWatcher, token: Token) -> Result<(), io::Error> {
+        match self {
+            Self::Epoll { poll, .. } => poll.registry().register_exit(watcher, token),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Self::IoUring(ring) => ring.register_exit(watcher, token),
+        }
+    }
+
+    fn deregister_exit(&self, watcher: &mut ExitWatcher) -> Result<(), io::Error> {
+        match self {
+            Self::Epoll { poll, .. } => poll.registry().deregister_exit(watcher),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Self::IoUring(ring) => ring.deregister_exit(watcher),
+        }
+    }
+}
+
+impl ProcessSet {
+    /// Creates an empty set.
+    pub fn new() -> Result<Self, io::Error> {
+        Ok(Self::with_backend(Backend::Epoll {
+            poll: mio::Poll::new()?,
+            events: Events::with_capacity(256),
+        }))
+    }
+
+    /// Creates an empty set that polls for output and for its processes to
+    /// exit with io_uring rather than epoll.
+    ///
+    /// The ring only replaces epoll as the poll backend: the polls for every
+    /// process are submitted together in a single system call, which saves
+    /// system calls when multiplexing hundreds of children, but output is
+    /// still read with `read` once it is available. Only sets can use the
+    /// ring, a [`ProcessReader`](crate::ProcessReader) always uses epoll.
+    ///
+    /// This fails if the kernel does not support io_uring or it is disabled,
+    /// such as with the `kernel.io_uring_disabled` sysctl. It requires
+    /// Linux 5.4 or later.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn with_io_uring() -> Result<Self, io::Error> {
+        Ok(Self::with_backend(Backend::IoUring(Box::new(Ring::new()?))))
+    }
+
+    fn with_backend(backend: Backend) -> Self {
+        Self {
+            processes: Vec::new(),
+            readiness: Vec::new(),
+            tokens: Tokens::default(),
+            backend,
+        }
+    }
+
+    /// Spawns `cmd` with its stdout and stderr captured, like
+    /// [`ProcessReader::start`].
+    pub fn start(&mut self, cmd: Command) -> Result<ProcessId, io::Error> {
+        self.spawn(ProcessReader::builder(cmd))
+    }
+
+    /// Spawns a child configured with `builder`.
+    pub fn spawn(&mut self, builder: ProcessReaderBuilder) -> Result<ProcessId, io::Error> {
+        let index = self.processes.len();
+        let process = Process::spawn(builder, &self.backend, &mut self.tokens, index)
+            .inspect_err(|_| self.tokens.release(index))?;
+        self.processes.push(Some(process));
+        Ok(ProcessId(index))
+    }
+
+    /// Returns how many processes have not finished yet.
+    pub fn len(&self) -> usize {
+        self.processes.iter().flatten().count()
+    }
+
+    /// Returns whether every process has finished.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn process(&self, id: ProcessId) -> Option<&Process> {
+        self.processes.get(id.0)?.as_ref()
+    }
+
+    fn process_mut(&mut self, id: ProcessId) -> Option<&mut Process> {
+        self.processes.get_mut(id.0)?.as_mut()
+    }
+
+    /// Returns a handle for controlling a process, or `None` if it has
+    /// finished.
+    pub fn handle(&self, id: ProcessId) -> Option<ProcessHandle> {
+        Some(self.process(id)?.handle())
+    }
+
+    /// Returns a handle for writing to a process's stdin.
+    ///
+    /// See [`ProcessReader::stdin`].
+    pub fn stdin(&mut self, id: ProcessId) -> Option<&mut Stdin> {
+        self.process_mut(id)?.stdin()
+    }
+
+    /// Takes ownership of a process's stdin handle.
+    pub fn take_stdin(&mut self, id: ProcessId) -> Option<Stdin> {
+        self.process_mut(id)?.take_stdin()
+    }
+
+    /// Like [`next`](Iterator::next), but yields the item together with the
+    /// time it was read and its sequence number within its process.
+    pub fn next_event(&mut self) -> Option<Result<(ProcessId, Event), io::Error>> {
+        loop {
+            // Yield whichever queued item was read first across all processes.
+            let next = self
+                .processes
+                .iter()
+                .enumerate()
+                .filter_map(|(index, process)| Some((process.as_ref()?.peek_at()?, index)))
+                .min();
+
+            if let Some((_, index)) = next {
+                let slot = &mut self.processes[index];
+                let process = slot.as_mut()?;
+                let next = process.pop()?;
+                if process.is_done() && process.peek_at().is_none() {
+                    *slot = None;
+                    self.tokens.release(index);
+                }
+
+                return Some(next.map(|event| (ProcessId(index), event)));
+            }
+
+            if self.is_empty() {
+                return None;
+            }
+
+            let timeout = self
+                .processes
+                .iter()
+                .flatten()
+                .filter_map(Process::next_poll_timeout)
+                .min();
+            self.readiness.clear();
+            self.readiness
+                .resize(self.processes.len(), Readiness::default());
+            let (readiness, tokens) = (&mut self.readiness, &self.tokens);
+            let waited = self.backend.wait(timeout, |token| {
+                if let Some((index, source)) = tokens.get(token) {
+                    readiness[index].set(source);
+                }
+            });
+            if let Err(err) = waited {
+                return Some(Err(err));
+            }
+
+            for (process, readiness) in self.processes.iter_mut().zip(&self.readiness) {
+                let Some(process) = process else {
+                    continue;
+                };
+
+                if let Err(err) = process.step(&self.backend, readiness) {
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for ProcessSet {
+    type Item = Result<(ProcessId, Out), io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.next_event()?;
+        Some(next.map(|(id, event)| (id, event.out)))
+    }
+}
+
+impl Drop for ProcessSet {
+    fn drop(&mut self) {
+        for slot in &mut self.processes {
+            let Some(process) = slot else {
+                continue;
+            };
+
+            if process.is_done() {
+                *slot = None;
+                continue;
+            }
+
+            match process.drop_policy() {
+                DropPolicy::Detach => *slot = None,
+                DropPolicy::Kill => {
+                    let _ = process.kill();
+                    let _ = process.reap();
+                    *slot = None;
+                }
+                DropPolicy::Wait => drop(process.take_stdin()),
+            }
+        }
+
+        while let Some(next) = self.next_event() {
+            if next.is_err_and(|err| !event::is_recoverable(&err)) {
+                for process in self.processes.iter_mut().flatten() {
+                    process.close_streams();
+                    let _ = process.reap();
+                }
+                break;
+            }
+        }
+    }
+}
+
+/// An item of output from [`run_all`], tagged with the command it came from.
+#[derive(Clone, Debug)]
+pub struct Tagged {
+    /// The position of the command in the list passed to [`run_all`].
+    pub index: usize,
+    /// The file name of the command's program, such as `cargo`.
+    pub name: Arc<str>,
+    /// The output itself.
+    pub out: Out,
+}
+
+/// Spawns every command in `cmds` and interleaves their output as it arrives,
+/// like the `concurrently` tool for npm.
+///
+/// Each item is tagged with the index and name of the command it came from,
+/// and each command ends with its own [`Out::Done`]. Iteration ends once
+/// every command has finished.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{run_all, Out};
+///
+/// for next in run_all([Command::new("make"), Command::new("npm")])? {
+///     let tagged = next?;
+///     if let Out::Stdout(line) | Out::Stderr(line) = tagged.out {
+///         println!("[{}] {line}", tagged.name);
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn run_all(cmds: impl IntoIterator<Item = Command>) -> Result<RunAll, io::Error> {
+    let mut set = ProcessSet::new()?;
+    let mut names = Vec::new();
+
+    for cmd in cmds {
+        let program = Path::new(cmd.get_program());
+        let name = program.file_name().unwrap_or(program.as_os_str());
+        names.push(Arc::from(name.to_string_lossy()));
+        set.start(cmd)?;
+    }
+
+    Ok(RunAll { set, names })
+}
+
+/// The interleaved output of the commands started by [`run_all`].
+pub struct RunAll {
+    set: ProcessSet,
+    names: Vec<Arc<str>>,
+}
+
+impl RunAll {
+    /// Returns a handle for controlling the command at `index`, or `None` if
+    /// it has finished.
+    pub fn handle(&self, index: usize) -> Option<ProcessHandle> {
+        self.set.handle(ProcessId(index))
+    }
+}
+
+impl Iterator for RunAll {
+    type Item = Result<Tagged, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.set.next()?;
+        Some(next.map(|(id, out)| Tagged {
+            index: id.0,
+            name: self.names[id.0].clone(),
+            out,
+        }))
+    }
+}
@@ -0,0 +1,114 @@
+use std::{io, process::ExitStatus};
+
+use crate::{Out, ProcessReader};
+
+/// A handler that output is pushed into by
+/// [`ProcessReader::run_with_sinks`].
+///
+/// Closures taking an [`Out`] by reference are sinks as well.
+pub trait OutputSink {
+    /// Handles the next item.
+    fn event(&mut self, out: &Out);
+}
+
+impl<F: FnMut(&Out)> OutputSink for F {
+    fn event(&mut self, out: &Out) {
+        self(out)
+    }
+}
+
+/// A sink that forwards lines to the [`log`](https://docs.rs/log) facade,
+/// stdout at [`Info`](log::Level::Info) and stderr at
+/// [`Warn`](log::Level::Warn), under a target of its own.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{LogSink, ProcessReader};
+///
+/// let mut sink = LogSink::new("worker").stderr_level(log::Level::Error);
+/// let reader = ProcessReader::start(Command::new("./worker"))?;
+/// reader.run_with_sinks(&mut [&mut sink])?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(feature = "log")]
+#[derive(Clone, Debug)]
+pub struct LogSink {
+    target: String,
+    stderr_level: log::Level,
+}
+
+#[cfg(feature = "log")]
+impl LogSink {
+    /// Creates a sink that logs under `target`, such as the name of the
+    /// service the child runs.
+    pub fn new(target: impl Into<String>) -> Self {
+        Self {
+            target: target.into(),
+            stderr_level: log::Level::Warn,
+        }
+    }
+
+    /// Sets the level that lines of stderr are logged at. Defaults to
+    /// [`Warn`](log::Level::Warn).
+    pub fn stderr_level(mut self, level: log::Level) -> Self {
+        self.stderr_level = level;
+        self
+    }
+}
+
+#[cfg(feature = "log")]
+impl OutputSink for LogSink {
+    fn event(&mut self, out: &Out) {
+        match out {
+            Out::Stdout(line) => log::info!(target: &self.target, "{line}"),
+            Out::Stderr(line) => log::log!(target: &self.target, self.stderr_level, "{line}"),
+            _ => {}
+        }
+    }
+}
+
+impl ProcessReader {
+    /// Reads the child's output until it exits, passing every item to each
+    /// of `sinks` in turn, and returns its exit status.
+    ///
+    /// The first error ends reading and is returned.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use incremental_command::{Out, OutputSink, ProcessReader};
+    ///
+    /// struct Counter(usize);
+    ///
+    /// impl OutputSink for Counter {
+    ///     fn event(&mut self, out: &Out) {
+    ///         if let Out::Stdout(_) = out {
+    ///             self.0 += 1;
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut counter = Counter(0);
+    /// let mut log = |out: &Out| println!("{out:?}");
+    /// let reader = ProcessReader::start(Command::new("ls"))?;
+    /// let status = reader.run_with_sinks(&mut [&mut counter, &mut log])?;
+    /// println!("{} lines, {status}", counter.0);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn run_with_sinks(
+        mut self,
+        sinks: &mut [&mut dyn OutputSink],
+    ) -> Result<ExitStatus, io::Error> {
+        for out in self.by_ref() {
+            let out = out?;
+            for sink in sinks.iter_mut() {
+                sink.event(&out);
+            }
+
+            if let Out::Done(status) = out {
+                return Ok(status);
+            }
+        }
+
+        Err(io::Error::other("the child's exit status was not reported"))
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    mem,
+    process::{self, ExitStatus},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// Everything a child wrote and how it exited, returned by
+/// [`ProcessReader::run_to_completion_spilled`](crate::ProcessReader::run_to_completion_spilled).
+#[derive(Debug)]
+pub struct SpilledCompletion {
+    /// The output written to stdout.
+    pub stdout: Spill,
+    /// The output written to stderr.
+    pub stderr: Spill,
+    /// The exit status of the child.
+    pub status: ExitStatus,
+    /// How long the child ran, from spawning it until it was seen to exit.
+    pub duration: Duration,
+}
+
+/// The output of one of the child's streams, kept in memory up to a
+/// threshold and moved to a temporary file once it grows past that.
+///
+/// The file is deleted as soon as the spill is dropped, or on Unix as soon
+/// as it is created, so that it is cleaned up even if the parent crashes.
+#[derive(Debug)]
+pub struct Spill {
+    buf: Buffer,
+    len: u64,
+    threshold: usize,
+}
+
+#[derive(Debug)]
+enum Buffer {
+    Memory(Vec<u8>),
+    File(BufWriter<File>),
+}
+
+impl Spill {
+    pub(crate) fn new(threshold: usize) -> Self {
+        Self {
+            buf: Buffer::Memory(Vec::new()),
+            len: 0,
+            threshold,
+        }
+    }
+
+    /// Appends `bytes`, moving everything to a temporary file first if they
+    /// would grow the output past the threshold.
+    pub(crate) fn write(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        if let Buffer::Memory(memory) = &mut self.buf {
+            if memory.len() + bytes.len() > self.threshold {
+                let mut file = BufWriter::new(temp_file()?);
+                file.write_all(&mem::take(memory))?;
+                self.buf = Buffer::File(file);
+            }
+        }
+
+        match &mut self.buf {
+            Buffer::Memory(memory) => memory.extend_from_slice(bytes),
+            Buffer::File(file) => file.write_all(bytes)?,
+        }
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the number of bytes of output.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether there is no output.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns whether the output has been moved to a temporary file.
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.buf, Buffer::File(_))
+    }
+
+    /// Returns a reader over the output from its start, wherever it is kept.
+    pub fn into_reader(self) -> Result<SpillReader, io::Error> {
+        let inner = match self.buf {
+            Buffer::Memory(memory) => Source::Memory(Cursor::new(memory)),
+            Buffer::File(file) => {
+                let mut file = file.into_inner().map_err(|err| err.into_error())?;
+                file.seek(SeekFrom::Start(0))?;
+                Source::File(BufReader::new(file))
+            }
+        };
+        Ok(SpillReader { inner })
+    }
+}
+
+/// Reads the output kept by a [`Spill`], returned by
+/// [`Spill::into_reader`].
+///
+/// Lines can be read one at a time with [`BufRead::lines`], without loading
+/// all of the output into memory.
+#[derive(Debug)]
+pub struct SpillReader {
+    inner: Source,
+}
+
+#[derive(Debug)]
+enum Source {
+    Memory(Cursor<Vec<u8>>),
+    File(BufReader<File>),
+}
+
+impl Read for SpillReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        match &mut self.inner {
+            Source::Memory(memory) => memory.read(buf),
+            Source::File(file) => file.read(buf),
+        }
+    }
+}
+
+impl BufRead for SpillReader {
+    fn fill_buf(&mut self) -> Result<&[u8], io::Error> {
+        match &mut self.inner {
+            Source::Memory(memory) => memory.fill_buf(),
+            Source::File(file) => file.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match &mut self.inner {
+            Source::Memory(memory) => memory.consume(amount),
+            Source::File(file) => file.consume(amount),
+        }
+    }
+}
+
+/// Creates a file in the temporary directory that is deleted once it is
+/// closed.
+fn temp_file() -> Result<File, io::Error> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let name = format!(
+        "incremental-command-{}-{}",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = env::temp_dir().join(name);
+
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        use windows_sys::Win32::Storage::FileSystem::FILE_FLAG_DELETE_ON_CLOSE;
+        options.custom_flags(FILE_FLAG_DELETE_ON_CLOSE);
+    }
+
+    let file = options.open(&path)?;
+    #[cfg(unix)]
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
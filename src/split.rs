@@ -0,0 +1,132 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+use crate::{framing::Stream, Out, ProcessReader};
+
+/// The lines of one of the child's streams, created with
+/// [`ProcessReader::split`].
+///
+/// Both halves share the reader. Whichever half runs out of lines reads from
+/// the child on behalf of both, queueing lines for the other half until it
+/// asks for them, while the other half waits for lines to arrive. Once a half
+/// is dropped, the lines of its stream are discarded.
+pub struct StreamLines {
+    which: Stream,
+    shared: Arc<SplitShared>,
+}
+
+struct SplitShared {
+    state: Mutex<SplitState>,
+    changed: Condvar,
+}
+
+struct SplitState {
+    /// The reader, or `None` while one of the halves is reading from it.
+    reader: Option<ProcessReader>,
+    stdout: Half,
+    stderr: Half,
+    done: bool,
+}
+
+#[derive(Default)]
+struct Half {
+    queue: VecDeque<Result<String, io::Error>>,
+    dropped: bool,
+}
+
+impl SplitState {
+    fn half(&mut self, which: Stream) -> &mut Half {
+        match which {
+            Stream::Stdout => &mut self.stdout,
+            Stream::Stderr => &mut self.stderr,
+        }
+    }
+
+    fn push(&mut self, which: Stream, line: Result<String, io::Error>) {
+        let half = self.half(which);
+        if !half.dropped {
+            half.queue.push_back(line);
+        }
+    }
+}
+
+impl SplitShared {
+    fn lock(&self) -> MutexGuard<'_, SplitState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+pub(crate) fn split(reader: ProcessReader) -> (StreamLines, StreamLines) {
+    let shared = Arc::new(SplitShared {
+        state: Mutex::new(SplitState {
+            reader: Some(reader),
+            stdout: Half::default(),
+            stderr: Half::default(),
+            done: false,
+        }),
+        changed: Condvar::new(),
+    });
+
+    let stdout = StreamLines {
+        which: Stream::Stdout,
+        shared: shared.clone(),
+    };
+    let stderr = StreamLines {
+        which: Stream::Stderr,
+        shared,
+    };
+    (stdout, stderr)
+}
+
+impl Iterator for StreamLines {
+    type Item = Result<String, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(line) = state.half(self.which).queue.pop_front() {
+                return Some(line);
+            }
+
+            if state.done {
+                return None;
+            }
+
+            let Some(mut reader) = state.reader.take() else {
+                state = self
+                    .shared
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|err| err.into_inner());
+                continue;
+            };
+
+            drop(state);
+            let next = reader.next();
+            state = self.shared.lock();
+            state.reader = Some(reader);
+
+            match next {
+                Some(Ok(Out::Stdout(line))) => state.push(Stream::Stdout, Ok(line)),
+                Some(Ok(Out::Stderr(line))) => state.push(Stream::Stderr, Ok(line)),
+                Some(Ok(_)) => {}
+                // Errors are yielded by the half that ran into them.
+                Some(Err(err)) => state.push(self.which, Err(err)),
+                None => state.done = true,
+            }
+            self.shared.changed.notify_all();
+        }
+    }
+}
+
+impl Drop for StreamLines {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        let half = state.half(self.which);
+        half.dropped = true;
+        half.queue.clear();
+    }
+}
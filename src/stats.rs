@@ -0,0 +1,184 @@
+use std::time::{Duration, Instant};
+
+use crate::framing::Stream;
+
+/// How long it takes for the estimate of
+/// [`StreamTiming::bytes_per_sec`] to forget about earlier output.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// How much a reader has read so far, returned by
+/// [`ProcessReader::stats`](crate::ProcessReader::stats), for example to show
+/// its throughput while the child runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The bytes read from stdout.
+    pub stdout_bytes: u64,
+    /// The bytes read from stderr.
+    pub stderr_bytes: u64,
+    /// The lines of stdout and stderr yielded so far.
+    pub lines: u64,
+    /// The lines read from stdout, including those that were not yielded
+    /// because of [`sample`](crate::ProcessReaderBuilder::sample) or a limit.
+    pub stdout_lines: u64,
+    /// The lines read from stderr. See [`stdout_lines`](Self::stdout_lines).
+    pub stderr_lines: u64,
+    /// The items dropped because the queue was full, see
+    /// [`ProcessReader::dropped`](crate::ProcessReader::dropped).
+    pub dropped: u64,
+    /// How often the reader woke up to read from the child or to check its
+    /// timeouts.
+    pub wakeups: u64,
+    /// The time since the child was spawned.
+    pub elapsed: Duration,
+    /// When and how fast stdout has been read.
+    pub stdout_timing: StreamTiming,
+    /// When and how fast stderr has been read.
+    pub stderr_timing: StreamTiming,
+}
+
+impl Stats {
+    /// Counts `n` bytes read from one of the child's streams at `at`.
+    pub(crate) fn add_read(&mut self, which: Stream, n: usize, at: Instant) {
+        let (bytes, timing) = match which {
+            Stream::Stdout => (&mut self.stdout_bytes, &mut self.stdout_timing),
+            Stream::Stderr => (&mut self.stderr_bytes, &mut self.stderr_timing),
+        };
+        *bytes += n as u64;
+        timing.record(n, at);
+    }
+}
+
+/// When and how fast one of the child's streams has been read, for noticing
+/// when a child starts lagging or stalls.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StreamTiming {
+    gaps: Histogram,
+    last_read: Option<Instant>,
+    /// The estimated rate in bytes per second as of `last_read`.
+    rate: f64,
+}
+
+impl StreamTiming {
+    fn record(&mut self, n: usize, at: Instant) {
+        let mut rate = self.rate;
+        if let Some(last_read) = self.last_read {
+            let gap = at.saturating_duration_since(last_read);
+            self.gaps.record(gap);
+            rate = decay(rate, gap);
+        }
+        self.rate = rate + n as f64 / RATE_WINDOW.as_secs_f64();
+        self.last_read = Some(at);
+    }
+
+    /// Returns the distribution of the time between consecutive reads that
+    /// returned output.
+    pub fn gaps(&self) -> &Histogram {
+        &self.gaps
+    }
+
+    /// Returns how long ago output was last read, or `None` if none has been
+    /// read yet.
+    pub fn since_last_read(&self) -> Option<Duration> {
+        Some(self.last_read?.elapsed())
+    }
+
+    /// Returns the rate at which output is read, in bytes per second,
+    /// weighted towards roughly the last second. This drops towards zero
+    /// while the stream is silent.
+    pub fn bytes_per_sec(&self) -> f64 {
+        match self.since_last_read() {
+            Some(since) => decay(self.rate, since),
+            None => 0.0,
+        }
+    }
+}
+
+/// Returns what is left of `rate` after `elapsed` without any output.
+fn decay(rate: f64, elapsed: Duration) -> f64 {
+    rate * (-elapsed.as_secs_f64() / RATE_WINDOW.as_secs_f64()).exp()
+}
+
+/// A histogram of durations in buckets that are each twice as wide as the
+/// previous one, so that it covers anything from nanoseconds to years in a
+/// fixed amount of space, with values accurate to within a factor of two.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    /// The number of values in `[2^(i - 1), 2^i)` nanoseconds at index `i`,
+    /// with zero at index zero.
+    counts: [u64; 64],
+    min: Duration,
+    max: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; 64],
+            min: Duration::MAX,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&mut self, value: Duration) {
+        let nanos = u64::try_from(value.as_nanos()).unwrap_or(u64::MAX);
+        let index = (u64::BITS - nanos.leading_zeros()).min(63) as usize;
+        self.counts[index] += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Returns how many values have been recorded.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the smallest value recorded, or `None` if there are none.
+    pub fn min(&self) -> Option<Duration> {
+        (self.count() > 0).then_some(self.min)
+    }
+
+    /// Returns the largest value recorded, or `None` if there are none.
+    pub fn max(&self) -> Option<Duration> {
+        (self.count() > 0).then_some(self.max)
+    }
+
+    /// Returns an upper bound for the value below which the fraction
+    /// `quantile` of values lie, such as `0.99` for the 99th percentile, or
+    /// `None` if there are no values.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((quantile.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (upper, n) in self.buckets() {
+            seen += n;
+            if seen >= rank {
+                return Some(upper.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// Returns the non-empty buckets in increasing order, as the largest
+    /// value that falls into each and how many values did.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n > 0)
+            .map(|(index, n)| {
+                let upper = match index {
+                    0 => 0,
+                    63 => u64::MAX,
+                    _ => (1 << index) - 1,
+                };
+                (Duration::from_nanos(upper), *n)
+            })
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+};
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle, RawHandle};
+
+/// A handle for writing to the child's stdin.
+///
+/// Returned by [`ProcessReader::stdin`](crate::ProcessReader::stdin). Writes
+/// block until the child has consumed enough input, even if the underlying
+/// file descriptor is non-blocking. Dropping the handle closes the child's
+/// stdin.
+#[derive(Debug)]
+pub struct Stdin {
+    file: File,
+}
+
+impl Stdin {
+    pub(crate) fn new(file: File) -> Self {
+        Self { file }
+    }
+
+    /// Returns the file the handle writes to.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn into_file(self) -> File {
+        self.file
+    }
+
+    /// Waits until the file descriptor is writable.
+    #[cfg(unix)]
+    fn wait_writable(&self) -> Result<(), io::Error> {
+        let mut fd = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+
+        loop {
+            if unsafe { libc::poll(&mut fd, 1, -1) } != -1 {
+                return Ok(());
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
+
+impl Write for Stdin {
+    #[cfg(unix)]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.file.write(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => self.wait_writable()?,
+                res => return res,
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Stdin {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsRawHandle for Stdin {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.file.as_raw_handle()
+    }
+}
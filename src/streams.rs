@@ -0,0 +1,340 @@
+use std::{
+    io::{self, Read},
+    sync::Arc,
+    time::Instant,
+};
+
+#[cfg(target_os = "linux")]
+use crate::tee::Tee;
+use crate::{
+    builder::grow_read_buf,
+    decode::Decoder,
+    event::OutputQueue,
+    framing::{Framer, Stream, StreamOptions},
+    sys::PipeReader,
+    LineOverflow, Mode, Out,
+};
+
+/// What a stream is read from, which decides what its output is yielded as.
+#[derive(Clone, Debug)]
+pub(crate) enum Tag {
+    /// The child's stdout or stderr, framed according to the mode.
+    Output(Stream),
+    /// A file descriptor of the child, whose lines are yielded as
+    /// [`Out::Fd`].
+    Fd(i32),
+    /// A source that was added by name, whose lines are yielded as
+    /// [`Out::Source`].
+    Source(Arc<str>),
+}
+
+impl Tag {
+    fn out(&self, line: String) -> Out {
+        match self {
+            Self::Output(Stream::Stdout) => Out::Stdout(line),
+            Self::Output(Stream::Stderr) => Out::Stderr(line),
+            Self::Fd(fd) => Out::Fd { fd: *fd, line },
+            Self::Source(name) => Out::Source {
+                name: name.clone(),
+                line,
+            },
+        }
+    }
+}
+
+/// How the output of a stream is turned into items.
+enum Framing {
+    Output(Box<Framer>),
+    Lines(Box<LineFramer>),
+}
+
+/// One of the streams that a process reads, which is registered under a
+/// token of its own, by its position among the process's streams.
+pub(crate) struct ProcessStream {
+    tag: Tag,
+    /// The read end, or `None` if the stream is not read or has been closed.
+    pub(crate) read: Option<PipeReader>,
+    /// Whether there may be more to read without a readiness event to tell,
+    /// because the queue was full or reading was paused.
+    pub(crate) pending: bool,
+    framing: Framing,
+    /// When the stream was last read from.
+    pub(crate) activity: Instant,
+}
+
+impl ProcessStream {
+    /// Reads the child's stdout or stderr from `read`, if it is captured.
+    pub(crate) fn output(read: Option<PipeReader>, framer: Framer, spawned: Instant) -> Self {
+        let which = framer.which();
+        Self {
+            tag: Tag::Output(which),
+            read,
+            pending: false,
+            framing: Framing::Output(Box::new(framer)),
+            activity: spawned,
+        }
+    }
+
+    /// Reads a stream besides stdout and stderr, which is split into lines.
+    pub(crate) fn lines(
+        tag: Tag,
+        read: PipeReader,
+        framing: &LineFraming,
+        spawned: Instant,
+    ) -> Self {
+        Self {
+            tag,
+            read: Some(read),
+            pending: false,
+            framing: Framing::Lines(Box::new(framing.framer())),
+            activity: spawned,
+        }
+    }
+
+    /// Returns the child's output stream that this is, if any.
+    pub(crate) fn which(&self) -> Option<Stream> {
+        match self.tag {
+            Tag::Output(which) => Some(which),
+            _ => None,
+        }
+    }
+
+    /// Returns whether this was added as a source, rather than being one of
+    /// the child's streams.
+    pub(crate) fn is_source(&self) -> bool {
+        matches!(self.tag, Tag::Source(_))
+    }
+
+    /// Returns the framer of stdout or stderr.
+    #[cfg(feature = "regex")]
+    pub(crate) fn output_framer(&mut self) -> Option<&mut Framer> {
+        match &mut self.framing {
+            Framing::Output(framer) => Some(framer),
+            Framing::Lines(_) => None,
+        }
+    }
+
+    /// Reads whatever is available, returning how many bytes were read and
+    /// why reading stopped. Unless `bounded` is false, this stops early once
+    /// the queue is full.
+    pub(crate) fn read(
+        &mut self,
+        out_buf: &mut OutputQueue,
+        read_buf: &mut Vec<u8>,
+        bounded: bool,
+    ) -> Result<(usize, ReadEnd), io::Error> {
+        let Some(reader) = &mut self.read else {
+            return Ok((0, ReadEnd::Drained));
+        };
+
+        // Output that is only written to a file is moved there without
+        // copying it through the reader, if the kernel supports it.
+        #[cfg(target_os = "linux")]
+        let (spliced, end) = match &mut self.framing {
+            Framing::Output(framer) => match framer.tee_only().cloned() {
+                Some(tee) => splice_pipe(reader, &tee, framer, out_buf),
+                None => (0, None),
+            },
+            Framing::Lines(_) => (0, None),
+        };
+        #[cfg(not(target_os = "linux"))]
+        let (spliced, end) = (0, None);
+
+        match end {
+            Some(end) => Ok((spliced, end)),
+            None => {
+                let (n, end) = read_pipe(
+                    reader,
+                    &self.tag,
+                    &mut self.framing,
+                    out_buf,
+                    read_buf,
+                    bounded,
+                )?;
+                Ok((spliced + n, end))
+            }
+        }
+    }
+
+    /// Queues the incomplete last record, if any.
+    pub(crate) fn flush(&mut self, out_buf: &mut OutputQueue) {
+        self.framing.flush(&self.tag, out_buf);
+    }
+}
+
+impl Framing {
+    fn push(&mut self, tag: &Tag, bytes: &[u8], out_buf: &mut OutputQueue) {
+        match self {
+            Self::Output(framer) => framer.push(bytes, out_buf),
+            Self::Lines(framer) => framer.push(bytes, out_buf, |line| tag.out(line)),
+        }
+    }
+
+    fn flush(&mut self, tag: &Tag, out_buf: &mut OutputQueue) {
+        match self {
+            Self::Output(framer) => framer.flush(out_buf),
+            Self::Lines(framer) => framer.flush(out_buf, |line| tag.out(line)),
+        }
+    }
+}
+
+/// Why [`read_pipe`] stopped reading.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReadEnd {
+    /// Everything available has been read.
+    Drained,
+    /// The child closed the stream.
+    Eof,
+    /// The queue is full. More may be available, but since the pipe is
+    /// edge-triggered there will be no further readiness event for it.
+    QueueFull,
+}
+
+/// Moves whatever is available in a pipe to the file of `tee` with
+/// `splice`, like [`read_pipe`]. Returns how many bytes were moved, and no
+/// end if the rest has to be read as usual.
+#[cfg(target_os = "linux")]
+fn splice_pipe(
+    reader: &PipeReader,
+    tee: &Tee,
+    framer: &mut Framer,
+    out_buf: &mut OutputQueue,
+) -> (usize, Option<ReadEnd>) {
+    use std::os::unix::io::AsRawFd;
+
+    let mut total = 0;
+    loop {
+        let n = match tee.splice(reader.as_raw_fd()) {
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                return (total, Some(ReadEnd::Drained));
+            }
+            // A pty can't be spliced from, and reading reports an error
+            // writing to the file the same way as without splicing.
+            Err(_) => return (total, None),
+        };
+
+        out_buf.stamp();
+        if n == 0 {
+            framer.flush(out_buf);
+            return (total, Some(ReadEnd::Eof));
+        }
+
+        total += n;
+    }
+}
+
+/// Reads from `reader` until it would block, stopping early if `bounded` and
+/// the queue is full.
+fn read_pipe(
+    reader: &mut impl Read,
+    tag: &Tag,
+    framing: &mut Framing,
+    out_buf: &mut OutputQueue,
+    buf: &mut Vec<u8>,
+    bounded: bool,
+) -> Result<(usize, ReadEnd), io::Error> {
+    let mut total = 0;
+    loop {
+        if bounded && !out_buf.has_room() {
+            return Ok((total, ReadEnd::QueueFull));
+        }
+
+        let n = match reader.read(buf) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                return Ok((total, ReadEnd::Drained));
+            }
+            Ok(n) => Ok(n),
+            err => err,
+        }?;
+
+        out_buf.stamp();
+        if n == 0 {
+            framing.flush(tag, out_buf);
+            return Ok((total, ReadEnd::Eof));
+        }
+
+        total += n;
+        framing.push(tag, &buf[..n], out_buf);
+        grow_read_buf(buf, n);
+    }
+}
+
+/// How the lines of the streams besides stdout and stderr are split and
+/// decoded, which is like stdout.
+#[derive(Clone, Debug)]
+pub(crate) struct LineFraming {
+    pub(crate) mode: Mode,
+    pub(crate) delimiter: Vec<u8>,
+    pub(crate) max_line_length: Option<(usize, LineOverflow)>,
+    pub(crate) decoder: Decoder,
+}
+
+impl LineFraming {
+    pub(crate) fn framer(&self) -> LineFramer {
+        // Only lines of text can be yielded for these streams, so the modes
+        // that yield bytes split lines like `Mode::Lines`.
+        let mode = match self.mode {
+            Mode::Lines | Mode::Progress | Mode::Raw => self.mode,
+            _ => Mode::Lines,
+        };
+        let options = StreamOptions {
+            delimiter: self.delimiter.clone(),
+            ..StreamOptions::default()
+        };
+
+        LineFramer {
+            framer: Framer::new(
+                Stream::Stdout,
+                mode,
+                options,
+                self.max_line_length,
+                self.decoder,
+                None,
+                None,
+            ),
+            framed: OutputQueue::new(false, None),
+        }
+    }
+}
+
+/// Splits the output of a stream besides stdout and stderr into lines.
+pub(crate) struct LineFramer {
+    framer: Framer,
+    /// Lines are framed as stdout into this queue, and moved out of it as
+    /// the item that the stream makes of them right away.
+    framed: OutputQueue,
+}
+
+impl LineFramer {
+    /// Queues every complete line in `bytes` as the item that `out` makes of
+    /// it, keeping the rest for the next call.
+    pub(crate) fn push(
+        &mut self,
+        bytes: &[u8],
+        out_buf: &mut OutputQueue,
+        out: impl Fn(String) -> Out,
+    ) {
+        self.framer.push(bytes, &mut self.framed);
+        self.forward(out_buf, out);
+    }
+
+    /// Queues the incomplete last line, if any.
+    pub(crate) fn flush(&mut self, out_buf: &mut OutputQueue, out: impl Fn(String) -> Out) {
+        self.framer.flush(&mut self.framed);
+        self.forward(out_buf, out);
+    }
+
+    fn forward(&mut self, out_buf: &mut OutputQueue, out: impl Fn(String) -> Out) {
+        while let Some(event) = self.framed.pop_front() {
+            match event.map(|event| event.out) {
+                Ok(Out::Stdout(line)) => out_buf.push_back(out(line)),
+                Ok(Out::Truncated(discarded)) => out_buf.push_back(Out::Truncated(discarded)),
+                // In-place updates are not yielded, only the line they end
+                // up as.
+                Ok(_) => continue,
+                Err(err) => out_buf.push_error(err),
+            };
+        }
+    }
+}
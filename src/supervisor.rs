@@ -0,0 +1,210 @@
+use std::{io, process::ExitStatus, thread, time::Duration};
+
+use crate::{Error, Out, ProcessHandle, ProcessReader, ProcessReaderBuilder};
+
+/// When a [`Supervisor`] restarts its child after it has exited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Never restart the child.
+    Never,
+    /// Restart the child whenever it exits.
+    Always,
+    /// Restart the child only if it did not exit successfully.
+    #[default]
+    OnFailure,
+}
+
+/// An item produced by a [`Supervisor`].
+#[derive(Clone, Debug)]
+pub enum SupervisorEvent {
+    /// A child has been spawned. `restarts` is zero for the first one.
+    Started { pid: u32, restarts: u32 },
+    /// Output of the current child. This is never [`Out::Done`], which is
+    /// reported as [`SupervisorEvent::Exited`] instead.
+    Output(Out),
+    /// The current child has exited.
+    Exited(ExitStatus),
+    /// The child will be restarted once `delay` has passed. This is the
+    /// `restarts`-th restart.
+    Restarting { restarts: u32, delay: Duration },
+}
+
+/// Runs a command and restarts it according to a [`RestartPolicy`].
+///
+/// Iterating yields the output of each child interleaved with its lifecycle
+/// events, and ends once the child has exited without being restarted. The
+/// delay before a restart doubles every time, from the initial
+/// [`backoff`](Self::backoff) up to its maximum, and iteration blocks while
+/// waiting for it. If a child cannot be spawned, the error is yielded and
+/// supervision ends.
+///
+/// Since a [`Command`](std::process::Command) can only be spawned once with a
+/// given configuration, the supervisor takes a closure that creates a fresh
+/// one for every start.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use incremental_command::{RestartPolicy, Supervisor, SupervisorEvent};
+///
+/// let supervisor = Supervisor::new(|| Command::new("my-server"))
+///     .restart(RestartPolicy::Always)
+///     .max_restarts(5);
+/// for event in supervisor {
+///     match event? {
+///         SupervisorEvent::Output(out) => println!("{out:?}"),
+///         SupervisorEvent::Restarting { delay, .. } => println!("restarting in {delay:?}"),
+///         _ => {}
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Supervisor {
+    spawn: Box<dyn FnMut() -> ProcessReaderBuilder + Send>,
+    policy: RestartPolicy,
+    max_restarts: Option<u32>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    reader: Option<ProcessReader>,
+    restarts: u32,
+    /// The delay before the next start, or `None` once supervision has ended.
+    next_start: Option<Duration>,
+    /// The restart to announce after the exit status has been yielded.
+    restarting: Option<SupervisorEvent>,
+    stopped: bool,
+}
+
+impl Supervisor {
+    /// Creates a supervisor that starts the child built by `spawn`, which may
+    /// return a [`Command`](std::process::Command) or a
+    /// [`ProcessReaderBuilder`].
+    pub fn new<F, B>(mut spawn: F) -> Self
+    where
+        F: FnMut() -> B + Send + 'static,
+        B: Into<ProcessReaderBuilder>,
+    {
+        Self {
+            spawn: Box::new(move || spawn().into()),
+            policy: RestartPolicy::default(),
+            max_restarts: None,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            reader: None,
+            restarts: 0,
+            next_start: Some(Duration::ZERO),
+            restarting: None,
+            stopped: false,
+        }
+    }
+
+    /// Sets when the child is restarted. Defaults to
+    /// [`RestartPolicy::OnFailure`].
+    pub fn restart(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets how often the child is restarted at most. Defaults to no limit.
+    pub fn max_restarts(mut self, max: u32) -> Self {
+        self.max_restarts = Some(max);
+        self
+    }
+
+    /// Sets the delay before the first restart and the maximum it doubles up
+    /// to. Defaults to 100 milliseconds and 30 seconds.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Returns a handle for the current child, or `None` while none is
+    /// running.
+    ///
+    /// Killing the child through the handle counts as a failure, so it may
+    /// be restarted. Use [`stop`](Self::stop) to end supervision instead.
+    pub fn handle(&self) -> Option<ProcessHandle> {
+        Some(self.reader.as_ref()?.handle())
+    }
+
+    /// Kills the current child, if any, and ends supervision once its
+    /// remaining output has been yielded.
+    pub fn stop(&mut self) -> Result<(), io::Error> {
+        self.stopped = true;
+        self.next_start = None;
+        self.restarting = None;
+        match &self.reader {
+            Some(reader) => reader.handle().kill(),
+            None => Ok(()),
+        }
+    }
+
+    fn should_restart(&self, status: ExitStatus) -> bool {
+        let wanted = match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => !status.success(),
+        };
+
+        wanted && !self.stopped && self.max_restarts.is_none_or(|max| self.restarts < max)
+    }
+
+    fn backoff_delay(&self) -> Duration {
+        let factor = 2u32.saturating_pow(self.restarts);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+impl Iterator for Supervisor {
+    type Item = Result<SupervisorEvent, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(restarting) = self.restarting.take() {
+            return Some(Ok(restarting));
+        }
+
+        let Some(reader) = &mut self.reader else {
+            let delay = self.next_start.take()?;
+            thread::sleep(delay);
+
+            let reader = match (self.spawn)().start() {
+                Ok(reader) => reader,
+                Err(err) => return Some(Err(err)),
+            };
+            let pid = reader.id();
+            self.reader = Some(reader);
+            return Some(Ok(SupervisorEvent::Started {
+                pid,
+                restarts: self.restarts,
+            }));
+        };
+
+        let out = match reader.next()? {
+            // A failed exit of a checked child still ends it like any other.
+            Err(err) => match Error::from_io(&err) {
+                Some(Error::NonZeroExit { status, .. }) => Ok(Out::Done(*status)),
+                _ => Err(err),
+            },
+            out => out,
+        };
+
+        match out {
+            Ok(Out::Done(status)) => {
+                self.reader = None;
+                if self.should_restart(status) {
+                    let delay = self.backoff_delay();
+                    self.restarts += 1;
+                    self.next_start = Some(delay);
+                    self.restarting = Some(SupervisorEvent::Restarting {
+                        restarts: self.restarts,
+                        delay,
+                    });
+                }
+                Some(Ok(SupervisorEvent::Exited(status)))
+            }
+            Ok(out) => Some(Ok(SupervisorEvent::Output(out))),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
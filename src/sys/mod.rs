@@ -0,0 +1,90 @@
+//! Platform-specific process spawning and pipe handling.
+
+#[cfg(target_os = "linux")]
+mod namespace;
+#[cfg(unix)]
+mod pty;
+#[cfg(unix)]
+mod unix;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(target_os = "linux")]
+pub(crate) use namespace::unshare;
+#[cfg(unix)]
+pub(crate) use unix::*;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub(crate) use uring::Ring;
+#[cfg(windows)]
+pub(crate) use windows::*;
+
+use std::{io, time::Duration};
+
+use mio::{Registry, Token};
+
+use crate::Stdin;
+
+/// How the child's standard streams are connected.
+pub(crate) struct SpawnOptions {
+    pub(crate) capture_stdout: bool,
+    pub(crate) capture_stderr: bool,
+    pub(crate) merge_stderr: bool,
+    pub(crate) pipe_stdin: bool,
+    pub(crate) pty: bool,
+    pub(crate) process_group: bool,
+    pub(crate) new_session: bool,
+    /// The additional file descriptors to capture.
+    pub(crate) capture_fds: Vec<i32>,
+    /// Installed after everything else the child sets up.
+    #[cfg(target_os = "linux")]
+    pub(crate) seccomp: Option<crate::SeccompFilter>,
+}
+
+/// A freshly spawned child along with the read ends of its output streams.
+pub(crate) struct Spawned {
+    pub(crate) child: Child,
+    pub(crate) stdin: Option<Stdin>,
+    pub(crate) stdout: Option<PipeReader>,
+    pub(crate) stderr: Option<PipeReader>,
+    /// The read ends of the additional file descriptors, by their number in
+    /// the child.
+    pub(crate) fds: Vec<(i32, PipeReader)>,
+    /// Whether the child leads its own process group.
+    pub(crate) process_group: bool,
+}
+
+/// The resources a child used, as reported when reaping it.
+pub(crate) struct Usage {
+    pub(crate) user_time: Duration,
+    pub(crate) system_time: Duration,
+    pub(crate) max_rss: u64,
+}
+
+/// Where the read ends of the child's streams and its exit watcher are
+/// registered to be woken up once they are ready.
+pub(crate) trait Register {
+    fn register_pipe(&self, reader: &mut PipeReader, token: Token) -> Result<(), io::Error>;
+    fn deregister_pipe(&self, reader: &mut PipeReader) -> Result<(), io::Error>;
+    fn register_exit(&self, watcher: &mut ExitWatcher, token: Token) -> Result<(), io::Error>;
+    fn deregister_exit(&self, watcher: &mut ExitWatcher) -> Result<(), io::Error>;
+}
+
+impl Register for Registry {
+    fn register_pipe(&self, reader: &mut PipeReader, token: Token) -> Result<(), io::Error> {
+        reader.register(self, token)
+    }
+
+    fn deregister_pipe(&self, reader: &mut PipeReader) -> Result<(), io::Error> {
+        reader.deregister(self)
+    }
+
+    fn register_exit(&self, watcher: &mut ExitWatcher, token: Token) -> Result<(), io::Error> {
+        watcher.register(self, token)
+    }
+
+    fn deregister_exit(&self, watcher: &mut ExitWatcher) -> Result<(), io::Error> {
+        watcher.deregister(self)
+    }
+}
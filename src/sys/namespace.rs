@@ -0,0 +1,284 @@
+//! Moves the child into new namespaces before it runs the program.
+
+use std::{
+    ffi::{CStr, CString},
+    io, mem,
+    os::unix::{ffi::OsStringExt, process::CommandExt},
+    path::Path,
+    process::Command,
+    ptr,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use libc::c_int;
+
+use crate::Namespace;
+
+/// The signals that the processes above the child in a new PID namespace
+/// pass on to it.
+const FORWARDED: [c_int; 7] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGWINCH,
+];
+
+/// Where [`forward`] sends the signals it receives, in each of the processes
+/// that install it.
+static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+
+/// Makes the child move itself into new `namespaces` before it runs the
+/// program.
+///
+/// This has to run before the child drops its privileges, and before it
+/// becomes a session leader for a pty, so that it does so below the
+/// processes that a new PID namespace adds. It also runs before the child
+/// changes its root directory to `root`, under which a fresh `/proc` is
+/// mounted.
+pub(crate) fn unshare(
+    cmd: &mut Command,
+    namespaces: &[Namespace],
+    root: Option<&Path>,
+) -> Result<(), io::Error> {
+    if namespaces.is_empty() {
+        return Ok(());
+    }
+
+    let proc = root.unwrap_or(Path::new("/")).join("proc");
+    let proc = CString::new(proc.into_os_string().into_vec())?;
+
+    let mut flags = 0;
+    for namespace in namespaces {
+        flags |= match namespace {
+            Namespace::Pid => libc::CLONE_NEWPID,
+            Namespace::Mount => libc::CLONE_NEWNS,
+            Namespace::Network => libc::CLONE_NEWNET,
+            Namespace::Ipc => libc::CLONE_NEWIPC,
+        };
+    }
+
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::unshare(flags) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if flags & libc::CLONE_NEWNS != 0 {
+                // The new namespace starts out sharing mount events with the
+                // old one, which would make its mounts visible outside of it.
+                let root = c"/".as_ptr();
+                let flags = libc::MS_REC | libc::MS_PRIVATE;
+                if libc::mount(ptr::null(), root, ptr::null(), flags, ptr::null()) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if flags & libc::CLONE_NEWNET != 0 {
+                loopback_up()?;
+            }
+
+            if flags & libc::CLONE_NEWPID != 0 {
+                let proc = (flags & libc::CLONE_NEWNS != 0).then_some(proc.as_c_str());
+                enter_pid_namespace(proc)?;
+            }
+
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Brings up the loopback interface of a new network namespace, which
+/// starts out down.
+unsafe fn loopback_up() -> Result<(), io::Error> {
+    let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if socket == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut req: libc::ifreq = mem::zeroed();
+    for (dst, &src) in req.ifr_name.iter_mut().zip(b"lo") {
+        *dst = src as _;
+    }
+
+    let mut res = libc::ioctl(socket, libc::SIOCGIFFLAGS, &mut req);
+    if res != -1 {
+        req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        res = libc::ioctl(socket, libc::SIOCSIFFLAGS, &req);
+    }
+    let err = io::Error::last_os_error();
+    libc::close(socket);
+    match res {
+        -1 => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Forks the init process of the new PID namespace, which in turn forks the
+/// child, and returns in the child.
+///
+/// The spawned process and the init process stay behind, each waiting for
+/// the process it forked and passing on signals to it, and never return.
+/// Failures up to the fork of the child are returned as usual, so std still
+/// reports them from the spawn, from whichever of the processes they happen
+/// in.
+///
+/// With a new mount namespace, the init process mounts a fresh `/proc` at
+/// `proc`.
+unsafe fn enter_pid_namespace(proc: Option<&CStr>) -> Result<(), io::Error> {
+    // The init process of a namespace cannot be killed by a signal from
+    // inside of it, so it cannot exit the way the child did. It sends the
+    // child's status up through this pipe instead.
+    let mut fds = [0; 2];
+    if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read, write] = fds;
+
+    // The spawned process leads the process group that signals are sent to,
+    // even with a pty, while the processes below it move into one of their
+    // own, so that they are signalled only once.
+    if libc::setpgid(0, 0) == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match libc::fork() {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => libc::close(read),
+        init => {
+            libc::close(write);
+            close_fds_except(read);
+            forward_signals(init);
+
+            let status = wait_for(init);
+            let mut child_status: c_int = 0;
+            let len = mem::size_of::<c_int>();
+            let status = match libc::read(read, ptr::from_mut(&mut child_status).cast(), len) {
+                n if n == len as isize => child_status,
+                // The init process was killed before the child exited.
+                _ => status,
+            };
+            exit_like(status);
+        }
+    };
+
+    // The init process is killed along with the spawned process, which kills
+    // everything in the namespace.
+    if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if libc::setpgid(0, 0) == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if let Some(target) = proc {
+        let flags = libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC;
+        let proc = c"proc".as_ptr();
+        if libc::mount(proc, target.as_ptr(), proc, flags, ptr::null()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let child = match libc::fork() {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {
+            libc::close(write);
+            return Ok(());
+        }
+        child => child,
+    };
+
+    close_fds_except(write);
+    // Signalling -1 reaches every process in the namespace except for the
+    // init process itself.
+    forward_signals(-1);
+
+    // Orphans in the namespace are reparented to the init process, which
+    // reaps them until the child exits.
+    let status = loop {
+        let mut status = 0;
+        match libc::waitpid(-1, &mut status, 0) {
+            pid if pid == child => break status,
+            -1 if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) => break 0,
+            _ => {}
+        }
+    };
+    libc::write(
+        write,
+        ptr::from_ref(&status).cast(),
+        mem::size_of::<c_int>(),
+    );
+    libc::_exit(0);
+}
+
+/// Closes every file descriptor except for the standard streams and `keep`,
+/// in particular the pipe that std waits on to see whether `exec` failed.
+unsafe fn close_fds_except(keep: c_int) {
+    let ranges = [(3, keep - 1), (keep + 1, c_int::MAX)];
+    for (first, last) in ranges {
+        if first > last {
+            continue;
+        }
+        if libc::syscall(libc::SYS_close_range, first as u32, last as u32, 0) == -1 {
+            // Before Linux 5.9, every possible descriptor is closed one by
+            // one.
+            let max = libc::sysconf(libc::_SC_OPEN_MAX).min(1 << 20) as c_int;
+            for fd in first..=last.min(max) {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+extern "C" fn forward(signal: c_int) {
+    unsafe { libc::kill(FORWARD_TO.load(Ordering::Relaxed), signal) };
+}
+
+/// Passes on the [`FORWARDED`] signals to `pid`.
+unsafe fn forward_signals(pid: libc::pid_t) {
+    FORWARD_TO.store(pid, Ordering::Relaxed);
+
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = forward as extern "C" fn(c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    for signal in FORWARDED {
+        libc::sigaction(signal, &action, ptr::null_mut());
+    }
+}
+
+/// Waits for `pid` to exit and returns its status.
+unsafe fn wait_for(pid: libc::pid_t) -> c_int {
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) == -1 {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            return 0;
+        }
+    }
+    status
+}
+
+/// Exits with the same exit code, or dies from the same signal, as the
+/// process that exited with `status`.
+unsafe fn exit_like(status: c_int) -> ! {
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+
+        // The child already dumped core if it was going to.
+        let no_core = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+        libc::signal(signal, libc::SIG_DFL);
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, ptr::null_mut());
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+
+    libc::_exit(libc::WEXITSTATUS(status));
+}
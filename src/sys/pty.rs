@@ -0,0 +1,62 @@
+use std::{
+    io,
+    os::unix::{
+        io::{FromRawFd, OwnedFd},
+        process::CommandExt,
+    },
+    process::Command,
+    ptr,
+};
+
+/// Opens a new pseudo-terminal, returning its master and slave ends.
+pub(crate) fn open() -> Result<(OwnedFd, OwnedFd), io::Error> {
+    let mut master = -1;
+    let mut slave = -1;
+    let size = libc::winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    let res =
+        unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), &size) };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    for fd in [&master, &slave] {
+        set_cloexec(fd)?;
+    }
+
+    Ok((master, slave))
+}
+
+/// Makes the child a session leader with the terminal on its stdin as its
+/// controlling terminal.
+pub(crate) fn set_controlling_terminal(cmd: &mut Command) {
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+}
+
+fn set_cloexec(fd: &OwnedFd) -> Result<(), io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,767 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{CString, OsString},
+    fs::File,
+    io::{self, Read},
+    iter,
+    mem::{self, MaybeUninit},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        io::{IntoRawFd, OwnedFd},
+        prelude::{AsRawFd, FromRawFd},
+        process::{CommandExt, ExitStatusExt},
+    },
+    path::PathBuf,
+    process::{self, Command, ExitStatus, Stdio},
+    ptr,
+    time::Duration,
+};
+
+use mio::{unix::pipe::Receiver, Interest, Registry, Token};
+
+#[cfg(target_os = "linux")]
+use mio::unix::SourceFd;
+
+use super::{pty, SpawnOptions, Spawned, Usage};
+use crate::{
+    builder::{Credentials, Scheduling},
+    Resource, SchedulingPolicy, Stdin,
+};
+
+/// A child spawned by std, or with [`posix_spawn`], which std cannot wrap in
+/// a [`process::Child`].
+pub(crate) enum Child {
+    Std(process::Child),
+    /// The process ID of a child spawned with [`posix_spawn`].
+    PosixSpawn(u32),
+}
+
+impl Child {
+    pub(crate) fn id(&self) -> u32 {
+        match self {
+            Self::Std(child) => child.id(),
+            Self::PosixSpawn(pid) => *pid,
+        }
+    }
+}
+
+/// The non-blocking read end of one of the child's output streams.
+pub(crate) struct PipeReader(Receiver);
+
+impl PipeReader {
+    /// Takes `fd`, the read end of a pipe or a similar stream that was
+    /// created elsewhere, switching it to non-blocking mode.
+    pub(crate) fn from_fd(fd: OwnedFd) -> Result<Self, io::Error> {
+        let read = unsafe { Receiver::from_raw_fd(fd.into_raw_fd()) };
+        read.set_nonblocking(true)?;
+        Ok(Self(read))
+    }
+
+    pub(crate) fn register(&mut self, registry: &Registry, token: Token) -> Result<(), io::Error> {
+        registry.register(&mut self.0, token, Interest::READABLE)
+    }
+
+    pub(crate) fn deregister(&mut self, registry: &Registry) -> Result<(), io::Error> {
+        registry.deregister(&mut self.0)
+    }
+}
+
+impl AsRawFd for PipeReader {
+    fn as_raw_fd(&self) -> i32 {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            // A pty master reports `EIO` once the slave side has been closed.
+            Err(err) if err.raw_os_error() == Some(libc::EIO) => Ok(0),
+            res => res,
+        }
+    }
+}
+
+/// Becomes readable once the child has exited, so that its exit wakes up the
+/// poll loop. Backed by a pidfd on Linux.
+#[cfg(target_os = "linux")]
+pub(crate) struct ExitWatcher(OwnedFd);
+
+#[cfg(target_os = "linux")]
+impl ExitWatcher {
+    /// Returns `None` if the kernel does not support pidfds.
+    pub(crate) fn new(child: &Child) -> Result<Option<Self>, io::Error> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, child.id(), libc::PIDFD_NONBLOCK) };
+        if fd == -1 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ENOSYS) => Ok(None),
+                _ => Err(err),
+            };
+        }
+
+        Ok(Some(Self(unsafe { OwnedFd::from_raw_fd(fd as _) })))
+    }
+
+    pub(crate) fn register(&mut self, registry: &Registry, token: Token) -> Result<(), io::Error> {
+        let fd = self.0.as_raw_fd();
+        registry.register(&mut SourceFd(&fd), token, Interest::READABLE)
+    }
+
+    pub(crate) fn deregister(&mut self, registry: &Registry) -> Result<(), io::Error> {
+        let fd = self.0.as_raw_fd();
+        registry.deregister(&mut SourceFd(&fd))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for ExitWatcher {
+    fn as_raw_fd(&self) -> i32 {
+        self.0.as_raw_fd()
+    }
+}
+
+/// Exit notifications are not available on this platform.
+#[cfg(not(target_os = "linux"))]
+pub(crate) enum ExitWatcher {}
+
+#[cfg(not(target_os = "linux"))]
+impl ExitWatcher {
+    pub(crate) fn new(_child: &Child) -> Result<Option<Self>, io::Error> {
+        Ok(None)
+    }
+
+    pub(crate) fn register(
+        &mut self,
+        _registry: &Registry,
+        _token: Token,
+    ) -> Result<(), io::Error> {
+        match *self {}
+    }
+    pub(crate) fn deregister(&mut self, _registry: &Registry) -> Result<(), io::Error> {
+        match *self {}
+    }
+}
+
+/// Creates a pipe whose read end is registered with mio and whose write end
+/// is handed to the child.
+///
+/// The write end is switched back to blocking mode, since the child expects
+/// writes to its standard streams to block rather than fail when the pipe is
+/// full.
+fn pipe() -> Result<(Receiver, OwnedFd), io::Error> {
+    let (write, read) = mio::unix::pipe::new()?;
+    write.set_nonblocking(false)?;
+
+    let write = unsafe { OwnedFd::from_raw_fd(write.into_raw_fd()) };
+    Ok((read, write))
+}
+
+/// Spawns the child with its streams connected as configured.
+///
+/// The child is spawned by std, which only uses `posix_spawn` if nothing has
+/// to run in the child between `fork` and `exec`. A pty, a new session and
+/// captured file descriptors all need to, as do most of the options applied
+/// before, so most children are forked unless [`posix_spawn`] is used
+/// instead.
+pub(crate) fn spawn(cmd: &mut Command, opts: &SpawnOptions) -> Result<Spawned, io::Error> {
+    // Stderr can only be merged into stdout if the latter is captured.
+    let merge_stderr = opts.merge_stderr && (opts.capture_stdout || opts.pty);
+
+    let mut stdin = None;
+    let stdout_read = if opts.pty {
+        let (master, slave) = pty::open()?;
+        if merge_stderr {
+            cmd.stderr(slave.try_clone()?);
+        }
+        cmd.stdin(slave.try_clone()?).stdout(slave);
+        pty::set_controlling_terminal(cmd);
+        stdin = Some(Stdin::new(File::from(master.try_clone()?)));
+
+        let read = unsafe { Receiver::from_raw_fd(master.into_raw_fd()) };
+        read.set_nonblocking(true)?;
+        Some(read)
+    } else if opts.capture_stdout {
+        let (read, write) = pipe()?;
+        if merge_stderr {
+            cmd.stderr(write.try_clone()?);
+        }
+        cmd.stdout(write);
+        Some(read)
+    } else {
+        None
+    };
+
+    let mut stderr_read = None;
+    if opts.capture_stderr && !merge_stderr {
+        let (read, write) = pipe()?;
+        cmd.stderr(write);
+        stderr_read = Some(PipeReader(read));
+    }
+
+    if opts.pipe_stdin && !opts.pty {
+        cmd.stdin(Stdio::piped());
+    }
+
+    // A pty child becomes a session leader, which also makes it the leader of
+    // a new process group.
+    let process_group = opts.process_group || opts.pty || opts.new_session;
+    if opts.new_session && !opts.pty {
+        set_new_session(cmd);
+    } else if process_group && !opts.pty {
+        cmd.process_group(0);
+    }
+
+    let mut fds = Vec::new();
+    let mut targets = Vec::new();
+    for &fd in &opts.capture_fds {
+        let (read, write) = pipe()?;
+        fds.push((fd, PipeReader(read)));
+        targets.push((write, fd));
+    }
+    map_fds(cmd, targets);
+
+    #[cfg(target_os = "linux")]
+    crate::seccomp::install(cmd, opts.seccomp.clone());
+
+    let mut child = cmd.spawn()?;
+    if let Some(child_stdin) = child.stdin.take() {
+        stdin = Some(Stdin::new(File::from(OwnedFd::from(child_stdin))));
+    }
+
+    Ok(Spawned {
+        child: Child::Std(child),
+        stdin,
+        stdout: stdout_read.map(PipeReader),
+        stderr: stderr_read,
+        fds,
+        process_group,
+    })
+}
+
+/// Makes the child move the write ends of the pipes for additional file
+/// descriptors to the numbers they are captured under.
+fn map_fds(cmd: &mut Command, targets: Vec<(OwnedFd, i32)>) {
+    if targets.is_empty() {
+        return;
+    }
+
+    // Every pipe is moved out of the way of the targets first, so that none
+    // of them is overwritten by another before it is moved itself. The slots
+    // are allocated here, since the child must not allocate.
+    let floor = targets.iter().map(|&(_, target)| target).max().unwrap_or(0) + 1;
+    let mut moved = vec![-1; targets.len()];
+    unsafe {
+        cmd.pre_exec(move || {
+            for (slot, (write, _)) in moved.iter_mut().zip(&targets) {
+                *slot = libc::fcntl(write.as_raw_fd(), libc::F_DUPFD_CLOEXEC, floor);
+                if *slot == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            // The duplicates do not inherit close-on-exec.
+            for (&slot, &(_, target)) in moved.iter().zip(&targets) {
+                if libc::dup2(slot, target) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+}
+
+/// Takes the streams of `child`, which was spawned elsewhere, switching the
+/// read ends of its output streams to non-blocking mode.
+///
+/// The child is taken to lead a process group if its process group ID is its
+/// own process ID.
+pub(crate) fn adopt(mut child: process::Child) -> Result<Spawned, io::Error> {
+    let stdout = child
+        .stdout
+        .take()
+        .map(|stdout| PipeReader::from_fd(stdout.into()))
+        .transpose()?;
+    let stderr = child
+        .stderr
+        .take()
+        .map(|stderr| PipeReader::from_fd(stderr.into()))
+        .transpose()?;
+    let stdin = child
+        .stdin
+        .take()
+        .map(|stdin| Stdin::new(File::from(OwnedFd::from(stdin))));
+
+    let pid = child.id() as libc::pid_t;
+    let process_group = unsafe { libc::getpgid(pid) } == pid;
+
+    Ok(Spawned {
+        child: Child::Std(child),
+        stdin,
+        stdout,
+        stderr,
+        fds: Vec::new(),
+        process_group,
+    })
+}
+
+/// Spawns the child with `posix_spawn`, with its streams and captured file
+/// descriptors handed over as `dup2` file actions, for
+/// [`posix_spawn`](crate::ProcessReaderBuilder::posix_spawn).
+///
+/// Only the program, arguments and working directory are taken from `cmd`,
+/// and the child gets exactly the variables in `env`. Streams that are not
+/// captured are inherited.
+pub(crate) fn posix_spawn(
+    cmd: &Command,
+    env: BTreeMap<OsString, OsString>,
+    opts: &SpawnOptions,
+) -> Result<Spawned, io::Error> {
+    let merge_stderr = opts.merge_stderr && opts.capture_stdout;
+
+    // The ends of the pipes that are handed to the child, along with the
+    // numbers they are moved to.
+    let mut targets = Vec::new();
+    let mut stdin = None;
+    if opts.pipe_stdin {
+        let (write, read) = mio::unix::pipe::new()?;
+        write.set_nonblocking(false)?;
+        read.set_nonblocking(false)?;
+        stdin = Some(Stdin::new(unsafe {
+            File::from_raw_fd(write.into_raw_fd())
+        }));
+        targets.push((unsafe { OwnedFd::from_raw_fd(read.into_raw_fd()) }, 0));
+    }
+
+    let mut stdout = None;
+    if opts.capture_stdout {
+        let (read, write) = pipe()?;
+        if merge_stderr {
+            targets.push((write.try_clone()?, 2));
+        }
+        targets.push((write, 1));
+        stdout = Some(PipeReader(read));
+    }
+
+    let mut stderr = None;
+    if opts.capture_stderr && !merge_stderr {
+        let (read, write) = pipe()?;
+        targets.push((write, 2));
+        stderr = Some(PipeReader(read));
+    }
+
+    let mut fds = Vec::new();
+    for &fd in &opts.capture_fds {
+        let (read, write) = pipe()?;
+        fds.push((fd, PipeReader(read)));
+        targets.push((write, fd));
+    }
+
+    // Every end is moved out of the way of the targets first, so that none
+    // of them is overwritten by another before it is moved itself. The
+    // duplicates are closed on exec, unlike the targets they are moved to.
+    let floor = targets.iter().map(|&(_, target)| target).max().unwrap_or(0) + 1;
+    let moved = targets
+        .into_iter()
+        .map(|(fd, target)| {
+            match unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, floor) } {
+                -1 => Err(io::Error::last_os_error()),
+                moved => Ok((unsafe { OwnedFd::from_raw_fd(moved) }, target)),
+            }
+        })
+        .collect::<Result<Vec<_>, io::Error>>()?;
+
+    let mut actions = MaybeUninit::uninit();
+    check_spawn(unsafe { libc::posix_spawn_file_actions_init(actions.as_mut_ptr()) })?;
+    let actions = FileActions(&mut actions);
+    for (fd, target) in &moved {
+        check_spawn(unsafe {
+            libc::posix_spawn_file_actions_adddup2(actions.0.as_mut_ptr(), fd.as_raw_fd(), *target)
+        })?;
+    }
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    if let Some(dir) = cmd.get_current_dir() {
+        let dir = CString::new(dir.as_os_str().as_bytes())?;
+        check_spawn(unsafe {
+            libc::posix_spawn_file_actions_addchdir_np(actions.0.as_mut_ptr(), dir.as_ptr())
+        })?;
+    }
+
+    let mut attr = MaybeUninit::uninit();
+    check_spawn(unsafe { libc::posix_spawnattr_init(attr.as_mut_ptr()) })?;
+    let attr = SpawnAttr(&mut attr);
+    // Like std, start the child with no signals blocked and `SIGPIPE` at its
+    // default disposition, which the Rust runtime ignores.
+    let mut flags = (libc::POSIX_SPAWN_SETSIGMASK | libc::POSIX_SPAWN_SETSIGDEF) as libc::c_int;
+    unsafe {
+        let mut signals = MaybeUninit::uninit();
+        libc::sigemptyset(signals.as_mut_ptr());
+        check_spawn(libc::posix_spawnattr_setsigmask(
+            attr.0.as_mut_ptr(),
+            signals.as_ptr(),
+        ))?;
+        libc::sigaddset(signals.as_mut_ptr(), libc::SIGPIPE);
+        check_spawn(libc::posix_spawnattr_setsigdefault(
+            attr.0.as_mut_ptr(),
+            signals.as_ptr(),
+        ))?;
+    }
+
+    let process_group = opts.process_group || opts.new_session;
+    #[cfg(target_os = "linux")]
+    if opts.new_session {
+        flags |= libc::POSIX_SPAWN_SETSID as libc::c_int;
+    }
+    if process_group && !opts.new_session {
+        flags |= libc::POSIX_SPAWN_SETPGROUP as libc::c_int;
+        check_spawn(unsafe { libc::posix_spawnattr_setpgroup(attr.0.as_mut_ptr(), 0) })?;
+    }
+    check_spawn(unsafe { libc::posix_spawnattr_setflags(attr.0.as_mut_ptr(), flags as _) })?;
+
+    let args = iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| CString::new(arg.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let env = env
+        .into_iter()
+        .map(|(key, value)| {
+            let mut var = key.into_vec();
+            var.push(b'=');
+            var.extend(value.into_vec());
+            CString::new(var)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let argv = nul_terminated(&args);
+    let envp = nul_terminated(&env);
+
+    let mut pid = 0;
+    check_spawn(unsafe {
+        libc::posix_spawnp(
+            &mut pid,
+            args[0].as_ptr(),
+            actions.0.as_ptr(),
+            attr.0.as_ptr(),
+            argv.as_ptr(),
+            envp.as_ptr(),
+        )
+    })?;
+    // Close the parent's copies of the child's ends of the pipes, so that
+    // EOF is seen once the child closes them.
+    drop(moved);
+
+    Ok(Spawned {
+        child: Child::PosixSpawn(pid as u32),
+        stdin,
+        stdout,
+        stderr,
+        fds,
+        process_group,
+    })
+}
+
+/// Destroys the `posix_spawn` file actions once the child is spawned.
+struct FileActions<'a>(&'a mut MaybeUninit<libc::posix_spawn_file_actions_t>);
+
+impl Drop for FileActions<'_> {
+    fn drop(&mut self) {
+        unsafe { libc::posix_spawn_file_actions_destroy(self.0.as_mut_ptr()) };
+    }
+}
+
+/// Destroys the `posix_spawn` attributes once the child is spawned.
+struct SpawnAttr<'a>(&'a mut MaybeUninit<libc::posix_spawnattr_t>);
+
+impl Drop for SpawnAttr<'_> {
+    fn drop(&mut self) {
+        unsafe { libc::posix_spawnattr_destroy(self.0.as_mut_ptr()) };
+    }
+}
+
+/// Turns the error number returned by a `posix_spawn` function into an
+/// error.
+fn check_spawn(ret: libc::c_int) -> Result<(), io::Error> {
+    match ret {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+/// Returns pointers to `strings`, followed by a null pointer.
+fn nul_terminated(strings: &[CString]) -> Vec<*mut libc::c_char> {
+    strings
+        .iter()
+        .map(|string| string.as_ptr() as *mut _)
+        .chain(iter::once(ptr::null_mut()))
+        .collect()
+}
+
+/// Makes the child the leader of a new session, without a controlling
+/// terminal.
+pub(crate) fn set_new_session(cmd: &mut Command) {
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+}
+
+/// Makes the child run as the configured user and groups.
+pub(crate) fn set_credentials(cmd: &mut Command, credentials: Credentials) {
+    let Credentials { uid, gid, groups } = credentials;
+    if uid.is_none() && gid.is_none() && groups.is_none() {
+        return;
+    }
+
+    // std sets the IDs before running `pre_exec`, which would keep the
+    // limits and scheduling from being set with the privileges still held,
+    // so they are set here instead, after everything else.
+    unsafe {
+        cmd.pre_exec(move || {
+            // Like std, drop the supplementary groups of root along with its
+            // user ID, unless they are set.
+            let groups = match &groups {
+                Some(groups) => Some(groups.as_slice()),
+                None if uid.is_some() && libc::getuid() == 0 => Some(&[][..]),
+                None => None,
+            };
+            if let Some(groups) = groups {
+                if libc::setgroups(groups.len() as _, groups.as_ptr()) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(gid) = gid {
+                if libc::setgid(gid) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(uid) = uid {
+                if libc::setuid(uid) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+}
+
+/// Makes the child change its root directory to `root`, if any, before it
+/// runs the program.
+///
+/// The working directory that `cmd` was given is taken to be inside of
+/// `root`, since std changes to it before the root is changed. Without one,
+/// the child changes to the new root, so that it is not left outside of it.
+pub(crate) fn set_root(cmd: &mut Command, root: Option<PathBuf>) -> Result<(), io::Error> {
+    let Some(root) = root else {
+        return Ok(());
+    };
+
+    let dir = cmd.get_current_dir().map(|dir| {
+        let dir = dir.strip_prefix("/").unwrap_or(dir);
+        root.join(dir)
+    });
+    let change_dir = dir.is_none();
+    if let Some(dir) = dir {
+        cmd.current_dir(dir);
+    }
+
+    let root = CString::new(root.into_os_string().into_vec())?;
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::chroot(root.as_ptr()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if change_dir && libc::chdir(c"/".as_ptr()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Makes the child set its umask, if any, before it runs the program.
+pub(crate) fn set_umask(cmd: &mut Command, mask: Option<u32>) {
+    let Some(mask) = mask else {
+        return;
+    };
+
+    unsafe {
+        cmd.pre_exec(move || {
+            libc::umask(mask as libc::mode_t);
+            Ok(())
+        });
+    }
+}
+
+/// Makes the child set `limits` on itself before it runs the program.
+pub(crate) fn set_rlimits(cmd: &mut Command, limits: &[(Resource, u64)]) {
+    if limits.is_empty() {
+        return;
+    }
+
+    let limits: Vec<_> = limits
+        .iter()
+        .map(|&(resource, limit)| {
+            let hard = match resource {
+                Resource::Cpu => limit.saturating_add(1),
+                _ => limit,
+            };
+            let limit = libc::rlimit {
+                rlim_cur: limit as libc::rlim_t,
+                rlim_max: hard as libc::rlim_t,
+            };
+            (resource.as_raw(), limit)
+        })
+        .collect();
+
+    unsafe {
+        cmd.pre_exec(move || {
+            for (resource, limit) in &limits {
+                if libc::setrlimit(*resource as _, limit) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+}
+
+/// Makes the child set its niceness, scheduling policy and CPU affinity
+/// before it runs the program.
+pub(crate) fn set_scheduling(cmd: &mut Command, scheduling: Scheduling) {
+    let Scheduling { nice, policy, cpus } = scheduling;
+    if nice.is_none() && policy.is_none() && cpus.is_none() {
+        return;
+    }
+
+    #[cfg(target_os = "linux")]
+    let policy = policy.map(|policy| match policy {
+        SchedulingPolicy::Other => libc::SCHED_OTHER,
+        SchedulingPolicy::Batch => libc::SCHED_BATCH,
+        SchedulingPolicy::Idle => libc::SCHED_IDLE,
+    });
+    #[cfg(target_os = "linux")]
+    let cpus = cpus.map(|cpus| {
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        for cpu in cpus {
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        set
+    });
+    #[cfg(not(target_os = "linux"))]
+    let _ = (policy, cpus);
+
+    unsafe {
+        cmd.pre_exec(move || {
+            #[cfg(target_os = "linux")]
+            if let Some(policy) = policy {
+                let param = libc::sched_param { sched_priority: 0 };
+                if libc::sched_setscheduler(0, policy, &param) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            #[cfg(target_os = "linux")]
+            if let Some(set) = &cpus {
+                if libc::sched_setaffinity(0, mem::size_of_val(set), set) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+}
+
+/// Makes the child set its `oom_score_adj`, if any, before it runs the
+/// program.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_oom_score_adj(cmd: &mut Command, adj: Option<i32>) {
+    let Some(adj) = adj else {
+        return;
+    };
+
+    let adj = adj.to_string();
+    unsafe {
+        cmd.pre_exec(move || {
+            let path = c"/proc/self/oom_score_adj".as_ptr();
+            let fd = libc::open(path, libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let written = libc::write(fd, adj.as_ptr().cast(), adj.len());
+            let err = io::Error::last_os_error();
+            libc::close(fd);
+            match written {
+                -1 => Err(err),
+                _ => Ok(()),
+            }
+        });
+    }
+}
+
+/// Sends `signal` to `pid`, or to the process group `-pid` if it is negative.
+pub(crate) fn send_signal(pid: libc::pid_t, signal: libc::c_int) -> Result<(), io::Error> {
+    if unsafe { libc::kill(pid, signal) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Reaps `child` with `wait4`, returning its exit status along with the
+/// resources it used, or `None` if it is still running and `block` is false.
+///
+/// Since this bypasses [`process::Child::wait`], `child` must not be waited
+/// for or killed through std afterwards.
+pub(crate) fn wait_with_usage(
+    child: &mut Child,
+    block: bool,
+) -> Result<Option<(ExitStatus, Usage)>, io::Error> {
+    let pid = child.id() as libc::pid_t;
+    let options = if block { 0 } else { libc::WNOHANG };
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+    loop {
+        match unsafe { libc::wait4(pid, &mut status, options, &mut rusage) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            0 => return Ok(None),
+            _ => break,
+        }
+    }
+
+    // The peak resident set size is in KiB, except on Apple platforms.
+    let max_rss = rusage.ru_maxrss as u64;
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    let max_rss = max_rss * 1024;
+
+    let usage = Usage {
+        user_time: duration(rusage.ru_utime),
+        system_time: duration(rusage.ru_stime),
+        max_rss,
+    };
+    Ok(Some((ExitStatus::from_raw(status), usage)))
+}
+
+fn duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
@@ -0,0 +1,327 @@
+//! A minimal io_uring instance used in place of epoll as the poll backend of
+//! a `ProcessSet`, to wait for the children's streams to become readable and
+//! for the children to exit. Reading the streams is left to `read`.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::c_void,
+    io,
+    os::unix::io::{AsRawFd, OwnedFd, RawFd},
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use mio::Token;
+use rustix::{
+    io_uring::{
+        io_uring_cqe, io_uring_enter, io_uring_params, io_uring_ptr, io_uring_setup, io_uring_sqe,
+        io_uring_user_data, IoringEnterFlags, IoringOp, Timespec, IORING_OFF_CQ_RING,
+        IORING_OFF_SQES, IORING_OFF_SQ_RING,
+    },
+    mm::{mmap, munmap, MapFlags, ProtFlags},
+};
+
+use super::{ExitWatcher, PipeReader, Register};
+
+/// How many submissions the ring holds. More are submitted in batches.
+const ENTRIES: u32 = 256;
+
+/// The user data of the timeout that bounds a wait.
+const TIMEOUT: u64 = u64::MAX;
+/// The user data of requests to cancel a poll, whose completions are
+/// ignored.
+const CANCEL: u64 = u64::MAX - 1;
+
+/// A shared memory region mapped from the ring.
+struct Mmap {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: &OwnedFd, len: usize, offset: u64) -> Result<Self, io::Error> {
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                ProtFlags::READ | ProtFlags::WRITE,
+                MapFlags::SHARED | MapFlags::POPULATE,
+                fd,
+                offset,
+            )
+        }?;
+        Ok(Self { ptr, len })
+    }
+
+    /// Returns a pointer to the value at `offset` bytes into the region.
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.cast::<u8>().add(offset as usize).cast() }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        let _ = unsafe { munmap(self.ptr, self.len) };
+    }
+}
+
+/// A source of readiness registered with the ring.
+#[derive(Clone, Copy, Debug)]
+struct Registration {
+    token: Token,
+    /// The user data of the poll that is currently submitted, if any.
+    armed: Option<u64>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The registered sources by file descriptor.
+    sources: HashMap<RawFd, Registration>,
+    /// The file descriptors of submitted polls by their user data.
+    armed: HashMap<u64, RawFd>,
+    /// Polls to cancel with the next submission, by user data.
+    cancel: Vec<u64>,
+    /// The user data of the next poll.
+    next: u64,
+}
+
+/// Waits for readiness with one-shot polls that are submitted together in a
+/// single system call, and submitted again after each completion for as long
+/// as the source stays registered.
+pub(crate) struct Ring {
+    fd: OwnedFd,
+    sq_ring: Mmap,
+    /// The completion queue, unless it shares a mapping with the submission
+    /// queue.
+    cq_ring: Option<Mmap>,
+    sqes: Mmap,
+    params: io_uring_params,
+    state: RefCell<State>,
+}
+
+impl Ring {
+    pub(crate) fn new() -> Result<Self, io::Error> {
+        let mut params = io_uring_params::default();
+        let fd = unsafe { io_uring_setup(ENTRIES, &mut params) }?;
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize
+            + params.cq_entries as usize * std::mem::size_of::<io_uring_cqe>();
+        let single_mmap = params
+            .features
+            .contains(rustix::io_uring::IoringFeatureFlags::SINGLE_MMAP);
+
+        let sq_ring = Mmap::new(
+            &fd,
+            if single_mmap {
+                sq_len.max(cq_len)
+            } else {
+                sq_len
+            },
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq_ring = match single_mmap {
+            true => None,
+            false => Some(Mmap::new(&fd, cq_len, IORING_OFF_CQ_RING)?),
+        };
+        let sqes = Mmap::new(
+            &fd,
+            params.sq_entries as usize * std::mem::size_of::<io_uring_sqe>(),
+            IORING_OFF_SQES,
+        )?;
+
+        Ok(Self {
+            fd,
+            sq_ring,
+            cq_ring,
+            sqes,
+            params,
+            state: RefCell::new(State::default()),
+        })
+    }
+
+    fn cq_ring(&self) -> &Mmap {
+        self.cq_ring.as_ref().unwrap_or(&self.sq_ring)
+    }
+
+    fn register_fd(&self, fd: RawFd, token: Token) {
+        // A registration that is still around belongs to a file that was
+        // closed without deregistering it, whose descriptor was reused.
+        self.deregister_fd(fd);
+        self.state
+            .borrow_mut()
+            .sources
+            .insert(fd, Registration { token, armed: None });
+    }
+
+    fn deregister_fd(&self, fd: RawFd) {
+        let mut state = self.state.borrow_mut();
+        if let Some(Registration {
+            armed: Some(data), ..
+        }) = state.sources.remove(&fd)
+        {
+            state.armed.remove(&data);
+            state.cancel.push(data);
+        }
+    }
+
+    /// Submits polls for every registered source that has none, waits for
+    /// at least one of them to complete or for `timeout` to elapse, and
+    /// calls `ready` with the token of every source that became ready.
+    pub(crate) fn wait(
+        &self,
+        timeout: Option<Duration>,
+        mut ready: impl FnMut(Token),
+    ) -> Result<(), io::Error> {
+        let mut pending = 0;
+        {
+            let mut guard = self.state.borrow_mut();
+            let state = &mut *guard;
+
+            for data in state.cancel.drain(..) {
+                let mut sqe = io_uring_sqe {
+                    opcode: IoringOp::PollRemove,
+                    user_data: io_uring_user_data::from_u64(CANCEL),
+                    ..Default::default()
+                };
+                sqe.addr_or_splice_off_in.user_data = io_uring_user_data::from_u64(data);
+                pending = self.push(sqe, pending)?;
+            }
+
+            for (&fd, source) in &mut state.sources {
+                if source.armed.is_some() {
+                    continue;
+                }
+
+                let data = state.next;
+                state.next += 1;
+                source.armed = Some(data);
+                state.armed.insert(data, fd);
+
+                let mut sqe = io_uring_sqe {
+                    opcode: IoringOp::PollAdd,
+                    fd,
+                    user_data: io_uring_user_data::from_u64(data),
+                    ..Default::default()
+                };
+                sqe.op_flags.poll_events = libc::POLLIN as u16;
+                pending = self.push(sqe, pending)?;
+            }
+        }
+
+        // The timespec is copied when the timeout is submitted, and the
+        // timeout completes early once anything else completes.
+        let timespec = timeout.map(|timeout| Timespec {
+            tv_sec: timeout.as_secs().try_into().unwrap_or(i64::MAX),
+            tv_nsec: timeout.subsec_nanos().into(),
+        });
+        if let Some(timespec) = &timespec {
+            let mut sqe = io_uring_sqe {
+                opcode: IoringOp::Timeout,
+                user_data: io_uring_user_data::from_u64(TIMEOUT),
+                ..Default::default()
+            };
+            sqe.addr_or_splice_off_in.addr =
+                io_uring_ptr::new(ptr::from_ref(timespec).cast_mut().cast());
+            sqe.len.len = 1;
+            sqe.off_or_addr2.off = 1;
+            pending = self.push(sqe, pending)?;
+        }
+
+        unsafe { io_uring_enter(&self.fd, pending, 1, IoringEnterFlags::GETEVENTS) }?;
+        self.complete(&mut ready);
+        Ok(())
+    }
+
+    /// Adds `sqe` to the submission queue, submitting what is queued first
+    /// if it is full, and returns how many entries are queued.
+    fn push(&self, sqe: io_uring_sqe, mut pending: u32) -> Result<u32, io::Error> {
+        let sq = &self.params.sq_off;
+        let head = unsafe { &*self.sq_ring.at::<AtomicU32>(sq.head) };
+        let tail = unsafe { &*self.sq_ring.at::<AtomicU32>(sq.tail) };
+        let mask = unsafe { *self.sq_ring.at::<u32>(sq.ring_mask) };
+
+        let mut current = tail.load(Ordering::Relaxed);
+        if current.wrapping_sub(head.load(Ordering::Acquire)) == self.params.sq_entries {
+            unsafe { io_uring_enter(&self.fd, pending, 0, IoringEnterFlags::empty()) }?;
+            pending = 0;
+            current = tail.load(Ordering::Relaxed);
+        }
+
+        let index = current & mask;
+        unsafe {
+            self.sqes
+                .at::<io_uring_sqe>(0)
+                .add(index as usize)
+                .write(sqe);
+            self.sq_ring
+                .at::<u32>(sq.array)
+                .add(index as usize)
+                .write(index);
+        }
+        tail.store(current.wrapping_add(1), Ordering::Release);
+        Ok(pending + 1)
+    }
+
+    /// Takes every completion off the completion queue.
+    fn complete(&self, ready: &mut impl FnMut(Token)) {
+        let cq = &self.params.cq_off;
+        let ring = self.cq_ring();
+        let head = unsafe { &*ring.at::<AtomicU32>(cq.head) };
+        let tail = unsafe { &*ring.at::<AtomicU32>(cq.tail) };
+        let mask = unsafe { *ring.at::<u32>(cq.ring_mask) };
+        let cqes = ring.at::<io_uring_cqe>(cq.cqes);
+
+        let mut state = self.state.borrow_mut();
+        let mut current = head.load(Ordering::Relaxed);
+        let end = tail.load(Ordering::Acquire);
+        while current != end {
+            let cqe = unsafe { &*cqes.add((current & mask) as usize) };
+            let (data, res) = (cqe.user_data.u64_(), cqe.res);
+            current = current.wrapping_add(1);
+
+            // Completions of polls that were cancelled or submitted again
+            // for a source that was registered anew are ignored.
+            let Some(fd) = state.armed.remove(&data) else {
+                continue;
+            };
+            let Some(source) = state.sources.get_mut(&fd) else {
+                continue;
+            };
+            let token = source.token;
+            source.armed = None;
+
+            // A poll fails if its file was closed without deregistering it,
+            // in which case it is not submitted again.
+            if res < 0 {
+                state.sources.remove(&fd);
+            }
+            ready(token);
+        }
+        head.store(current, Ordering::Release);
+    }
+}
+
+impl Register for Ring {
+    fn register_pipe(&self, reader: &mut PipeReader, token: Token) -> Result<(), io::Error> {
+        self.register_fd(reader.as_raw_fd(), token);
+        Ok(())
+    }
+
+    fn deregister_pipe(&self, reader: &mut PipeReader) -> Result<(), io::Error> {
+        self.deregister_fd(reader.as_raw_fd());
+        Ok(())
+    }
+
+    fn register_exit(&self, watcher: &mut ExitWatcher, token: Token) -> Result<(), io::Error> {
+        self.register_fd(watcher.as_raw_fd(), token);
+        Ok(())
+    }
+
+    fn deregister_exit(&self, watcher: &mut ExitWatcher) -> Result<(), io::Error> {
+        self.deregister_fd(watcher.as_raw_fd());
+        Ok(())
+    }
+}
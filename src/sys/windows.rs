@@ -0,0 +1,251 @@
+use std::{
+    ffi::OsStr,
+    fs::{File, OpenOptions},
+    io::{self, Read},
+    iter, mem,
+    os::windows::{
+        ffi::OsStrExt,
+        io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    },
+    path::PathBuf,
+    process::{self, Command, ExitStatus, Stdio},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use mio::{windows::NamedPipe, Interest, Registry, Token};
+use windows_sys::Win32::{
+    Foundation::{FILETIME, INVALID_HANDLE_VALUE},
+    Storage::FileSystem::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_INBOUND,
+    },
+    System::{
+        Pipes::{
+            CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE,
+            PIPE_WAIT,
+        },
+        ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+        Threading::{GetProcessTimes, TerminateProcess},
+    },
+};
+
+pub(crate) use std::process::Child;
+
+use super::{SpawnOptions, Spawned, Usage};
+use crate::{
+    builder::{Credentials, Scheduling},
+    Resource, Stdin,
+};
+
+const PIPE_BUFFER_SIZE: u32 = 64 * 1024;
+
+/// The overlapped read end of one of the child's output streams.
+pub(crate) struct PipeReader(NamedPipe);
+
+impl PipeReader {
+    pub(crate) fn register(&mut self, registry: &Registry, token: Token) -> Result<(), io::Error> {
+        registry.register(&mut self.0, token, Interest::READABLE)?;
+
+        // The child already holds the client end, so this completes right
+        // away and starts the first read.
+        match self.0.connect() {
+            Err(err) if err.kind() != io::ErrorKind::WouldBlock => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn deregister(&mut self, registry: &Registry) -> Result<(), io::Error> {
+        registry.deregister(&mut self.0)
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+/// Exit notifications are not available on this platform.
+pub(crate) enum ExitWatcher {}
+
+impl ExitWatcher {
+    pub(crate) fn new(_child: &Child) -> Result<Option<Self>, io::Error> {
+        Ok(None)
+    }
+
+    pub(crate) fn register(
+        &mut self,
+        _registry: &Registry,
+        _token: Token,
+    ) -> Result<(), io::Error> {
+        match *self {}
+    }
+    pub(crate) fn deregister(&mut self, _registry: &Registry) -> Result<(), io::Error> {
+        match *self {}
+    }
+}
+
+/// Creates a named pipe that mio can read from without blocking, returning
+/// it along with a synchronous client end for the child to write to.
+fn pipe() -> Result<(PipeReader, File), io::Error> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let name = format!(
+        r"\\.\pipe\process-reader-{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let wide: Vec<u16> = OsStr::new(&name)
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect();
+
+    let server = unsafe {
+        CreateNamedPipeW(
+            wide.as_ptr(),
+            PIPE_ACCESS_INBOUND | FILE_FLAG_OVERLAPPED | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            PIPE_BUFFER_SIZE,
+            PIPE_BUFFER_SIZE,
+            0,
+            ptr::null(),
+        )
+    };
+    if server == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+
+    let server = unsafe { NamedPipe::from_raw_handle(server as _) };
+    let client = OpenOptions::new().write(true).open(&name)?;
+    Ok((PipeReader(server), client))
+}
+
+/// Users and groups can only be changed on Unix.
+pub(crate) fn set_credentials(_cmd: &mut Command, _credentials: Credentials) {}
+
+/// The root directory can only be changed on Unix.
+pub(crate) fn set_root(_cmd: &mut Command, _root: Option<PathBuf>) -> Result<(), io::Error> {
+    Ok(())
+}
+
+/// The umask only exists on Unix.
+pub(crate) fn set_umask(_cmd: &mut Command, _mask: Option<u32>) {}
+
+/// Resource limits can only be set on Unix.
+pub(crate) fn set_rlimits(_cmd: &mut Command, _limits: &[(Resource, u64)]) {}
+
+/// Scheduling can only be configured on Unix.
+pub(crate) fn set_scheduling(_cmd: &mut Command, _scheduling: Scheduling) {}
+
+/// The output of a child spawned elsewhere is read from anonymous pipes,
+/// which cannot be registered with mio on Windows.
+pub(crate) fn adopt(_child: Child) -> Result<Spawned, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "adopting a child is not supported on windows",
+    ))
+}
+
+pub(crate) fn spawn(cmd: &mut Command, opts: &SpawnOptions) -> Result<Spawned, io::Error> {
+    if opts.pty || opts.process_group {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "pty and process_group are not supported on windows",
+        ));
+    }
+
+    // Stderr can only be merged into stdout if the latter is captured.
+    let merge_stderr = opts.merge_stderr && opts.capture_stdout;
+
+    let mut stdout_read = None;
+    if opts.capture_stdout {
+        let (read, write) = pipe()?;
+        if merge_stderr {
+            cmd.stderr(write.try_clone()?);
+        }
+        cmd.stdout(write);
+        stdout_read = Some(read);
+    }
+
+    let mut stderr_read = None;
+    if opts.capture_stderr && !merge_stderr {
+        let (read, write) = pipe()?;
+        cmd.stderr(write);
+        stderr_read = Some(read);
+    }
+
+    if opts.pipe_stdin {
+        cmd.stdin(Stdio::piped());
+    }
+
+    let mut child = cmd.spawn()?;
+    let stdin = child
+        .stdin
+        .take()
+        .map(|stdin| Stdin::new(File::from(OwnedHandle::from(stdin))));
+
+    Ok(Spawned {
+        child,
+        stdin,
+        stdout: stdout_read,
+        stderr: stderr_read,
+        fds: Vec::new(),
+        process_group: false,
+    })
+}
+
+/// Terminates the process behind `process`, like [`Child::kill`].
+pub(crate) fn terminate(process: &OwnedHandle) -> Result<(), io::Error> {
+    if unsafe { TerminateProcess(process.as_raw_handle() as _, 1) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Reaps `child`, returning its exit status along with the resources it
+/// used, or `None` if it is still running and `block` is false.
+pub(crate) fn wait_with_usage(
+    child: &mut Child,
+    block: bool,
+) -> Result<Option<(ExitStatus, Usage)>, io::Error> {
+    let status = if block {
+        child.wait()?
+    } else {
+        match child.try_wait()? {
+            Some(status) => status,
+            None => return Ok(None),
+        }
+    };
+
+    let handle = child.as_raw_handle() as _;
+    let zero = FILETIME {
+        dwLowDateTime: 0,
+        dwHighDateTime: 0,
+    };
+    let (mut creation, mut exit, mut kernel, mut user) = (zero, zero, zero, zero);
+    if unsafe { GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { mem::zeroed() };
+    let size = mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+    if unsafe { GetProcessMemoryInfo(handle, &mut counters, size) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let usage = Usage {
+        user_time: duration(user),
+        system_time: duration(kernel),
+        max_rss: counters.PeakWorkingSetSize as u64,
+    };
+    Ok(Some((status, usage)))
+}
+
+/// Converts a `FILETIME` holding a span of time, in 100 ns ticks.
+fn duration(time: FILETIME) -> Duration {
+    let ticks = (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime);
+    Duration::from_nanos(ticks * 100)
+}
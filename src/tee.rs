@@ -0,0 +1,320 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// How many bytes a single `splice` moves at most.
+#[cfg(target_os = "linux")]
+const SPLICE_LEN: usize = 1024 * 1024;
+
+/// When and how files written by [`tee`](crate::ProcessReaderBuilder::tee)
+/// are rotated.
+///
+/// Once a file is due, it is renamed to `<path>.1`, older files move up by
+/// one and files beyond [`max_files`](Self::max_files) are deleted.
+/// Rotation is only checked when output is written, and a file is never
+/// rotated while it is empty.
+///
+/// ```no_run
+/// use std::{process::Command, time::Duration};
+/// use incremental_command::{ProcessReader, Rotation};
+///
+/// let reader = ProcessReader::builder(Command::new("my-daemon"))
+///     .tee("daemon.log")
+///     .tee_rotation(Rotation::new().max_size(10 << 20).max_files(3))
+///     .start()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Rotation {
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    max_files: usize,
+    compress: bool,
+}
+
+impl Rotation {
+    /// Creates a rotation without any limits, which never rotates until a
+    /// size or age limit is set.
+    pub fn new() -> Self {
+        Self {
+            max_size: None,
+            max_age: None,
+            max_files: 5,
+            compress: false,
+        }
+    }
+
+    /// Rotates a file before a write would grow it past `bytes`. A single
+    /// write larger than that still ends up in one file. Defaults to no
+    /// limit.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotates a file once it has been written to for `age`. Defaults to no
+    /// limit.
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Sets how many rotated files are kept. With zero, a file is truncated
+    /// instead of being rotated. Defaults to 5.
+    pub fn max_files(mut self, count: usize) -> Self {
+        self.max_files = count;
+        self
+    }
+
+    /// Sets whether rotated files are compressed with gzip, as
+    /// `<path>.1.gz`. Compression happens while reading, which delays the
+    /// next item. Defaults to `false`.
+    #[cfg(feature = "gzip")]
+    pub fn compress(mut self, enable: bool) -> Self {
+        self.compress = enable;
+        self
+    }
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A file that the raw output of a stream is copied to as it is read.
+#[derive(Clone, Debug)]
+pub(crate) struct Tee {
+    file: Arc<Mutex<TeeFile>>,
+    /// Whether output is only written to the file, and not yielded.
+    only: bool,
+}
+
+impl Tee {
+    /// Creates the files for stdout and stderr, sharing a single one if both
+    /// streams are written to the same path.
+    pub(crate) fn open(
+        stdout: Option<&Path>,
+        stderr: Option<&Path>,
+        rotation: Option<&Rotation>,
+        only: bool,
+    ) -> Result<(Option<Tee>, Option<Tee>), io::Error> {
+        let create = |path| Tee::create(path, rotation.cloned(), only);
+        let stdout_tee = stdout.map(create).transpose()?;
+        let stderr_tee = match (stderr, &stdout_tee) {
+            (Some(path), Some(tee)) if stdout == Some(path) => Some(tee.clone()),
+            (Some(path), _) => Some(create(path)?),
+            (None, _) => None,
+        };
+
+        Ok((stdout_tee, stderr_tee))
+    }
+
+    fn create(path: &Path, rotation: Option<Rotation>, only: bool) -> Result<Self, io::Error> {
+        let file = TeeFile {
+            path: path.to_owned(),
+            file: Some(File::create(path)?),
+            written: 0,
+            opened: Instant::now(),
+            rotation,
+        };
+
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            only,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TeeFile> {
+        self.file.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn write(&self, bytes: &[u8]) -> Result<(), io::Error> {
+        self.lock().write(bytes)
+    }
+
+    /// Returns whether output is only written to the file, and not yielded.
+    pub(crate) fn is_only(&self) -> bool {
+        self.only
+    }
+
+    /// Moves whatever is available in the pipe `fd` to the file without
+    /// copying it through userspace, and returns how many bytes were moved,
+    /// or zero once the pipe has been closed.
+    ///
+    /// This fails with [`io::ErrorKind::WouldBlock`] if the pipe is empty,
+    /// and with `EINVAL` if `fd` is not a pipe, such as a pty.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn splice(&self, fd: RawFd) -> Result<usize, io::Error> {
+        self.lock().splice(fd)
+    }
+}
+
+#[derive(Debug)]
+struct TeeFile {
+    path: PathBuf,
+    /// The open file, or `None` if reopening it after a rotation failed.
+    file: Option<File>,
+    written: u64,
+    opened: Instant,
+    rotation: Option<Rotation>,
+}
+
+impl TeeFile {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        if self.is_due(bytes.len() as u64) {
+            self.rotate()?;
+        }
+
+        if self.file.is_none() {
+            self.reopen()?;
+        }
+
+        if let Some(file) = &mut self.file {
+            file.write_all(bytes)?;
+            self.written += bytes.len() as u64;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn splice(&mut self, fd: RawFd) -> Result<usize, io::Error> {
+        if self.is_due(1) {
+            self.rotate()?;
+        }
+
+        if self.file.is_none() {
+            self.reopen()?;
+        }
+
+        // Stop where the file is due to be rotated, so that it never grows
+        // past its maximum size.
+        let max_size = self
+            .rotation
+            .as_ref()
+            .and_then(|rotation| rotation.max_size);
+        let len = match max_size {
+            Some(max) => max.saturating_sub(self.written).clamp(1, SPLICE_LEN as u64) as usize,
+            None => SPLICE_LEN,
+        };
+
+        let Some(file) = &self.file else {
+            return Ok(0);
+        };
+        let n = unsafe {
+            libc::splice(
+                fd,
+                std::ptr::null_mut(),
+                file.as_raw_fd(),
+                std::ptr::null_mut(),
+                len,
+                libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.written += n as u64;
+        Ok(n as usize)
+    }
+
+    fn is_due(&self, len: u64) -> bool {
+        let Some(rotation) = &self.rotation else {
+            return false;
+        };
+        if self.written == 0 {
+            return false;
+        }
+
+        let too_large = rotation
+            .max_size
+            .is_some_and(|max| self.written + len > max);
+        let too_old = rotation
+            .max_age
+            .is_some_and(|age| self.opened.elapsed() >= age);
+        too_large || too_old
+    }
+
+    fn rotate(&mut self) -> Result<(), io::Error> {
+        let Some(rotation) = &self.rotation else {
+            return Ok(());
+        };
+        let max_files = rotation.max_files;
+        let compress = rotation.compress;
+
+        // The file is closed first, since open files cannot be renamed on
+        // every platform.
+        self.file = None;
+
+        if max_files > 0 {
+            let suffix = if compress { ".gz" } else { "" };
+            ignore_missing(fs::remove_file(self.rotated(max_files, suffix)))?;
+            for n in (1..max_files).rev() {
+                let from = self.rotated(n, suffix);
+                ignore_missing(fs::rename(from, self.rotated(n + 1, suffix)))?;
+            }
+
+            let first = self.rotated(1, "");
+            fs::rename(&self.path, &first)?;
+            if compress {
+                compress_file(&first, &self.rotated(1, suffix))?;
+            }
+        } else {
+            File::create(&self.path)?;
+        }
+
+        self.reopen()
+    }
+
+    /// Opens the file again for appending, in case a failed rotation left it
+    /// in place.
+    fn reopen(&mut self) -> Result<(), io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = file.metadata()?.len();
+        self.file = Some(file);
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    /// Returns the path of the `n`-th rotated file.
+    fn rotated(&self, n: usize, suffix: &str) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{n}{suffix}"));
+        PathBuf::from(path)
+    }
+}
+
+fn ignore_missing(result: Result<(), io::Error>) -> Result<(), io::Error> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Compresses the file at `from` into `to` and removes it.
+#[cfg(feature = "gzip")]
+fn compress_file(from: &Path, to: &Path) -> Result<(), io::Error> {
+    use flate2::{write::GzEncoder, Compression};
+
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut File::open(from)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(from)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn compress_file(_from: &Path, _to: &Path) -> Result<(), io::Error> {
+    Ok(())
+}
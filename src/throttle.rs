@@ -0,0 +1,166 @@
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+
+use crate::{event::Event, Out, RateLimit, RatePolicy};
+
+/// What happened to the last item of output, which an [`Out::Truncated`]
+/// after it shares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Last {
+    Passed,
+    Held,
+    Suppressed,
+}
+
+/// Keeps the output of one stream within a [`RateLimit`] with a token
+/// bucket that holds up to a second's worth of output.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    limit: RateLimit,
+    policy: RatePolicy,
+    tokens: f64,
+    refilled: Instant,
+    /// How many items were suppressed since the last summary.
+    suppressed: u64,
+    /// The last item that was suppressed, along with its [`Out::Truncated`],
+    /// to be yielded after the summary in [`RatePolicy::Coalesce`].
+    held: Vec<Event>,
+    last: Last,
+}
+
+impl Throttle {
+    pub(crate) fn new(limit: RateLimit, policy: RatePolicy) -> Self {
+        let mut throttle = Self {
+            limit,
+            policy,
+            tokens: 0.0,
+            refilled: Instant::now(),
+            suppressed: 0,
+            held: Vec::new(),
+            last: Last::Passed,
+        };
+        throttle.tokens = throttle.rate();
+        throttle
+    }
+
+    /// Returns how many items were suppressed since the last summary, or
+    /// zero if no summary is due, and the items to yield after it.
+    pub(crate) fn admit(&mut self, event: Event) -> (u64, Vec<Event>) {
+        if let Out::Truncated(_) = event.out {
+            match self.last {
+                Last::Passed => return (0, vec![event]),
+                Last::Held => self.held.push(event),
+                Last::Suppressed => {}
+            }
+            return (0, Vec::new());
+        }
+
+        self.refill(event.at);
+        let cost = self.cost(&event.out);
+        if self.tokens < cost {
+            match self.policy {
+                RatePolicy::Drop => {
+                    self.suppressed += 1;
+                    self.last = Last::Suppressed;
+                }
+                RatePolicy::Coalesce => {
+                    if !self.held.is_empty() {
+                        self.suppressed += 1;
+                    }
+                    self.held = vec![event];
+                    self.last = Last::Held;
+                }
+            }
+            return (0, Vec::new());
+        }
+
+        self.tokens -= cost;
+        self.last = Last::Passed;
+        if !self.held.is_empty() {
+            self.held.clear();
+            self.suppressed += 1;
+        }
+        (mem::take(&mut self.suppressed), vec![event])
+    }
+
+    /// Returns when a summary and the item held back are due, if any.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        if self.suppressed == 0 && self.held.is_empty() {
+            return None;
+        }
+
+        let missing = (self.held_cost() - self.tokens).max(0.0);
+        Some(self.refilled + Duration::from_secs_f64(missing / self.rate()))
+    }
+
+    /// Returns the summary and the item held back if they are due at `now`.
+    /// See [`admit`](Self::admit).
+    pub(crate) fn release(&mut self, now: Instant) -> (u64, Vec<Event>) {
+        self.refill(now);
+        if self.tokens < self.held_cost() {
+            return (0, Vec::new());
+        }
+
+        self.finish()
+    }
+
+    /// Returns the summary and the item held back regardless of the rate.
+    pub(crate) fn finish(&mut self) -> (u64, Vec<Event>) {
+        if !self.held.is_empty() {
+            self.tokens = (self.tokens - self.held_cost()).max(0.0);
+            self.last = Last::Passed;
+        }
+        (mem::take(&mut self.suppressed), mem::take(&mut self.held))
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled);
+        let rate = self.rate();
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(rate);
+        self.refilled = self.refilled.max(now);
+    }
+
+    /// Returns how many tokens are added per second, which is also how many
+    /// the bucket holds.
+    fn rate(&self) -> f64 {
+        match self.limit {
+            RateLimit::Lines(rate) => rate as f64,
+            RateLimit::Bytes(rate) => rate as f64,
+        }
+    }
+
+    /// Returns how many tokens yielding `out` takes, which is never more
+    /// than the bucket holds.
+    fn cost(&self, out: &Out) -> f64 {
+        let cost = match (self.limit, out) {
+            (RateLimit::Lines(_), _) => 1.0,
+            (
+                RateLimit::Bytes(_),
+                Out::Stdout(text)
+                | Out::Stderr(text)
+                | Out::StdoutProgress(text)
+                | Out::StderrProgress(text),
+            ) => text.len() as f64,
+            (RateLimit::Bytes(_), Out::StdoutBytes(bytes) | Out::StderrBytes(bytes)) => {
+                bytes.len() as f64
+            }
+            #[cfg(feature = "bytes")]
+            (RateLimit::Bytes(_), Out::StdoutShared(bytes) | Out::StderrShared(bytes)) => {
+                bytes.len() as f64
+            }
+            (RateLimit::Bytes(_), _) => 0.0,
+        };
+        cost.min(self.rate())
+    }
+
+    /// Returns how many tokens must be available before the summary and the
+    /// item held back are due.
+    fn held_cost(&self) -> f64 {
+        match self.held.first() {
+            Some(held) => self.cost(&held.out),
+            None => 1.0_f64.min(self.rate()),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::{fmt, future, io, process, thread, time::Duration};
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::OwnedHandle;
+
+#[cfg(unix)]
+use tokio::io::unix::AsyncFd;
+use tokio::process::ChildStdin;
+
+use crate::{event::Event, DropPolicy, Out, ProcessReader, ProcessReaderBuilder, Stats};
+
+/// How often the reader is checked for output where its poll cannot be
+/// registered with tokio.
+#[cfg(not(unix))]
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A [`ProcessReader`] driven by tokio instead of blocking on its own poll
+/// loop.
+///
+/// Created with [`ProcessReaderBuilder::start_tokio`]. Output is read with
+/// [`next`](Self::next), which yields the same [`Out`] items as the blocking
+/// reader, since the child is read the same way: the reader's poll is
+/// registered with tokio, which wakes up the task whenever the child has
+/// written something.
+///
+/// The child is not spawned with [`tokio::process::Command`], and its output
+/// is not read through tokio's async pipes. Instead, the file descriptor of
+/// the reader's own poll, its epoll instance on Linux, is wrapped in an
+/// `AsyncFd`, so that every builder option works as it does for a blocking
+/// reader. On Windows, the reader is checked every few milliseconds.
+pub struct TokioProcessReader {
+    /// The reader, which is only taken when it is dropped.
+    reader: Option<Driven>,
+    stdin: Option<ChildStdin>,
+}
+
+/// The reader with its poll registered with tokio.
+#[cfg(unix)]
+type Driven = AsyncFd<PollFd>;
+/// The reader, which is checked periodically.
+#[cfg(not(unix))]
+type Driven = ProcessReader;
+
+/// A reader whose poll is readable whenever the child has written
+/// something.
+#[cfg(unix)]
+struct PollFd(ProcessReader);
+
+#[cfg(unix)]
+impl AsRawFd for PollFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.poll_fd()
+    }
+}
+
+impl fmt::Debug for TokioProcessReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokioProcessReader")
+            .field("id", &self.id())
+            .field("stdin", &self.stdin)
+            .finish()
+    }
+}
+
+impl ProcessReaderBuilder {
+    /// Spawns the child and reads its output using tokio.
+    ///
+    /// This must be called from within a tokio runtime with IO and time
+    /// enabled. Every option applies as with [`start`](Self::start). With
+    /// [`DropPolicy::Wait`], dropping the reader waits for the child on a
+    /// thread of its own, since dropping cannot wait asynchronously.
+    pub fn start_tokio(self) -> Result<TokioProcessReader, io::Error> {
+        TokioProcessReader::new(self.start()?)
+    }
+}
+
+impl TokioProcessReader {
+    fn new(mut reader: ProcessReader) -> Result<Self, io::Error> {
+        let stdin = reader.take_stdin().map(tokio_stdin).transpose()?;
+        #[cfg(unix)]
+        let reader = AsyncFd::new(PollFd(reader))?;
+
+        Ok(Self {
+            reader: Some(reader),
+            stdin,
+        })
+    }
+
+    fn reader(&self) -> &ProcessReader {
+        let reader = self.reader.as_ref().expect("reader is only taken on drop");
+        #[cfg(unix)]
+        let reader = &reader.get_ref().0;
+        reader
+    }
+
+    fn reader_mut(&mut self) -> &mut ProcessReader {
+        let reader = self.reader.as_mut().expect("reader is only taken on drop");
+        #[cfg(unix)]
+        let reader = &mut reader.get_mut().0;
+        reader
+    }
+
+    /// Returns the OS-assigned process identifier of the child.
+    pub fn id(&self) -> u32 {
+        self.reader().id()
+    }
+
+    /// Returns the cgroup the child was placed in with
+    /// [`cgroup`](ProcessReaderBuilder::cgroup), if any.
+    #[cfg(target_os = "linux")]
+    pub fn cgroup(&self) -> Option<&crate::Cgroup> {
+        self.reader().cgroup()
+    }
+
+    /// Returns a handle for writing to the child's stdin.
+    ///
+    /// This is only available if the reader was built with
+    /// [`pipe_stdin`](ProcessReaderBuilder::pipe_stdin) or
+    /// [`pty`](ProcessReaderBuilder::pty) and the handle has not been taken.
+    pub fn stdin(&mut self) -> Option<&mut ChildStdin> {
+        self.stdin.as_mut()
+    }
+
+    /// Takes ownership of the child's stdin handle. Dropping the handle closes
+    /// the child's stdin.
+    pub fn take_stdin(&mut self) -> Option<ChildStdin> {
+        self.stdin.take()
+    }
+
+    /// Returns how many items have been dropped because the queue was full.
+    ///
+    /// See [`ProcessReader::dropped`].
+    pub fn dropped(&self) -> u64 {
+        self.reader().dropped()
+    }
+
+    /// Returns how much has been read from the child so far.
+    ///
+    /// See [`ProcessReader::stats`].
+    pub fn stats(&self) -> Stats {
+        self.reader().stats()
+    }
+
+    /// Stops reading from the child's stdout and stderr until
+    /// [`resume_reading`](Self::resume_reading) is called.
+    ///
+    /// See [`ProcessReader::pause_reading`].
+    pub fn pause_reading(&mut self) -> Result<(), io::Error> {
+        self.reader_mut().pause_reading()
+    }
+
+    /// Resumes reading after [`pause_reading`](Self::pause_reading).
+    pub fn resume_reading(&mut self) -> Result<(), io::Error> {
+        self.reader_mut().resume_reading()
+    }
+
+    /// Asks the child to terminate by sending it `SIGTERM`, killing it if it
+    /// has not exited within `grace`.
+    ///
+    /// See [`ProcessReader::shutdown`].
+    #[cfg(unix)]
+    pub fn shutdown(&mut self, grace: Duration) -> Result<(), io::Error> {
+        self.reader_mut().shutdown(grace)
+    }
+
+    /// Sends `signal` to the child, or to its process group if it leads one.
+    ///
+    /// See [`ProcessReader::signal`].
+    #[cfg(unix)]
+    pub fn signal(&mut self, signal: i32) -> Result<(), io::Error> {
+        self.reader_mut().signal(signal)
+    }
+
+    /// Stops the child, or its process group if it leads one, with `SIGSTOP`.
+    ///
+    /// See [`ProcessReader::suspend`].
+    #[cfg(unix)]
+    pub fn suspend(&mut self) -> Result<(), io::Error> {
+        self.reader_mut().suspend()
+    }
+
+    /// Continues the child after [`suspend`](Self::suspend) with `SIGCONT`.
+    #[cfg(unix)]
+    pub fn resume(&mut self) -> Result<(), io::Error> {
+        self.reader_mut().resume()
+    }
+
+    /// Waits for the next item of output.
+    ///
+    /// Returns `None` once [`Out::Done`] has been yielded.
+    pub async fn next(&mut self) -> Option<Result<Out, io::Error>> {
+        let event = self.next_event().await?;
+        Some(event.map(|event| event.out))
+    }
+
+    /// Like [`next`](Self::next), but yields every item that is ready at
+    /// once.
+    ///
+    /// See [`ProcessReader::next_batch`].
+    pub async fn next_batch(&mut self) -> Option<Result<Vec<Out>, io::Error>> {
+        let first = match self.next_event().await? {
+            Ok(event) => event.out,
+            Err(err) => return Some(Err(err)),
+        };
+
+        let reader = self.reader_mut();
+        let mut batch = vec![first];
+        while let Some(Ok(_)) = reader.peek_event() {
+            if let Some(Ok(event)) = reader.pop_event() {
+                batch.push(event.out);
+            }
+        }
+        Some(Ok(batch))
+    }
+
+    /// Like [`next`](Self::next), but yields the item together with the time
+    /// it was read and its sequence number.
+    pub async fn next_event(&mut self) -> Option<Result<Event, io::Error>> {
+        // Whatever the child wrote before the first call is read right away.
+        let mut wait = false;
+        loop {
+            let reader = self.reader_mut();
+            if let Some(next) = reader.pop_event() {
+                return Some(next);
+            }
+            if reader.is_done() {
+                return None;
+            }
+
+            if wait {
+                if let Err(err) = self.ready().await {
+                    return Some(Err(err));
+                }
+            }
+            wait = true;
+            if let Err(err) = self.reader_mut().read_now() {
+                return Some(Err(err));
+            }
+        }
+    }
+
+    /// Waits until the child has written something, or until the reader has
+    /// to check its timeouts.
+    async fn ready(&mut self) -> Result<(), io::Error> {
+        let timeout = self.reader().next_poll_timeout();
+        #[cfg(unix)]
+        {
+            let fd = self.reader.as_ref().expect("reader is only taken on drop");
+            tokio::select! {
+                guard = fd.readable() => guard?.clear_ready(),
+                _ = sleep(timeout) => {}
+            }
+        }
+        #[cfg(not(unix))]
+        sleep(Some(
+            timeout.map_or(POLL_INTERVAL, |timeout| timeout.min(POLL_INTERVAL)),
+        ))
+        .await;
+        Ok(())
+    }
+}
+
+impl Drop for TokioProcessReader {
+    fn drop(&mut self) {
+        let Some(reader) = self.reader.take() else {
+            return;
+        };
+        #[cfg(unix)]
+        let reader = reader.into_inner().0;
+
+        // Dropping the reader would block the runtime until the child has
+        // exited.
+        if reader.drop_policy() == DropPolicy::Wait && !reader.is_done() {
+            let _ = thread::Builder::new()
+                .name(format!("process-reader-{}", reader.id()))
+                .spawn(move || drop(reader));
+        }
+    }
+}
+
+/// Turns the child's stdin into one that can be written to asynchronously.
+fn tokio_stdin(stdin: crate::Stdin) -> Result<ChildStdin, io::Error> {
+    #[cfg(unix)]
+    let stdin = process::ChildStdin::from(OwnedFd::from(stdin.into_file()));
+    #[cfg(windows)]
+    let stdin = process::ChildStdin::from(OwnedHandle::from(stdin.into_file()));
+    ChildStdin::from_std(stdin)
+}
+
+async fn sleep(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => future::pending().await,
+    }
+}
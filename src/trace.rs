@@ -0,0 +1,67 @@
+use std::process::{Command, ExitStatus};
+
+use tracing::Span;
+
+use crate::{
+    hooks::{Hook, Hooks},
+    ExitKind, Out,
+};
+
+/// Wraps `hooks` so that the child `pid` also reports its spawn, every line
+/// and its exit as `tracing` events within a span of its own.
+pub(crate) fn instrument(hooks: Hooks, pid: u32, cmd: &Command) -> Hooks {
+    let span = tracing::info_span!("process", pid, command = ?cmd.get_program());
+    let Hooks {
+        spawn,
+        output,
+        exit,
+    } = hooks;
+
+    let spawn_span = span.clone();
+    let spawn = move |pid| {
+        tracing::info!(parent: &spawn_span, "spawned");
+        if let Some(hook) = spawn {
+            (hook.0)(pid);
+        }
+    };
+
+    let output_span = span.clone();
+    let mut output = output;
+    let on_output = move |out: &Out| {
+        match out {
+            Out::Stdout(text) => {
+                tracing::info!(parent: &output_span, stream = "stdout", line = %text)
+            }
+            Out::Stderr(text) => {
+                tracing::info!(parent: &output_span, stream = "stderr", line = %text)
+            }
+            _ => {}
+        }
+        if let Some(hook) = &mut output {
+            (hook.0)(out);
+        }
+    };
+
+    let on_exit = move |status: ExitStatus| {
+        exited(&span, status);
+        if let Some(hook) = exit {
+            (hook.0)(status);
+        }
+    };
+
+    Hooks {
+        spawn: Some(Hook(Box::new(spawn))),
+        output: Some(Hook(Box::new(on_output))),
+        exit: Some(Hook(Box::new(on_exit))),
+    }
+}
+
+fn exited(span: &Span, status: ExitStatus) {
+    match ExitKind::from(status) {
+        ExitKind::Code(code) => tracing::info!(parent: span, code, "exited"),
+        ExitKind::Signaled {
+            signal,
+            core_dumped,
+        } => tracing::info!(parent: span, signal, core_dumped, "killed by signal"),
+    }
+}
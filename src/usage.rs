@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+/// The resources a child used over its lifetime, reported in
+/// [`Out::ResourceUsage`](crate::Out::ResourceUsage) when
+/// [`resource_usage`](crate::ProcessReaderBuilder::resource_usage) is
+/// enabled.
+///
+/// CPU time and memory only cover the child itself, not any of its children
+/// that it did not wait for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in the kernel on behalf of the child.
+    pub system_time: Duration,
+    /// The peak resident set size in bytes, or the peak working set on
+    /// Windows.
+    pub max_rss: u64,
+    /// The time from spawning the child until the reader saw it exit.
+    pub wall_time: Duration,
+}
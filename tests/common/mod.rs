@@ -0,0 +1,25 @@
+use std::process::Command;
+
+use incremental_command::{ProcessReader, ProcessReaderBuilder};
+
+/// Returns a builder for running `script` with `sh`.
+pub fn sh(script: &str) -> ProcessReaderBuilder {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(script);
+    ProcessReader::builder(cmd)
+}
+
+/// Reads every item, describing each by its `Debug` output, or by its kind
+/// for errors, and leaving out the exit status.
+#[allow(dead_code)]
+pub fn items(builder: ProcessReaderBuilder) -> Vec<String> {
+    builder
+        .start()
+        .unwrap()
+        .filter_map(|out| match out {
+            Ok(incremental_command::Out::Done(_)) => None,
+            Ok(out) => Some(format!("{out:?}")),
+            Err(err) => Some(format!("Err({:?})", err.kind())),
+        })
+        .collect()
+}
@@ -0,0 +1,62 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::{fs, io, path::PathBuf, process};
+
+use common::sh;
+
+fn job_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("incremental-command-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn job_records_its_output_and_status() {
+    let dir = job_dir("job");
+    let job = sh("echo out; echo err >&2; exit 3").detach(&dir).unwrap();
+
+    assert_eq!(job.wait().unwrap().code(), Some(3));
+    assert_eq!(fs::read_to_string(job.stdout_path()).unwrap(), "out\n");
+    assert_eq!(fs::read_to_string(job.stderr_path()).unwrap(), "err\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn options_that_cannot_apply_are_rejected() {
+    let dir = job_dir("unsupported");
+    let err = sh("true").pty(true).detach(&dir).unwrap_err();
+
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    assert!(!dir.exists());
+}
+
+#[test]
+fn job_that_fails_to_run_leaves_nothing_behind() {
+    for attempt in 0..20 {
+        let dir = job_dir(&format!("missing-{attempt}"));
+        let cmd = process::Command::new("/nonexistent/program");
+        let err = incremental_command::ProcessReader::builder(cmd)
+            .detach(&dir)
+            .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!dir.exists(), "{} was left behind", dir.display());
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn seccomp_filter_applies_to_the_job() {
+    let dir = job_dir("seccomp");
+    let job = sh("grep '^Seccomp:' /proc/self/status")
+        .seccomp(incremental_command::SeccompFilter::deny_network())
+        .detach(&dir)
+        .unwrap();
+
+    assert!(job.wait().unwrap().success());
+    let status = fs::read_to_string(job.stdout_path()).unwrap();
+    assert_eq!(status.split_whitespace().nth(1), Some("2"));
+    fs::remove_dir_all(&dir).unwrap();
+}
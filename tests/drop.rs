@@ -0,0 +1,104 @@
+mod common;
+
+use std::{process::Command, sync::mpsc, thread, time::Duration};
+
+use incremental_command::{DropPolicy, InvalidUtf8, LineOverflow, ProcessReader};
+
+use common::sh;
+
+/// Drops the reader on a thread of its own, failing if that does not return
+/// in time.
+fn drop_within(reader: ProcessReader, timeout: Duration) {
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || {
+        drop(reader);
+        let _ = done.send(());
+    });
+    finished
+        .recv_timeout(timeout)
+        .expect("dropping the reader did not return");
+}
+
+#[test]
+fn wait_reads_past_overflowing_lines() {
+    let reader = sh("printf 'aaaaaaaaaaaaaaaaaaaaaaaa\\n'; head -c 1000000 /dev/zero")
+        .max_line_length(10, LineOverflow::Error)
+        .on_drop(DropPolicy::Wait)
+        .start()
+        .unwrap();
+
+    drop_within(reader, Duration::from_secs(10));
+}
+
+#[test]
+fn wait_lets_the_child_finish_after_overflowing_lines() {
+    let marker = std::env::temp_dir().join(format!("incremental-command-{}", std::process::id()));
+    let _ = std::fs::remove_file(&marker);
+    let script = format!(
+        "printf 'aaaaaaaaaaaaaaaaaaaaaaaa\\n'; sleep 0.1; head -c 1000000 /dev/zero || exit 1; touch '{}'",
+        marker.display()
+    );
+    let reader = sh(&script)
+        .max_line_length(10, LineOverflow::Error)
+        .on_drop(DropPolicy::Wait)
+        .start()
+        .unwrap();
+
+    drop_within(reader, Duration::from_secs(10));
+    assert!(marker.exists(), "the child did not finish");
+    let _ = std::fs::remove_file(&marker);
+}
+
+#[test]
+fn wait_lets_the_child_finish_after_invalid_lines() {
+    let marker =
+        std::env::temp_dir().join(format!("incremental-command-utf8-{}", std::process::id()));
+    let _ = std::fs::remove_file(&marker);
+    let script = format!(
+        "printf '\\377\\n'; sleep 0.1; head -c 1000000 /dev/zero || exit 1; touch '{}'",
+        marker.display()
+    );
+    let reader = sh(&script)
+        .strict_utf8(InvalidUtf8::Error)
+        .on_drop(DropPolicy::Wait)
+        .start()
+        .unwrap();
+
+    drop_within(reader, Duration::from_secs(10));
+    assert!(marker.exists(), "the child did not finish");
+    let _ = std::fs::remove_file(&marker);
+}
+
+#[test]
+fn wait_waits_for_the_child() {
+    let reader = sh("echo one; sleep 0.1; exit 3")
+        .on_drop(DropPolicy::Wait)
+        .start()
+        .unwrap();
+    let handle = reader.handle();
+
+    drop_within(reader, Duration::from_secs(10));
+    assert_eq!(handle.try_wait().and_then(|status| status.code()), Some(3));
+}
+
+#[test]
+fn kill_kills_the_child() {
+    let reader = sh("sleep 30").on_drop(DropPolicy::Kill).start().unwrap();
+    let handle = reader.handle();
+
+    drop_within(reader, Duration::from_secs(5));
+    let status = handle.try_wait().expect("the child was not reaped");
+    assert!(!status.success());
+}
+
+#[test]
+fn detach_leaves_the_child_running() {
+    let reader = sh("sleep 30").on_drop(DropPolicy::Detach).start().unwrap();
+    let handle = reader.handle();
+
+    drop_within(reader, Duration::from_secs(5));
+    assert!(handle.try_wait().is_none());
+    assert!(handle.wait().is_err());
+
+    let _ = Command::new("kill").arg(handle.id().to_string()).status();
+}
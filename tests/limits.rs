@@ -0,0 +1,229 @@
+mod common;
+
+use std::time::Duration;
+
+use incremental_command::{
+    Out, OutputLimit, QueuePolicy, RateLimit, RatePolicy, Sampling, Truncation,
+};
+
+use common::{items, sh};
+
+const FIVE_LINES: &str = "for i in 1 2 3 4 5; do echo $i; done";
+
+#[test]
+fn output_limit_keeps_the_start() {
+    assert_eq!(
+        items(sh(FIVE_LINES).output_limit(OutputLimit::Lines(2), Truncation::Tail)),
+        [
+            "Stdout(\"1\")",
+            "Stdout(\"2\")",
+            "StdoutElided { dropped_lines: 3 }"
+        ],
+    );
+}
+
+#[test]
+fn output_limit_keeps_both_ends() {
+    assert_eq!(
+        items(sh(FIVE_LINES).output_limit(OutputLimit::Lines(2), Truncation::Middle)),
+        [
+            "Stdout(\"1\")",
+            "StdoutElided { dropped_lines: 3 }",
+            "Stdout(\"5\")"
+        ],
+    );
+}
+
+#[test]
+fn output_limit_counts_bytes() {
+    assert_eq!(
+        items(
+            sh("echo abc; echo defg; echo h").output_limit(OutputLimit::Bytes(5), Truncation::Tail)
+        ),
+        ["Stdout(\"abc\")", "StdoutElided { dropped_lines: 2 }"],
+    );
+}
+
+#[test]
+fn output_limit_applies_per_stream() {
+    assert_eq!(
+        items(
+            sh("echo a; echo b >&2; echo c; echo d >&2")
+                .output_limit(OutputLimit::Lines(1), Truncation::Tail)
+        ),
+        [
+            "Stdout(\"a\")",
+            "Stderr(\"b\")",
+            "StdoutElided { dropped_lines: 1 }",
+            "StderrElided { dropped_lines: 1 }"
+        ],
+    );
+}
+
+#[test]
+fn rate_limit_drops_and_reports() {
+    assert_eq!(
+        items(sh(FIVE_LINES).rate_limit(RateLimit::Lines(1), RatePolicy::Drop)),
+        ["Stdout(\"1\")", "StdoutSuppressed { count: 4 }"],
+    );
+}
+
+#[test]
+fn rate_limit_coalesces_into_the_latest() {
+    assert_eq!(
+        items(sh(FIVE_LINES).rate_limit(RateLimit::Lines(1), RatePolicy::Coalesce)),
+        [
+            "Stdout(\"1\")",
+            "StdoutSuppressed { count: 3 }",
+            "Stdout(\"5\")"
+        ],
+    );
+}
+
+#[test]
+fn sampling_yields_every_nth() {
+    assert_eq!(
+        items(sh(FIVE_LINES).sample(Sampling::Nth(2))),
+        ["Stdout(\"1\")", "Stdout(\"3\")", "Stdout(\"5\")"]
+    );
+}
+
+#[test]
+fn sampling_by_interval() {
+    assert_eq!(
+        items(sh(FIVE_LINES).sample(Sampling::Interval(Duration::from_secs(60)))),
+        ["Stdout(\"1\")"],
+    );
+}
+
+#[test]
+fn queue_drops_newest() {
+    let mut reader = sh(FIVE_LINES)
+        .queue_capacity(2, QueuePolicy::DropNewest)
+        .start()
+        .unwrap();
+    // The child has written everything by the time it is first read.
+    std::thread::sleep(Duration::from_millis(200));
+    let mut lines = Vec::new();
+    for out in reader.by_ref() {
+        if let Out::Stdout(line) = out.unwrap() {
+            lines.push(line);
+        }
+    }
+
+    assert_eq!(lines, ["1", "2"]);
+    assert_eq!(reader.dropped(), 3);
+}
+
+#[test]
+fn queue_drops_lines_of_captured_fds() {
+    let mut reader = sh("for i in 1 2 3 4 5; do echo $i >&3; done")
+        .capture_fd(3)
+        .queue_capacity(2, QueuePolicy::DropNewest)
+        .start()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    let mut lines = Vec::new();
+    for out in reader.by_ref() {
+        if let Out::Fd { line, .. } = out.unwrap() {
+            lines.push(line);
+        }
+    }
+
+    assert_eq!(lines, ["1", "2"]);
+    assert_eq!(reader.dropped(), 3);
+}
+
+#[test]
+fn output_limit_leaves_captured_fds_alone() {
+    assert_eq!(
+        items(
+            sh("echo a; echo 0123456789abc >&3; echo b")
+                .capture_fd(3)
+                .max_line_length(10, incremental_command::LineOverflow::Truncate)
+                .output_limit(OutputLimit::Lines(1), Truncation::Tail)
+        ),
+        [
+            "Stdout(\"a\")",
+            "Fd { fd: 3, line: \"0123456789\" }",
+            "Truncated(3)",
+            "StdoutElided { dropped_lines: 1 }"
+        ],
+    );
+}
+
+#[test]
+fn queue_drops_lines_of_sources() {
+    let (read, mut write) = std::os::unix::net::UnixStream::pair().unwrap();
+    std::io::Write::write_all(&mut write, b"1\n2\n3\n4\n5\n").unwrap();
+    drop(write);
+    let mut reader = sh("sleep 0.2")
+        .source("log", std::os::unix::io::OwnedFd::from(read))
+        .queue_capacity(2, QueuePolicy::DropNewest)
+        .start()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    let mut lines = Vec::new();
+    for out in reader.by_ref() {
+        if let Out::Source { line, .. } = out.unwrap() {
+            lines.push(line);
+        }
+    }
+
+    assert_eq!(lines, ["1", "2"]);
+    assert_eq!(reader.dropped(), 3);
+}
+
+#[test]
+fn queue_drops_lines_of_followed_files() {
+    let path =
+        std::env::temp_dir().join(format!("incremental-command-follow-{}", std::process::id()));
+    std::fs::write(&path, "").unwrap();
+    let script = format!(
+        "sleep 0.1; printf '1\\n2\\n3\\n4\\n5\\n' >> '{}'; sleep 0.3",
+        path.display()
+    );
+    let mut reader = sh(&script)
+        .follow_file("log", &path)
+        .queue_capacity(2, QueuePolicy::DropNewest)
+        .start()
+        .unwrap();
+    let mut lines = Vec::new();
+    for out in reader.by_ref() {
+        if let Out::Followed { line, .. } = out.unwrap() {
+            lines.push(line);
+        }
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(lines, ["1", "2"]);
+    assert_eq!(reader.dropped(), 3);
+}
+
+#[test]
+fn backpressure_holds_back_followed_files() {
+    let path = std::env::temp_dir().join(format!(
+        "incremental-command-backpressure-{}",
+        std::process::id()
+    ));
+    std::fs::write(&path, "").unwrap();
+    let script = format!(
+        "sleep 0.1; for i in $(seq 1 100); do echo $i; done >> '{}'; sleep 0.3",
+        path.display()
+    );
+    let mut reader = sh(&script)
+        .follow_file("log", &path)
+        .queue_capacity(2, QueuePolicy::Backpressure)
+        .start()
+        .unwrap();
+    let mut lines = Vec::new();
+    for out in reader.by_ref() {
+        if let Out::Followed { line, .. } = out.unwrap() {
+            lines.push(line);
+        }
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(lines.len(), 100);
+    assert_eq!(reader.dropped(), 0);
+}
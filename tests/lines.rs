@@ -0,0 +1,107 @@
+mod common;
+
+use incremental_command::{InvalidUtf8, LineOverflow};
+
+use common::{items, sh};
+
+#[test]
+fn lines_without_delimiter_are_flushed_at_exit() {
+    assert_eq!(
+        items(sh("printf 'one\\ntwo'")),
+        ["Stdout(\"one\")", "Stdout(\"two\")"],
+    );
+}
+
+#[test]
+fn stdout_and_stderr_are_told_apart() {
+    assert_eq!(
+        items(sh("echo out; sleep 0.05; echo err >&2")),
+        ["Stdout(\"out\")", "Stderr(\"err\")"],
+    );
+}
+
+#[test]
+fn custom_delimiter_splits_lines() {
+    assert_eq!(
+        items(sh("printf 'a\\0b\\nc\\0'").delimiter(b"\0".to_vec())),
+        ["Stdout(\"a\")", "Stdout(\"b\\nc\")"],
+    );
+}
+
+#[test]
+fn multi_byte_delimiter_split_across_writes() {
+    assert_eq!(
+        items(sh("printf 'a-'; sleep 0.05; printf -- '-b--'").delimiter("--")),
+        ["Stdout(\"a\")", "Stdout(\"b\")"],
+    );
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn shared_lines_split_on_a_delimiter_across_writes() {
+    assert_eq!(
+        items(
+            sh("printf 'a-'; sleep 0.05; printf -- '-b--c'")
+                .mode(incremental_command::Mode::SharedLines)
+                .delimiter("--")
+        ),
+        [
+            "StdoutShared(b\"a\")",
+            "StdoutShared(b\"b\")",
+            "StdoutShared(b\"c\")"
+        ],
+    );
+}
+
+#[test]
+fn overflow_truncates() {
+    assert_eq!(
+        items(sh("echo 0123456789abc; echo ok").max_line_length(10, LineOverflow::Truncate)),
+        ["Stdout(\"0123456789\")", "Truncated(3)", "Stdout(\"ok\")"],
+    );
+}
+
+#[test]
+fn overflow_splits() {
+    assert_eq!(
+        items(sh("echo 0123456789abc").max_line_length(10, LineOverflow::Split)),
+        ["Stdout(\"0123456789\")", "Stdout(\"abc\")"],
+    );
+}
+
+#[test]
+fn overflow_errors_and_goes_on() {
+    assert_eq!(
+        items(sh("echo 0123456789abc; echo ok").max_line_length(10, LineOverflow::Error)),
+        ["Err(InvalidData)", "Stdout(\"ok\")"],
+    );
+}
+
+#[test]
+fn invalid_utf8_is_replaced() {
+    assert_eq!(items(sh("printf 'a\\377b\\n'")), ["Stdout(\"a\u{fffd}b\")"],);
+}
+
+#[test]
+fn strict_utf8_replaces() {
+    assert_eq!(
+        items(sh("printf 'a\\377b\\n'").strict_utf8(InvalidUtf8::Replace)),
+        ["Stdout(\"a\u{fffd}b\")"],
+    );
+}
+
+#[test]
+fn strict_utf8_errors_and_goes_on() {
+    assert_eq!(
+        items(sh("printf 'a\\377b\\nok\\n'").strict_utf8(InvalidUtf8::Error)),
+        ["Err(InvalidData)", "Stdout(\"ok\")"],
+    );
+}
+
+#[test]
+fn utf8_split_across_writes_is_kept_whole() {
+    assert_eq!(
+        items(sh("printf '\\303'; sleep 0.05; printf '\\251\\n'").strict_utf8(InvalidUtf8::Error)),
+        ["Stdout(\"é\")"],
+    );
+}
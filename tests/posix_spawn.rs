@@ -0,0 +1,124 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    io::{self, Write},
+    os::unix::process::ExitStatusExt,
+    process::Command,
+};
+
+use common::{items, sh};
+use incremental_command::{DropPolicy, Out, ProcessReader};
+
+#[test]
+fn captures_stdout_and_stderr() {
+    let out = items(sh("echo out; sleep 0.1; echo err >&2").posix_spawn(true));
+
+    assert_eq!(out, ["Stdout(\"out\")", "Stderr(\"err\")"]);
+}
+
+#[test]
+fn merges_stderr_into_stdout() {
+    let out = items(
+        sh("echo out; echo err >&2")
+            .merge_stderr(true)
+            .posix_spawn(true),
+    );
+
+    assert_eq!(out, ["Stdout(\"out\")", "Stdout(\"err\")"]);
+}
+
+#[test]
+fn captures_additional_fds() {
+    let out = items(
+        sh("echo three >&3; echo four >&4")
+            .capture_fd(3)
+            .capture_fd(4)
+            .posix_spawn(true),
+    );
+
+    assert_eq!(out.len(), 2);
+    assert!(out.iter().any(|out| out.contains("\"three\"")));
+    assert!(out.iter().any(|out| out.contains("\"four\"")));
+}
+
+#[test]
+fn pipes_stdin() {
+    let mut reader = sh("cat")
+        .pipe_stdin(true)
+        .posix_spawn(true)
+        .start()
+        .unwrap();
+    let mut stdin = reader.take_stdin().unwrap();
+    stdin.write_all(b"input\n").unwrap();
+    drop(stdin);
+
+    let out = reader.next().unwrap().unwrap();
+    assert!(matches!(out, Out::Stdout(line) if line == "input"));
+}
+
+#[test]
+fn passes_environment_and_working_directory() {
+    let out = items(
+        sh("echo \"$GREETING\"; pwd")
+            .env("GREETING", "hello")
+            .current_dir("/")
+            .posix_spawn(true),
+    );
+
+    assert_eq!(out, ["Stdout(\"hello\")", "Stdout(\"/\")"]);
+}
+
+#[test]
+fn reports_the_exit_status() {
+    let reader = sh("exit 7").posix_spawn(true).start().unwrap();
+
+    assert_eq!(reader.run_to_completion().unwrap().status.code(), Some(7));
+}
+
+#[test]
+fn kills_the_child() {
+    let mut reader = sh("sleep 10").posix_spawn(true).start().unwrap();
+    let handle = reader.handle();
+    handle.kill().unwrap();
+
+    assert!(reader.all(|out| out.is_ok()));
+    assert_eq!(handle.wait().unwrap().signal(), Some(libc::SIGKILL));
+}
+
+#[test]
+fn spawns_in_a_process_group() {
+    let reader = sh("sleep 10")
+        .process_group(true)
+        .on_drop(DropPolicy::Kill)
+        .posix_spawn(true)
+        .start()
+        .unwrap();
+    let pid = reader.id() as libc::pid_t;
+
+    assert_eq!(unsafe { libc::getpgid(pid) }, pid);
+}
+
+#[test]
+fn missing_program_fails_to_start() {
+    let err = ProcessReader::builder(Command::new("/nonexistent/program"))
+        .posix_spawn(true)
+        .start()
+        .err()
+        .unwrap();
+
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn options_that_run_in_the_child_are_rejected() {
+    let err = sh("true")
+        .posix_spawn(true)
+        .umask(0o077)
+        .start()
+        .err()
+        .unwrap();
+
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+}
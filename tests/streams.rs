@@ -0,0 +1,94 @@
+#![cfg(unix)]
+
+use std::{
+    collections::BTreeMap,
+    process::{Command, Stdio},
+};
+
+use incremental_command::{
+    Multiplexer, Out, ProcessReader, ProcessReaderBuilder, ProcessSet, StreamConfig, StreamOut,
+};
+
+/// Returns a builder for running `script` with `bash`, since `sh` may only
+/// redirect to single-digit file descriptors.
+fn bash(script: &str) -> ProcessReaderBuilder {
+    let mut cmd = Command::new("bash");
+    cmd.arg("-c").arg(script);
+    ProcessReader::builder(cmd)
+}
+
+#[test]
+fn captures_any_number_of_fds() {
+    let fds = 3..75;
+    let mut builder = bash("for fd in {3..74}; do echo $fd >&$fd; done");
+    for fd in fds.clone() {
+        builder = builder.capture_fd(fd);
+    }
+
+    let mut lines = BTreeMap::new();
+    for out in builder.start().unwrap() {
+        if let Out::Fd { fd, line } = out.unwrap() {
+            lines.insert(fd, line);
+        }
+    }
+
+    assert_eq!(lines.len(), fds.len());
+    assert!(lines.iter().all(|(fd, line)| *line == fd.to_string()));
+}
+
+#[test]
+fn set_reads_processes_with_many_streams() {
+    let mut set = ProcessSet::new().unwrap();
+    for id in 0..3 {
+        let mut builder = bash(&format!("for fd in {{3..20}}; do echo {id} >&$fd; done"));
+        for fd in 3..=20 {
+            builder = builder.capture_fd(fd);
+        }
+        set.spawn(builder).unwrap();
+    }
+
+    let mut lines = 0;
+    for out in set {
+        if let (id, Out::Fd { line, .. }) = out.unwrap() {
+            assert_eq!(line, id.index().to_string());
+            lines += 1;
+        }
+    }
+    assert_eq!(lines, 3 * 18);
+}
+
+#[test]
+fn multiplexer_frames_each_stream() {
+    let mut printf = Command::new("printf")
+        .arg("a\\0b")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut echo = Command::new("echo")
+        .arg("line")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut mux = Multiplexer::new().unwrap();
+    let nul = StreamConfig::new().delimiter(b"\0");
+    let printf_id = mux
+        .add("printf", printf.stdout.take().unwrap(), nul)
+        .unwrap();
+    let echo_id = mux
+        .add("echo", echo.stdout.take().unwrap(), StreamConfig::new())
+        .unwrap();
+
+    let mut outs: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for out in &mut mux {
+        let (id, out) = out.unwrap();
+        outs.entry(id).or_default().push(out);
+    }
+    printf.wait().unwrap();
+    echo.wait().unwrap();
+
+    let line = |line: &str| StreamOut::Line(line.to_owned());
+    assert_eq!(outs[&printf_id], [line("a"), line("b"), StreamOut::Closed]);
+    assert_eq!(outs[&echo_id], [line("line"), StreamOut::Closed]);
+    assert_eq!(mux.name(echo_id), "echo");
+}